@@ -0,0 +1,40 @@
+#!/bin/sh
+
+# Run the riscv-tests ISA suite and report pass/fail per test.
+#
+# Usage: run_isa_tests.sh <riscv-tests isa directory> [pattern]
+#
+# The tests must be built first (`make -C isa XLEN=32`). Only the
+# physical-memory (-p-) variants are run by default.
+
+
+REPO_ROOT=$(git rev-parse --show-toplevel)
+CASIM=${REPO_ROOT}/target/debug/casim
+
+ISA_DIR=$1
+PATTERN=${2:-rv32u?-p-*}
+
+if [ -z "$ISA_DIR" ]; then
+    echo "usage: $0 <riscv-tests isa directory> [pattern]"
+    exit 1
+fi
+
+PASSED=0
+FAILED=0
+for test in ${ISA_DIR}/${PATTERN}; do
+    case "$test" in
+        *.dump) continue ;;
+    esac
+    ${CASIM} --full-system "$test" > /dev/null 2>&1
+    code=$?
+    if [ $code -eq 0 ]; then
+        echo "PASS $(basename $test)"
+        PASSED=$((PASSED + 1))
+    else
+        echo "FAIL $(basename $test) (test #$code)"
+        FAILED=$((FAILED + 1))
+    fi
+done
+
+echo "${PASSED} passed, ${FAILED} failed"
+[ $FAILED -eq 0 ]
@@ -0,0 +1,302 @@
+//! Checkpoints of the simulated machine.
+//!
+//! A checkpoint is taken at a quiescent point: the harts stop fetching,
+//! everything in flight commits or is squashed, and then the architectural
+//! state is written out. That is the clock, every hart's registers, CSRs and
+//! branch predictor, the threads, the program's memory and stack, the CLINT,
+//! the UART and HTIF registers and the files the program opened through
+//! semihosting. Caches, TLBs and DRAM rows are not saved and start cold after
+//! a restore, and statistics count from the restore.
+//!
+//! The file is a sequence of little-endian integers and length-prefixed
+//! byte strings after a magic number and a version.
+
+use machine::Machine;
+use std::io::{self, Read, Write};
+
+const MAGIC: &[u8; 8] = b"CASIMCKP";
+const VERSION: u32 = 2;
+
+/// State that can be written to a checkpoint and read back.
+pub trait Snapshot {
+    fn save(&self, out: &mut Encoder);
+    fn restore(&mut self, input: &mut Decoder) -> io::Result<()>;
+}
+
+#[derive(Debug, Default)]
+pub struct Encoder {
+    buf: Vec<u8>,
+}
+
+impl Encoder {
+    pub fn u8(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn usize(&mut self, value: usize) {
+        self.u64(value as u64);
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.usize(bytes.len());
+        self.buf.extend_from_slice(bytes);
+    }
+
+    pub fn str(&mut self, s: &str) {
+        self.bytes(s.as_bytes());
+    }
+}
+
+pub struct Decoder<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.data.len() < len {
+            return Err(invalid("checkpoint is truncated"));
+        }
+        let (taken, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    pub fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> io::Result<bool> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn u32(&mut self) -> io::Result<u32> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    pub fn u64(&mut self) -> io::Result<u64> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    pub fn usize(&mut self) -> io::Result<usize> {
+        Ok(self.u64()? as usize)
+    }
+
+    pub fn bytes(&mut self) -> io::Result<Vec<u8>> {
+        let len = self.usize()?;
+        Ok(self.take(len)?.to_vec())
+    }
+
+    pub fn str(&mut self) -> io::Result<String> {
+        String::from_utf8(self.bytes()?).map_err(|_| invalid("invalid string in checkpoint"))
+    }
+}
+
+pub fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Writes the state of `machine`, which must be drained.
+pub fn save<W: Write>(machine: &Machine, mut out: W) -> io::Result<()> {
+    assert!(
+        machine.is_drained(),
+        "checkpoint of a machine with instructions in flight"
+    );
+    let mut enc = Encoder::default();
+    enc.buf.extend_from_slice(MAGIC);
+    enc.u32(VERSION);
+    enc.usize(machine.clock);
+    enc.usize(machine.harts.len());
+
+    for hart in machine.harts.iter() {
+        enc.bool(hart.tid.is_some());
+        enc.u32(hart.tid.unwrap_or(0));
+        enc.u32(hart.clear_child_tid);
+        enc.u32(hart.reg.pc.read());
+        for reg in hart.reg.gpr.iter() {
+            enc.u32(reg.read());
+        }
+        hart.reg.csr.save(&mut enc);
+        hart.branch_predictor.save(&mut enc);
+    }
+    machine.threads.save(&mut enc);
+
+    let memory = &machine.mem_sys.memory;
+    for &(start, end) in [
+        memory.v_address_range,
+        memory.read_only_range,
+        memory.stack_range,
+    ]
+    .iter()
+    {
+        enc.u32(start);
+        enc.u32(end);
+    }
+    enc.bytes(&memory.data);
+    enc.bytes(&memory.stack);
+    enc.u32(memory.stack_pointer_init);
+    machine.mem_sys.clint.save(&mut enc);
+    memory.mmio.save(&mut enc);
+    enc.bool(machine.mem_sys.htif.is_some());
+    if let Some(ref htif) = machine.mem_sys.htif {
+        htif.save(&mut enc);
+    }
+    machine.mem_sys.semihosting.save(&mut enc);
+
+    out.write_all(&enc.buf)?;
+    out.flush()
+}
+
+/// Replaces the state of `machine`, freshly built for the same program and
+/// number of harts, with a checkpoint.
+pub fn restore<R: Read>(machine: &mut Machine, mut input: R) -> io::Result<()> {
+    let mut data = Vec::new();
+    input.read_to_end(&mut data)?;
+    let mut dec = Decoder { data: &data };
+    if dec.take(MAGIC.len())? != MAGIC || dec.u32()? != VERSION {
+        return Err(invalid("not a checkpoint of this version"));
+    }
+    let clock = dec.usize()?;
+    if dec.usize()? != machine.harts.len() {
+        return Err(invalid("checkpoint has a different number of harts"));
+    }
+    machine.clock = clock;
+    machine.start_clock = clock;
+
+    for hart in machine.harts.iter_mut() {
+        let is_running = dec.bool()?;
+        let tid = dec.u32()?;
+        hart.tid = if is_running { Some(tid) } else { None };
+        hart.clear_child_tid = dec.u32()?;
+        hart.reg.pc.write(dec.u32()?);
+        for reg in hart.reg.gpr.iter_mut() {
+            reg.write(dec.u32()?);
+        }
+        hart.reg.csr.restore(&mut dec)?;
+        hart.branch_predictor.restore(&mut dec)?;
+        hart.reg.sync_phys_regs();
+        hart.clock = clock;
+    }
+    machine.threads.restore(&mut dec)?;
+
+    let memory = &mut machine.mem_sys.memory;
+    for range in [
+        &mut memory.v_address_range,
+        &mut memory.read_only_range,
+        &mut memory.stack_range,
+    ]
+    .iter_mut()
+    {
+        **range = (dec.u32()?, dec.u32()?);
+    }
+    memory.data = dec.bytes()?;
+    memory.stack = dec.bytes()?;
+    memory.stack_pointer_init = dec.u32()?;
+    machine.mem_sys.clint.restore(&mut dec)?;
+    memory.mmio.restore(&mut dec)?;
+    if dec.bool()? != machine.mem_sys.htif.is_some() {
+        return Err(invalid("checkpoint is of a program with a different HTIF"));
+    }
+    if let Some(ref mut htif) = machine.mem_sys.htif {
+        htif.restore(&mut dec)?;
+    }
+    machine.mem_sys.semihosting.restore(&mut dec)?;
+    machine.mem_sys.clint.tick(clock);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use machine::tests::with_program;
+
+    #[test]
+    fn save_and_restore() {
+        // li a0, 0; addi a0, a0, 1; sw a0, 0x100(zero); j -8
+        let program = [0x0000_0513u32, 0x0015_0513, 0x10a0_2023, 0xff9f_f06f];
+        let new_machine = || with_program(&program, Default::default());
+
+        let mut machine = new_machine();
+        for _ in 0..100 {
+            machine.run_clock();
+        }
+        machine.drain(true);
+        while !machine.is_drained() {
+            machine.run_clock();
+        }
+        let mut file = Vec::new();
+        save(&machine, &mut file).unwrap();
+        let count = machine.harts[0].reg.gpr[10].read();
+        assert!(count > 0);
+
+        let mut restored = new_machine();
+        restore(&mut restored, &file[..]).unwrap();
+        assert_eq!(restored.clock, machine.clock);
+        assert_eq!(restored.harts[0].reg.gpr[10].read(), count);
+        assert_eq!(restored.mem_sys.memory.data, machine.mem_sys.memory.data);
+        assert_eq!(
+            restored.harts[0].reg.pc.read(),
+            machine.harts[0].reg.pc.read()
+        );
+        for _ in 0..100 {
+            restored.run_clock();
+        }
+        assert!(restored.harts[0].reg.gpr[10].read() > count);
+
+        assert!(restore(&mut new_machine(), &file[..20]).is_err());
+    }
+
+    #[test]
+    fn devices_are_saved() {
+        use memory::uart::{Uart, UART_BASE, UART_SIZE};
+
+        let new_machine = || {
+            let mut machine = with_program(&[0x0000_006fu32], Default::default());
+            let mut uart = Uart::default();
+            uart.receive(b"ok");
+            let mmio = &mut machine.mem_sys.memory.mmio;
+            mmio.map(UART_BASE, UART_SIZE, Box::new(uart));
+            machine
+        };
+        let mut machine = new_machine();
+        let mmio = &mut machine.mem_sys.memory.mmio;
+        // LCR의 DLAB을 켜고 divisor를 쓴 뒤 scratch register를 씀
+        mmio.write(UART_BASE + 3, 1, 0x80).unwrap();
+        mmio.write(UART_BASE, 1, 0x34).unwrap();
+        mmio.write(UART_BASE + 1, 1, 0x12).unwrap();
+        mmio.write(UART_BASE + 3, 1, 0x03).unwrap();
+        mmio.write(UART_BASE + 7, 1, 0x5a).unwrap();
+        assert_eq!(mmio.read(UART_BASE, 1), Some(u32::from(b'o')));
+        let mut file = Vec::new();
+        save(&machine, &mut file).unwrap();
+
+        let mut restored = new_machine();
+        restore(&mut restored, &file[..]).unwrap();
+        let mmio = &mut restored.mem_sys.memory.mmio;
+        assert_eq!(mmio.read(UART_BASE + 3, 1), Some(0x03));
+        assert_eq!(mmio.read(UART_BASE + 7, 1), Some(0x5a));
+        assert_eq!(mmio.read(UART_BASE, 1), Some(u32::from(b'k')));
+        mmio.write(UART_BASE + 3, 1, 0x83).unwrap();
+        assert_eq!(mmio.read(UART_BASE, 1), Some(0x34));
+        assert_eq!(mmio.read(UART_BASE + 1, 1), Some(0x12));
+
+        // UART가 없는 machine에는 복원할 수 없음
+        let mut without_uart = with_program(&[0x0000_006f], Default::default());
+        assert!(restore(&mut without_uart, &file[..]).is_err());
+    }
+}
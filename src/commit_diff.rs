@@ -0,0 +1,359 @@
+//! Finds where two commit logs diverge.
+//!
+//! Both logs are read a line at a time and the instructions each hart
+//! retired are compared in order, so harts that interleave differently in
+//! the two logs still line up. Lines that aren't commits, like Spike's trap
+//! messages, are skipped. The first instruction whose PC, instruction word,
+//! register write or memory access differs is reported together with the
+//! instructions the hart retired before it.
+
+use instruction::Instruction;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::io::{self, BufRead};
+
+/// An instruction read from a commit log.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Commit {
+    pub core: usize,
+    pub pc: u64,
+    pub inst: u32,
+    /// Registers written, e.g. `("x10", 5)`.
+    pub writes: Vec<(String, u64)>,
+    /// Memory accessed, with the value for writes.
+    pub mem: Vec<(u64, Option<u64>)>,
+    pub line: String,
+}
+
+impl Commit {
+    /// Parses a line of Spike's `--log-commits` format, or returns `None`
+    /// if it isn't a commit.
+    pub fn parse(line: &str) -> Option<Commit> {
+        let mut tokens = line.split_whitespace().peekable();
+        if tokens.next()? != "core" {
+            return None;
+        }
+        let core = tokens.next()?.strip_suffix(':')?.parse().ok()?;
+        tokens.next()?.parse::<u32>().ok()?;
+        let pc = hex(tokens.next()?)?;
+        let inst = tokens.next()?;
+        let inst = hex(inst.strip_prefix('(')?.strip_suffix(')')?)? as u32;
+
+        let (mut writes, mut mem) = (Vec::new(), Vec::new());
+        while let Some(token) = tokens.next() {
+            if token == "mem" {
+                let addr = hex(tokens.next()?)?;
+                let value = match tokens.peek() {
+                    Some(value) if value.starts_with("0x") => hex(tokens.next()?),
+                    _ => None,
+                };
+                mem.push((addr, value));
+            } else {
+                writes.push((token.to_string(), hex(tokens.next()?)?));
+            }
+        }
+        Some(Commit {
+            core,
+            pc,
+            inst,
+            writes,
+            mem,
+            line: line.to_string(),
+        })
+    }
+
+    /// How `other` differs from this commit, if it does.
+    pub fn compare(&self, other: &Commit) -> Option<Mismatch> {
+        if self.pc != other.pc {
+            Some(Mismatch::Pc)
+        } else if self.inst != other.inst {
+            Some(Mismatch::Instruction)
+        } else if self.writes != other.writes {
+            Some(Mismatch::Register)
+        } else if self.mem != other.mem {
+            Some(Mismatch::Memory)
+        } else {
+            None
+        }
+    }
+
+    fn disassembly(&self) -> String {
+        match Instruction::decode(self.inst) {
+            Some(inst) => inst.to_string(),
+            None => "unknown".to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Commit {
+    /// The instruction in assembler syntax followed by what it wrote.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "0x{:08x}: {:<28}", self.pc, self.disassembly())?;
+        for (reg, value) in self.writes.iter() {
+            write!(f, " {}=0x{:x}", reg, value)?;
+        }
+        for (addr, value) in self.mem.iter() {
+            match value {
+                Some(value) => write!(f, " [0x{:x}]=0x{:x}", addr, value)?,
+                None => write!(f, " [0x{:x}]", addr)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn hex(token: &str) -> Option<u64> {
+    u64::from_str_radix(token.strip_prefix("0x")?, 16).ok()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mismatch {
+    Pc,
+    Instruction,
+    Register,
+    Memory,
+    /// One log ended before the other.
+    Length,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Mismatch::Pc => "PC",
+            Mismatch::Instruction => "instruction word",
+            Mismatch::Register => "register write",
+            Mismatch::Memory => "memory access",
+            Mismatch::Length => "end of log",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// First point two commit logs disagree.
+#[derive(Debug, Clone)]
+pub struct Divergence {
+    pub mismatch: Mismatch,
+    pub core: usize,
+    /// Instructions the hart retired before it.
+    pub index: usize,
+    pub left: Option<Commit>,
+    pub right: Option<Commit>,
+    /// Instructions the hart retired just before, oldest first.
+    pub before: Vec<Commit>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "First divergence at instruction {} of core {}: {}",
+            self.index, self.core, self.mismatch
+        )?;
+        for (i, commit) in self.before.iter().enumerate() {
+            writeln!(f, "  {:>8}  {}", self.index - self.before.len() + i, commit)?;
+        }
+        for (side, commit) in [("<", &self.left), (">", &self.right)].iter() {
+            match commit {
+                Some(commit) => {
+                    writeln!(f, "{} {:>8}  {}", side, self.index, commit)?;
+                    writeln!(f, "{}           {}", side, commit.line)?;
+                }
+                None => writeln!(f, "{} {:>8}  (log ended)", side, self.index)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reads commits from a log, skipping other lines.
+struct CommitReader<R: BufRead> {
+    input: R,
+    line: String,
+}
+
+impl<R: BufRead> CommitReader<R> {
+    fn next(&mut self) -> io::Result<Option<Commit>> {
+        loop {
+            self.line.clear();
+            if self.input.read_line(&mut self.line)? == 0 {
+                return Ok(None);
+            }
+            if let Some(commit) = Commit::parse(self.line.trim_end()) {
+                return Ok(Some(commit));
+            }
+        }
+    }
+}
+
+/// Compares two commit logs, keeping `context` instructions of each hart
+/// to show before a divergence. Returns the number of instructions that
+/// matched and the divergence, if any.
+pub fn diff<A: BufRead, B: BufRead>(
+    left: A,
+    right: B,
+    context: usize,
+) -> io::Result<(usize, Option<Divergence>)> {
+    let mut left = CommitReader {
+        input: left,
+        line: String::new(),
+    };
+    let mut right = CommitReader {
+        input: right,
+        line: String::new(),
+    };
+    // 각 hart마다 아직 짝이 없는 commit과 최근에 일치한 commit.
+    // 어느 hart를 먼저 보고할지 정해지도록 hart 번호 순으로 둠
+    let mut pending: [BTreeMap<usize, VecDeque<Commit>>; 2] = Default::default();
+    let mut matched: BTreeMap<usize, (usize, VecDeque<Commit>)> = BTreeMap::new();
+    let mut total = 0;
+    let mut done = [false; 2];
+
+    loop {
+        for side in 0..2 {
+            if done[side] {
+                continue;
+            }
+            let commit = if side == 0 { left.next()? } else { right.next()? };
+            match commit {
+                Some(commit) => pending[side]
+                    .entry(commit.core)
+                    .or_default()
+                    .push_back(commit),
+                None => done[side] = true,
+            }
+        }
+
+        let cores: Vec<usize> = pending[0].keys().cloned().collect();
+        for core in cores {
+            loop {
+                let (l, r) = match (pending[0].get(&core), pending[1].get(&core)) {
+                    (Some(l), Some(r)) if !l.is_empty() && !r.is_empty() => (&l[0], &r[0]),
+                    _ => break,
+                };
+                let (index, history) = matched.entry(core).or_default();
+                if let Some(mismatch) = l.compare(r) {
+                    return Ok((
+                        total,
+                        Some(Divergence {
+                            mismatch,
+                            core,
+                            index: *index,
+                            left: Some(l.clone()),
+                            right: Some(r.clone()),
+                            before: history.iter().cloned().collect(),
+                        }),
+                    ));
+                }
+                let commit = pending[0].get_mut(&core).unwrap().pop_front().unwrap();
+                pending[1].get_mut(&core).unwrap().pop_front();
+                history.push_back(commit);
+                if history.len() > context {
+                    history.pop_front();
+                }
+                *index += 1;
+                total += 1;
+            }
+        }
+
+        // 한쪽 log가 끝났으면 반대쪽에 남은 commit은 더 이상 짝이 생기지 않음
+        for side in 0..2 {
+            if !done[1 - side] {
+                continue;
+            }
+            let leftover = pending[side].iter().find(|(_, commits)| !commits.is_empty());
+            let (&core, commits) = match leftover {
+                Some(leftover) => leftover,
+                None => continue,
+            };
+            let (index, history) = matched.remove(&core).unwrap_or_default();
+            let commit = Some(commits[0].clone());
+            let (left, right) = if side == 0 {
+                (commit, None)
+            } else {
+                (None, commit)
+            };
+            return Ok((
+                total,
+                Some(Divergence {
+                    mismatch: Mismatch::Length,
+                    core,
+                    index,
+                    left,
+                    right,
+                    before: history.into_iter().collect(),
+                }),
+            ));
+        }
+
+        if done[0] && done[1] {
+            return Ok((total, None));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOG: &str = "core   0: 3 0x00010000 (0x00500513) x10 0x00000005
+core   1: 3 0x00010000 (0x00500513) x10 0x00000005
+core   0: exception trap_illegal_instruction, epc 0x00010004
+core   0: 3 0x00010004 (0x00a581a3) mem 0x00011003 0x05
+core   0: 3 0x00010008 (0x0005a583) x11 0x00000005 mem 0x00011000
+";
+
+    #[test]
+    fn first_divergence() {
+        let commit = Commit::parse("core   0: 3 0x00010004 (0x00a581a3) mem 0x00011003 0x05");
+        assert_eq!(commit.unwrap().mem, vec![(0x11003, Some(5))]);
+
+        // hart 사이의 순서가 달라도 같은 log로 봄
+        let mut lines: Vec<_> = LOG.lines().collect();
+        let core1 = lines.remove(1);
+        lines.push(core1);
+        let reordered = lines.join("\n");
+        let (total, divergence) = diff(LOG.as_bytes(), reordered.as_bytes(), 4).unwrap();
+        assert_eq!((total, divergence.is_none()), (4, true));
+
+        let changed = LOG.replace("x11 0x00000005", "x11 0x00000006");
+        let divergence = diff(LOG.as_bytes(), changed.as_bytes(), 1).unwrap().1.unwrap();
+        assert_eq!(divergence.mismatch, Mismatch::Register);
+        assert_eq!((divergence.core, divergence.index), (0, 2));
+        assert_eq!(divergence.before.len(), 1);
+        assert!(divergence.to_string().contains("sb a0, 3(a1)"));
+
+        let short: String = LOG.lines().take(2).map(|line| line.to_string() + "\n").collect();
+        let divergence = diff(LOG.as_bytes(), short.as_bytes(), 1).unwrap().1.unwrap();
+        assert_eq!(divergence.mismatch, Mismatch::Length);
+        assert!(divergence.right.is_none());
+    }
+
+    /// Fails the test if the log is read past the lines it was given.
+    struct Endless;
+
+    impl io::Read for Endless {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Err(io::Error::other("read past the divergence"))
+        }
+    }
+
+    #[test]
+    fn ended_log_is_reported_early() {
+        use std::io::Read;
+
+        let short: String = LOG.lines().take(2).map(|line| line.to_string() + "\n").collect();
+        let long = io::BufReader::new(LOG.as_bytes().chain(Endless));
+        let divergence = diff(short.as_bytes(), long, 1).unwrap().1.unwrap();
+        assert_eq!(divergence.mismatch, Mismatch::Length);
+        assert_eq!((divergence.core, divergence.index), (0, 1));
+        assert!(divergence.left.is_none());
+
+        // 짝이 없는 hart가 여럿이면 번호가 작은 hart를 보고함
+        let left = "core   2: 3 0x00010000 (0x00500513) x10 0x00000005\n";
+        let right = "core   1: 3 0x00010000 (0x00500513) x10 0x00000005
+core   0: 3 0x00010000 (0x00500513) x10 0x00000005
+";
+        let divergence = diff(left.as_bytes(), right.as_bytes(), 1).unwrap().1.unwrap();
+        assert_eq!(divergence.core, 0);
+    }
+}
@@ -0,0 +1,157 @@
+//! Commit logs in the format of Spike's `--log-commits`, to diff a run
+//! against Spike and other simulators that write it.
+//!
+//! Every retired instruction gets a line with the hart, the privilege it ran
+//! at, its PC and raw encoding, followed by the registers and CSRs it wrote
+//! and the memory it accessed:
+//!
+//! ```text
+//! core   0: 3 0x00010074 (0x00a52023) mem 0x00011000 0x00000005
+//! core   0: 3 0x00010078 (0x00052583) x11 0x00000005 mem 0x00011000
+//! core   0: 3 0x0001007c (0x30529073) c773_mtvec 0x00010100
+//! ```
+//!
+//! Loads show the address they read, stores the address and the value they
+//! wrote, sized by the access, and AMOs both. Writes to `x0` are left out.
+
+use csr;
+use instruction::{Function, Opcode};
+use pipeline::operand::Operand;
+use pipeline::reorder_buffer::ReorderBufferEntry;
+use std::fmt::Write as FmtWrite;
+use std::io::{self, Write};
+
+pub struct CommitLogWriter<W: Write> {
+    out: W,
+}
+
+impl<W: Write> CommitLogWriter<W> {
+    pub fn new(out: W) -> Self {
+        CommitLogWriter { out }
+    }
+
+    /// Writes the line of `entry`, which hart `hart` retired.
+    pub fn record(&mut self, hart: usize, entry: &ReorderBufferEntry) -> io::Result<()> {
+        writeln!(self.out, "{}", Self::line(hart, entry))
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    fn line(hart: usize, entry: &ReorderBufferEntry) -> String {
+        let mut line = format!(
+            "core {:3}: {} 0x{:08x} (0x{:08x})",
+            hart, entry.privilege as u32, entry.pc, entry.raw
+        );
+        if let (rd, Some(value)) = (entry.rd, entry.reg_value) {
+            if rd != 0 {
+                write!(line, " x{:<2} 0x{:08x}", rd, value).unwrap();
+            }
+        }
+        for &(addr, value) in entry.csr_written.iter() {
+            write!(line, " c{}_{} 0x{:08x}", addr, csr::name(addr), value).unwrap();
+        }
+
+        let addr = match entry.addr {
+            Operand::Value(addr) => addr,
+            _ => return line,
+        };
+        let function = entry.inst.function;
+        let reads = match entry.inst.opcode {
+            Opcode::Load => true,
+            Opcode::Amo => !matches!(function, Function::Scw),
+            _ => false,
+        };
+        if reads {
+            write!(line, " mem 0x{:08x}", addr).unwrap();
+        }
+        if let (Opcode::Store, Some(value)) | (Opcode::Amo, Some(value)) =
+            (entry.inst.opcode, entry.mem_written)
+        {
+            let digits = match function {
+                Function::Sb => 2,
+                Function::Sh => 4,
+                _ => 8,
+            };
+            write!(line, " mem 0x{:08x} 0x{:02$x}", addr, value, digits).unwrap();
+        }
+        line
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use csr::{CsrFile, Privilege};
+    use instruction::Instruction;
+    use pipeline::reorder_buffer::ReorderBuffer;
+    use register::RegisterFile;
+
+    fn retired(pc: u32, value: u32) -> ReorderBufferEntry {
+        let mut rob = ReorderBuffer::default();
+        let reg = RegisterFile::new(0, 0);
+        let idx = rob.issue(pc, Instruction::new(value), &reg, false);
+        rob.get_mut(idx).unwrap().clone()
+    }
+
+    #[test]
+    fn registers_and_memory() {
+        // addi a0, x0, 5
+        let mut addi = retired(0x10000, 0x0050_0513);
+        addi.reg_value = Some(5);
+        // sb a0, 3(a1)
+        let mut sb = retired(0x10004, 0x00a5_81a3);
+        sb.addr = Operand::Value(0x11003);
+        sb.mem_written = Some(5);
+        sb.privilege = Privilege::User;
+        // amoadd.w a2, a0, (a1)
+        let mut amo = retired(0x10008, 0x00a5_a62f);
+        amo.addr = Operand::Value(0x11000);
+        amo.reg_value = Some(7);
+        amo.mem_written = Some(12);
+        // addi x0, x0, 0
+        let mut nop = retired(0x1000c, 0x0000_0013);
+        nop.reg_value = Some(0);
+
+        let mut log = CommitLogWriter::new(Vec::new());
+        for entry in [addi, sb, amo, nop].iter() {
+            log.record(1, entry).unwrap();
+        }
+        let text = String::from_utf8(log.finish().unwrap()).unwrap();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines[0], "core   1: 3 0x00010000 (0x00500513) x10 0x00000005");
+        assert_eq!(lines[1], "core   1: 0 0x00010004 (0x00a581a3) mem 0x00011003 0x05");
+        assert_eq!(
+            lines[2],
+            "core   1: 3 0x00010008 (0x00a5a62f) x12 0x00000007 mem 0x00011000 mem 0x00011000 0x0000000c"
+        );
+        assert_eq!(lines[3], "core   1: 3 0x0001000c (0x00000013)");
+    }
+
+    #[test]
+    fn csr_writes_and_raw_words() {
+        let mut csr = CsrFile::default();
+        csr.write(csr::MSTATUS, 0x1822).unwrap();
+        // csrrw a0, sstatus, a1
+        let mut csrrw = retired(0x10000, 0x1005_9573);
+        csrrw.reg_value = Some(0x22);
+        csrrw.csr_written = csrrw.written_csrs(&csr);
+        // csrr a0, mhartid
+        let mut csrr = retired(0x10004, 0xf140_2573);
+        csrr.reg_value = Some(0);
+        csrr.csr_written = csrr.written_csrs(&csr);
+        // fsrm a0는 nop으로 실행되지만 원래 word를 남김
+        let mut fsrm = retired(0x10008, 0x0035_1073);
+        fsrm.raw = 0x0035_1073;
+
+        let line = |entry| CommitLogWriter::<Vec<u8>>::line(0, entry);
+        assert_eq!(
+            line(&csrrw),
+            "core   0: 3 0x00010000 (0x10059573) x10 0x00000022 c256_sstatus 0x00000022 c768_mstatus 0x00001822"
+        );
+        assert_eq!(line(&csrr), "core   0: 3 0x00010004 (0xf1402573) x10 0x00000000");
+        assert_eq!(line(&fsrm), "core   0: 3 0x00010008 (0x00351073)");
+    }
+}
@@ -41,6 +41,7 @@ pub const SYSCALL_ARG4_REG: usize = 13;
 pub const SYSCALL_ARG5_REG: usize = 14;
 pub const SYSCALL_ARG6_REG: usize = 15;
 
+/// Fixed controller and bus latency of a memory read, on top of DRAM bank timing.
 pub const MEM_CYCLE: usize = 10;
 pub const ADD_CYCLE: usize = 1;
 pub const MUL_CYCLE: usize = 4;
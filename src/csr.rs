@@ -0,0 +1,515 @@
+//! Control and status registers.
+
+use checkpoint::{Decoder, Encoder, Snapshot};
+use memory::pmp::{Pmp, PMP_ENTRIES};
+use std::io;
+
+pub const SSTATUS: u16 = 0x100;
+pub const SIE: u16 = 0x104;
+pub const STVEC: u16 = 0x105;
+pub const SSCRATCH: u16 = 0x140;
+pub const SEPC: u16 = 0x141;
+pub const SCAUSE: u16 = 0x142;
+pub const STVAL: u16 = 0x143;
+pub const SIP: u16 = 0x144;
+pub const SATP: u16 = 0x180;
+pub const MSTATUS: u16 = 0x300;
+pub const MEDELEG: u16 = 0x302;
+pub const MIDELEG: u16 = 0x303;
+pub const MIE: u16 = 0x304;
+pub const MTVEC: u16 = 0x305;
+pub const MSCRATCH: u16 = 0x340;
+pub const MEPC: u16 = 0x341;
+pub const MCAUSE: u16 = 0x342;
+pub const MTVAL: u16 = 0x343;
+pub const MIP: u16 = 0x344;
+pub const PMPCFG0: u16 = 0x3a0;
+pub const PMPADDR0: u16 = 0x3b0;
+pub const MHARTID: u16 = 0xf14;
+
+/// `satp` MODE bit selecting Sv32 translation instead of bare addressing.
+pub const SATP_MODE_SV32: u32 = 0x8000_0000;
+/// `satp` field holding the physical page number of the root page table.
+pub const SATP_PPN_MASK: u32 = 0x003f_ffff;
+
+pub const MSTATUS_SIE: u32 = 1 << 1;
+pub const MSTATUS_MIE: u32 = 1 << 3;
+pub const MSTATUS_SPIE: u32 = 1 << 5;
+pub const MSTATUS_MPIE: u32 = 1 << 7;
+pub const MSTATUS_SPP: u32 = 1 << 8;
+pub const MSTATUS_MPP_SHIFT: u32 = 11;
+pub const MSTATUS_MPP: u32 = 0b11 << MSTATUS_MPP_SHIFT;
+/// Lets S-mode load and store to user pages.
+pub const MSTATUS_SUM: u32 = 1 << 18;
+/// Lets loads read pages that are only executable.
+pub const MSTATUS_MXR: u32 = 1 << 19;
+
+const MSTATUS_WRITABLE: u32 = MSTATUS_SIE
+    | MSTATUS_MIE
+    | MSTATUS_SPIE
+    | MSTATUS_MPIE
+    | MSTATUS_SPP
+    | MSTATUS_MPP
+    | MSTATUS_SUM
+    | MSTATUS_MXR;
+/// Bits of `mstatus` visible through `sstatus`.
+const SSTATUS_MASK: u32 = MSTATUS_SIE | MSTATUS_SPIE | MSTATUS_SPP | MSTATUS_SUM | MSTATUS_MXR;
+/// Environment calls from M-mode can't be delegated.
+const MEDELEG_WRITABLE: u32 = 0xffff & !(1 << 11);
+
+/// Set in `mcause`/`scause` when the trap is an interrupt.
+pub const CAUSE_INTERRUPT: u32 = 1 << 31;
+pub const IRQ_S_SOFT: u32 = 1;
+pub const IRQ_M_SOFT: u32 = 3;
+pub const IRQ_S_TIMER: u32 = 5;
+pub const IRQ_M_TIMER: u32 = 7;
+pub const IRQ_S_EXT: u32 = 9;
+pub const IRQ_M_EXT: u32 = 11;
+/// Interrupts in the order they are taken when several are pending.
+const IRQ_PRIORITY: [u32; 6] = [
+    IRQ_M_EXT,
+    IRQ_M_SOFT,
+    IRQ_M_TIMER,
+    IRQ_S_EXT,
+    IRQ_S_SOFT,
+    IRQ_S_TIMER,
+];
+const S_INTERRUPTS: u32 = (1 << IRQ_S_SOFT) | (1 << IRQ_S_TIMER) | (1 << IRQ_S_EXT);
+const ALL_INTERRUPTS: u32 =
+    S_INTERRUPTS | (1 << IRQ_M_SOFT) | (1 << IRQ_M_TIMER) | (1 << IRQ_M_EXT);
+
+fn is_pmpcfg(addr: u16) -> bool {
+    (PMPCFG0..PMPCFG0 + PMP_ENTRIES as u16 / 4).contains(&addr)
+}
+
+fn is_pmpaddr(addr: u16) -> bool {
+    (PMPADDR0..PMPADDR0 + PMP_ENTRIES as u16).contains(&addr)
+}
+
+/// Assembler name of an implemented CSR.
+pub fn name(addr: u16) -> String {
+    let name = match addr {
+        SSTATUS => "sstatus",
+        SIE => "sie",
+        STVEC => "stvec",
+        SSCRATCH => "sscratch",
+        SEPC => "sepc",
+        SCAUSE => "scause",
+        STVAL => "stval",
+        SIP => "sip",
+        SATP => "satp",
+        MSTATUS => "mstatus",
+        MEDELEG => "medeleg",
+        MIDELEG => "mideleg",
+        MIE => "mie",
+        MTVEC => "mtvec",
+        MSCRATCH => "mscratch",
+        MEPC => "mepc",
+        MCAUSE => "mcause",
+        MTVAL => "mtval",
+        MIP => "mip",
+        MHARTID => "mhartid",
+        _ if is_pmpcfg(addr) => return format!("pmpcfg{}", addr - PMPCFG0),
+        _ if is_pmpaddr(addr) => return format!("pmpaddr{}", addr - PMPADDR0),
+        _ => return format!("csr{:#x}", addr),
+    };
+    name.to_string()
+}
+
+/// Privilege level a hart runs at.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Privilege {
+    User = 0,
+    Supervisor = 1,
+    #[default]
+    Machine = 3,
+}
+
+impl Privilege {
+    fn from_bits(bits: u32) -> Privilege {
+        match bits {
+            0 => Privilege::User,
+            1 => Privilege::Supervisor,
+            _ => Privilege::Machine,
+        }
+    }
+}
+
+/// The CSRs implemented by the simulator, along with the privilege level the
+/// hart runs at.
+#[derive(Debug, Default, Clone)]
+pub struct CsrFile {
+    pub privilege: Privilege,
+    /// `sstatus` is a restricted view of this register.
+    pub mstatus: u32,
+    pub medeleg: u32,
+    pub mideleg: u32,
+    /// `sie` is a view of the delegated bits of this register.
+    pub mie: u32,
+    /// Pending interrupts. The M-mode timer and software bits follow the
+    /// CLINT; `sip` is a view of the delegated bits.
+    pub mip: u32,
+    pub mtvec: u32,
+    pub mscratch: u32,
+    pub mepc: u32,
+    pub mcause: u32,
+    pub mtval: u32,
+    pub stvec: u32,
+    pub sscratch: u32,
+    pub sepc: u32,
+    pub scause: u32,
+    pub stval: u32,
+    pub satp: u32,
+    /// Read-only id of the hart.
+    pub mhartid: u32,
+    /// `pmpcfg0-3` and `pmpaddr0-15`.
+    pub pmp: Pmp,
+}
+
+impl CsrFile {
+    /// True if the current privilege level may access the CSR.
+    fn is_accessible(&self, addr: u16) -> bool {
+        // 주소의 [9:8] 비트가 접근에 필요한 최소 권한
+        self.privilege as u16 >= (addr >> 8) & 0b11
+    }
+
+    /// Reads a CSR, or `None` if it isn't implemented or the current
+    /// privilege level can't access it.
+    pub fn read(&self, addr: u16) -> Option<u32> {
+        if !self.is_accessible(addr) {
+            return None;
+        }
+        self.value(addr)
+    }
+
+    /// Reads a CSR whatever the privilege level, or `None` if it isn't
+    /// implemented.
+    pub fn value(&self, addr: u16) -> Option<u32> {
+        let value = match addr {
+            SSTATUS => self.mstatus & SSTATUS_MASK,
+            SIE => self.mie & self.mideleg,
+            SIP => self.mip & self.mideleg,
+            STVEC => self.stvec,
+            SSCRATCH => self.sscratch,
+            SEPC => self.sepc,
+            SCAUSE => self.scause,
+            STVAL => self.stval,
+            SATP => self.satp,
+            MSTATUS => self.mstatus,
+            MEDELEG => self.medeleg,
+            MIDELEG => self.mideleg,
+            MIE => self.mie,
+            MIP => self.mip,
+            MTVEC => self.mtvec,
+            MSCRATCH => self.mscratch,
+            MEPC => self.mepc,
+            MCAUSE => self.mcause,
+            MTVAL => self.mtval,
+            MHARTID => self.mhartid,
+            _ if is_pmpcfg(addr) => self.pmp.read_cfg((addr - PMPCFG0) as usize),
+            _ if is_pmpaddr(addr) => self.pmp.read_addr((addr - PMPADDR0) as usize),
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    /// Writes a CSR, or returns `None` if it isn't implemented, is
+    /// read-only, or the current privilege level can't access it.
+    pub fn write(&mut self, addr: u16, value: u32) -> Option<()> {
+        // 주소의 [11:10] 비트가 0b11이면 read-only
+        if !self.is_accessible(addr) || addr >> 10 == 0b11 {
+            return None;
+        }
+        match addr {
+            SSTATUS => {
+                self.mstatus = (self.mstatus & !SSTATUS_MASK) | (value & SSTATUS_MASK);
+                return Some(());
+            }
+            MSTATUS => {
+                self.mstatus = value & MSTATUS_WRITABLE;
+                return Some(());
+            }
+            MEDELEG => {
+                self.medeleg = value & MEDELEG_WRITABLE;
+                return Some(());
+            }
+            MIDELEG => {
+                self.mideleg = value & S_INTERRUPTS;
+                return Some(());
+            }
+            MIE => {
+                self.mie = value & ALL_INTERRUPTS;
+                return Some(());
+            }
+            SIE => {
+                self.mie = (self.mie & !self.mideleg) | (value & self.mideleg);
+                return Some(());
+            }
+            // M-mode 인터럽트의 pending 비트는 CLINT가 관리
+            MIP => {
+                self.mip = (self.mip & !S_INTERRUPTS) | (value & S_INTERRUPTS);
+                return Some(());
+            }
+            // S-mode에서는 software interrupt만 지울 수 있음
+            SIP => {
+                let writable = self.mideleg & (1 << IRQ_S_SOFT);
+                self.mip = (self.mip & !writable) | (value & writable);
+                return Some(());
+            }
+            _ if is_pmpcfg(addr) => {
+                self.pmp.write_cfg((addr - PMPCFG0) as usize, value);
+                return Some(());
+            }
+            _ if is_pmpaddr(addr) => {
+                self.pmp.write_addr((addr - PMPADDR0) as usize, value);
+                return Some(());
+            }
+            _ => {}
+        }
+        let reg = match addr {
+            STVEC => &mut self.stvec,
+            SSCRATCH => &mut self.sscratch,
+            SEPC => &mut self.sepc,
+            SCAUSE => &mut self.scause,
+            STVAL => &mut self.stval,
+            SATP => &mut self.satp,
+            MTVEC => &mut self.mtvec,
+            MSCRATCH => &mut self.mscratch,
+            MEPC => &mut self.mepc,
+            MCAUSE => &mut self.mcause,
+            MTVAL => &mut self.mtval,
+            _ => return None,
+        };
+        *reg = if addr == SEPC || addr == MEPC {
+            value & !0x3
+        } else {
+            value
+        };
+        Some(())
+    }
+
+    /// Returns true when loads, stores and fetches go through Sv32. M-mode
+    /// always uses physical addresses.
+    pub fn is_translating(&self) -> bool {
+        self.privilege != Privilege::Machine && self.satp & SATP_MODE_SV32 != 0
+    }
+
+    /// Returns the interrupt to take now, if any is pending and enabled at
+    /// the current privilege level.
+    pub fn pending_interrupt(&self) -> Option<u32> {
+        let pending = self.mip & self.mie;
+        let m_enabled =
+            self.privilege < Privilege::Machine || self.mstatus & MSTATUS_MIE != 0;
+        let s_enabled = self.privilege < Privilege::Supervisor
+            || (self.privilege == Privilege::Supervisor && self.mstatus & MSTATUS_SIE != 0);
+        let mut enabled = 0;
+        if m_enabled {
+            enabled |= pending & !self.mideleg;
+        }
+        if s_enabled {
+            enabled |= pending & self.mideleg;
+        }
+        IRQ_PRIORITY
+            .iter()
+            .cloned()
+            .find(|&irq| (enabled >> irq) & 1 == 1)
+    }
+
+    /// Privilege level that handles a trap with `cause`, which may be an
+    /// interrupt.
+    pub fn trap_target(&self, cause: u32) -> Privilege {
+        let (code, delegated) = if cause & CAUSE_INTERRUPT != 0 {
+            (cause & !CAUSE_INTERRUPT, self.mideleg)
+        } else {
+            (cause, self.medeleg)
+        };
+        if self.privilege != Privilege::Machine && (delegated >> code) & 1 == 1 {
+            Privilege::Supervisor
+        } else {
+            Privilege::Machine
+        }
+    }
+
+    /// Address of the trap handler of `target`.
+    pub fn trap_vector(&self, target: Privilege) -> u32 {
+        match target {
+            Privilege::Machine => self.mtvec & !0x3,
+            _ => self.stvec & !0x3,
+        }
+    }
+
+    /// Takes a trap and returns the address of its handler. Interrupts go to
+    /// their own entry when the handler's vector is in vectored mode.
+    pub fn enter_trap(&mut self, cause: u32, epc: u32, tval: u32) -> u32 {
+        let target = self.trap_target(cause);
+        let tvec = match target {
+            Privilege::Machine => self.mtvec,
+            _ => self.stvec,
+        };
+        let prev = self.privilege as u32;
+        if target == Privilege::Machine {
+            self.mcause = cause;
+            self.mepc = epc;
+            self.mtval = tval;
+            let mpie = if self.mstatus & MSTATUS_MIE != 0 {
+                MSTATUS_MPIE
+            } else {
+                0
+            };
+            self.mstatus &= !(MSTATUS_MIE | MSTATUS_MPIE | MSTATUS_MPP);
+            self.mstatus |= mpie | (prev << MSTATUS_MPP_SHIFT);
+        } else {
+            self.scause = cause;
+            self.sepc = epc;
+            self.stval = tval;
+            let spie = if self.mstatus & MSTATUS_SIE != 0 {
+                MSTATUS_SPIE
+            } else {
+                0
+            };
+            let spp = if prev == Privilege::User as u32 {
+                0
+            } else {
+                MSTATUS_SPP
+            };
+            self.mstatus &= !(MSTATUS_SIE | MSTATUS_SPIE | MSTATUS_SPP);
+            self.mstatus |= spie | spp;
+        }
+        self.privilege = target;
+        if cause & CAUSE_INTERRUPT != 0 && tvec & 0x3 == 1 {
+            self.trap_vector(target) + 4 * (cause & !CAUSE_INTERRUPT)
+        } else {
+            self.trap_vector(target)
+        }
+    }
+
+    /// Returns from an M-mode trap handler and gives the address to resume
+    /// at, or `None` below M-mode.
+    pub fn mret(&mut self) -> Option<u32> {
+        if self.privilege != Privilege::Machine {
+            return None;
+        }
+        self.privilege = Privilege::from_bits((self.mstatus & MSTATUS_MPP) >> MSTATUS_MPP_SHIFT);
+        let mie = if self.mstatus & MSTATUS_MPIE != 0 {
+            MSTATUS_MIE
+        } else {
+            0
+        };
+        self.mstatus &= !(MSTATUS_MIE | MSTATUS_MPP);
+        self.mstatus |= mie | MSTATUS_MPIE;
+        Some(self.mepc)
+    }
+
+    /// Returns from an S-mode trap handler and gives the address to resume
+    /// at, or `None` in U-mode.
+    pub fn sret(&mut self) -> Option<u32> {
+        if self.privilege == Privilege::User {
+            return None;
+        }
+        self.privilege = if self.mstatus & MSTATUS_SPP != 0 {
+            Privilege::Supervisor
+        } else {
+            Privilege::User
+        };
+        let sie = if self.mstatus & MSTATUS_SPIE != 0 {
+            MSTATUS_SIE
+        } else {
+            0
+        };
+        self.mstatus &= !(MSTATUS_SIE | MSTATUS_SPP);
+        self.mstatus |= sie | MSTATUS_SPIE;
+        Some(self.sepc)
+    }
+}
+
+impl Snapshot for CsrFile {
+    fn save(&self, out: &mut Encoder) {
+        out.u32(self.privilege as u32);
+        for value in [
+            self.mstatus, self.medeleg, self.mideleg, self.mie, self.mip, self.mtvec,
+            self.mscratch, self.mepc, self.mcause, self.mtval, self.stvec, self.sscratch,
+            self.sepc, self.scause, self.stval, self.satp,
+        ]
+        .iter()
+        {
+            out.u32(*value);
+        }
+        self.pmp.save(out);
+    }
+
+    fn restore(&mut self, input: &mut Decoder) -> io::Result<()> {
+        self.privilege = Privilege::from_bits(input.u32()?);
+        for value in [
+            &mut self.mstatus, &mut self.medeleg, &mut self.mideleg, &mut self.mie,
+            &mut self.mip, &mut self.mtvec, &mut self.mscratch, &mut self.mepc,
+            &mut self.mcause, &mut self.mtval, &mut self.stvec, &mut self.sscratch,
+            &mut self.sepc, &mut self.scause, &mut self.stval, &mut self.satp,
+        ]
+        .iter_mut()
+        {
+            **value = input.u32()?;
+        }
+        self.pmp.restore(input)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delegated_trap_and_return() {
+        let mut csr = CsrFile::default();
+        csr.write(MTVEC, 0x100).unwrap();
+        csr.write(STVEC, 0x200).unwrap();
+        csr.write(MEDELEG, 1 << 8).unwrap();
+        csr.write(MEPC, 0x1000).unwrap();
+        csr.write(MSTATUS, 0).unwrap();
+        assert_eq!(csr.mret(), Some(0x1000));
+        assert_eq!(csr.privilege, Privilege::User);
+        assert_eq!(csr.read(MSTATUS), None);
+
+        // U-mode의 ecall은 S-mode로 위임됨
+        assert_eq!(csr.enter_trap(8, 0x1004, 0), 0x200);
+        assert_eq!(csr.privilege, Privilege::Supervisor);
+        assert_eq!(csr.read(SSTATUS).unwrap() & MSTATUS_SPP, 0);
+        assert_eq!(csr.read(MEPC), None);
+        assert_eq!(csr.sret(), Some(0x1004));
+        assert_eq!(csr.privilege, Privilege::User);
+
+        // 위임되지 않은 exception은 M-mode로
+        assert_eq!(csr.enter_trap(2, 0x1008, 0x13), 0x100);
+        assert_eq!(csr.privilege, Privilege::Machine);
+        assert_eq!(csr.mtval, 0x13);
+        assert_eq!(csr.read(MSTATUS).unwrap() & MSTATUS_MPP, 0);
+        assert_eq!(csr.write(MHARTID, 1), None);
+    }
+
+    #[test]
+    fn interrupt_enable_and_delegation() {
+        let mut csr = CsrFile::default();
+        csr.write(MTVEC, 0x101).unwrap();
+        csr.write(MIE, (1 << IRQ_M_TIMER) | (1 << IRQ_S_SOFT))
+            .unwrap();
+        csr.write(MIDELEG, 1 << IRQ_S_SOFT).unwrap();
+        csr.mip = 1 << IRQ_M_TIMER;
+        // M-mode에서는 MIE가 꺼져 있으면 받지 않음
+        assert_eq!(csr.pending_interrupt(), None);
+        csr.write(MSTATUS, MSTATUS_MIE).unwrap();
+        assert_eq!(csr.pending_interrupt(), Some(IRQ_M_TIMER));
+        let handler = csr.enter_trap(CAUSE_INTERRUPT | IRQ_M_TIMER, 0x40, 0);
+        assert_eq!(handler, 0x100 + 4 * IRQ_M_TIMER);
+        assert_eq!(csr.pending_interrupt(), None);
+
+        // 위임된 인터럽트는 M-mode에서 받지 않음
+        csr.mip = 1 << IRQ_S_SOFT;
+        csr.write(MSTATUS, MSTATUS_MIE | MSTATUS_SIE).unwrap();
+        assert_eq!(csr.pending_interrupt(), None);
+        csr.privilege = Privilege::User;
+        assert_eq!(csr.pending_interrupt(), Some(IRQ_S_SOFT));
+        assert_eq!(
+            csr.trap_target(CAUSE_INTERRUPT | IRQ_S_SOFT),
+            Privilege::Supervisor
+        );
+    }
+}
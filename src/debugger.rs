@@ -0,0 +1,931 @@
+//! Interactive debugger.
+//!
+//! Commands run against a stopped machine. It advances by clocks or by
+//! retired instructions, stops before an instruction at a breakpoint retires
+//! and after a store to a watched range has retired, and shows the
+//! registers, the ROB, the reservation station, the load buffer and memory
+//! while stopped.
+//!
+//! While recording, it keeps the undo journal of every retired instruction
+//! and can step and run backwards by undoing it. The pipeline then restarts
+//! from the instruction it went back to.
+
+use instruction::{Opcode, REG_NAMES};
+use machine::Machine;
+use memory::mmu::{AccessType, Mmu};
+use pipeline::journal::{MemWrite, UndoRecord};
+use pipeline::operand::Operand;
+use pipeline::reorder_buffer::ReorderBufferEntry;
+use pipeline::Pipeline;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{self, Write};
+use std::str::FromStr;
+
+pub const HELP: &str = "\
+step [N]          run N clocks (1)
+stepi [N]         run until N instructions of the current hart retire (1)
+continue          run until a breakpoint, a watchpoint or the end
+break ADDR        stop before the instruction at ADDR retires
+watch ADDR [LEN]  stop after a store to LEN bytes at ADDR (4)
+delete [N]        delete breakpoint or watchpoint N, or all of them
+info regs|rob|rs|lsq|break
+x/N ADDR          show N words of memory at ADDR
+hart N            switch to hart N
+record [stop]     start or stop recording, to go backwards
+reverse-stepi [N] undo N instructions of the current hart (1)
+reverse-continue  undo back to a breakpoint, a watchpoint or the start of the record
+last-writer REG|ADDR
+                  show the last recorded instruction that wrote a register or memory
+quit
+ADDR is a symbol, 0x-prefixed hex or decimal";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Info {
+    Regs,
+    Rob,
+    Rs,
+    Lsq,
+    Break,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Step(usize),
+    StepInst(usize),
+    Continue,
+    Break(String),
+    Watch(String, u32),
+    Delete(Option<usize>),
+    Info(Info),
+    Examine(usize, String),
+    Hart(usize),
+    Record(bool),
+    ReverseStepInst(usize),
+    ReverseContinue,
+    LastWriter(String),
+    Help,
+    Quit,
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        let name = words.next().unwrap_or("");
+        let args: Vec<_> = words.collect();
+        let count = |default: usize| match args.first() {
+            Some(arg) => arg.parse().map_err(|_| format!("invalid count '{}'", arg)),
+            None => Ok(default),
+        };
+        let address = || match args.first() {
+            Some(arg) => Ok(arg.to_string()),
+            None => Err(format!("'{}' needs an address", name)),
+        };
+
+        let command = match name {
+            "step" | "s" => Command::Step(count(1)?),
+            "stepi" | "si" => Command::StepInst(count(1)?),
+            "continue" | "c" => Command::Continue,
+            "break" | "b" => Command::Break(address()?),
+            "watch" => {
+                let len = match args.get(1) {
+                    Some(len) => parse_number(len).ok_or(format!("invalid length '{}'", len))?,
+                    None => 4,
+                };
+                Command::Watch(address()?, len)
+            }
+            "delete" | "d" => Command::Delete(args.first().map(|_| count(0)).transpose()?),
+            "info" | "i" => Command::Info(match args.first() {
+                Some(&"regs") | Some(&"r") => Info::Regs,
+                Some(&"rob") => Info::Rob,
+                Some(&"rs") => Info::Rs,
+                Some(&"lsq") => Info::Lsq,
+                Some(&"break") | Some(&"b") => Info::Break,
+                _ => return Err("expected info regs, rob, rs, lsq or break".to_string()),
+            }),
+            "hart" => Command::Hart(count(0)?),
+            "record" => Command::Record(args.first() != Some(&"stop")),
+            "reverse-stepi" | "rsi" => Command::ReverseStepInst(count(1)?),
+            "reverse-continue" | "rc" => Command::ReverseContinue,
+            "last-writer" => Command::LastWriter(address()?),
+            "help" | "h" => Command::Help,
+            "quit" | "q" => Command::Quit,
+            _ if name.starts_with("x/") => {
+                let words = &name[2..];
+                let words = words
+                    .parse()
+                    .map_err(|_| format!("invalid count '{}'", words))?;
+                Command::Examine(words, address()?)
+            }
+            "x" => Command::Examine(1, address()?),
+            _ => return Err(format!("unknown command '{}', try 'help'", name)),
+        };
+        Ok(command)
+    }
+}
+
+/// An ABI register name or `x0` to `x31`.
+fn parse_reg(text: &str) -> Option<usize> {
+    if let Some(reg) = REG_NAMES.iter().position(|&name| name == text) {
+        return Some(reg);
+    }
+    match text.strip_prefix('x').map(str::parse) {
+        Some(Ok(reg)) if reg < 32 => Some(reg),
+        _ => None,
+    }
+}
+
+fn parse_number(text: &str) -> Option<u32> {
+    match text.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+/// Looks up the named functions and objects of the program.
+pub fn symbols(elf: &goblin::elf::Elf) -> HashMap<String, u32> {
+    elf.syms
+        .iter()
+        .filter(|sym| sym.st_value != 0 && !sym.is_import())
+        .filter_map(|sym| match elf.strtab.get(sym.st_name) {
+            Some(Ok(name)) if !name.is_empty() => Some((name.to_string(), sym.st_value as u32)),
+            _ => None,
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Point {
+    Break(u32),
+    Watch(u32, u32),
+}
+
+/// Why the machine stopped.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stop {
+    Breakpoint {
+        id: usize,
+        hart: usize,
+        pc: u32,
+    },
+    Watchpoint {
+        id: usize,
+        hart: usize,
+        pc: u32,
+        addr: u32,
+        value: u32,
+    },
+    Finished(u32),
+    /// Going backwards undid every recorded instruction.
+    RecordStart,
+    /// Going backwards reached a trap or a system instruction, whose changes
+    /// to CSRs, privilege or threads aren't recorded.
+    Irreversible { hart: usize, pc: u32 },
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stop::Breakpoint { id, hart, pc } => {
+                write!(f, "Breakpoint {}, hart {} at {:#010x}", id, hart, pc)
+            }
+            Stop::Watchpoint {
+                id,
+                hart,
+                pc,
+                addr,
+                value,
+            } => write!(
+                f,
+                "Watchpoint {}, hart {} at {:#010x} wrote {:#x} to {:#010x}",
+                id, hart, pc, value, addr
+            ),
+            Stop::Finished(code) => write!(f, "The program exited with code {}", code),
+            Stop::RecordStart => write!(f, "No more reverse-execution history"),
+            Stop::Irreversible { hart, pc } => write!(
+                f,
+                "Hart {} can't go back past the trap or system instruction at {:#010x}",
+                hart, pc
+            ),
+        }
+    }
+}
+
+pub struct Debugger {
+    symbols: HashMap<String, u32>,
+    points: BTreeMap<usize, Point>,
+    next_id: usize,
+    hart: usize,
+    is_finished: bool,
+    /// Undo records of the instructions retired while recording, oldest
+    /// first.
+    journal: Option<Vec<UndoRecord>>,
+}
+
+impl Debugger {
+    pub fn new(symbols: HashMap<String, u32>) -> Self {
+        Debugger {
+            symbols,
+            points: BTreeMap::new(),
+            next_id: 1,
+            hart: 0,
+            is_finished: false,
+            journal: None,
+        }
+    }
+
+    /// Runs `command`, returning what to print.
+    pub fn run(&mut self, machine: &mut Machine, command: Command) -> Result<String, String> {
+        match command {
+            Command::Step(clocks) => {
+                let stops = self.resume(machine, |_, clocks_run| clocks_run >= clocks)?;
+                Ok(self.stopped(machine, &stops))
+            }
+            Command::StepInst(count) => {
+                let stops = self.step_inst(machine, count, |_| false)?;
+                Ok(self.stopped(machine, &stops))
+            }
+            Command::Continue => {
+                let stops = self.resume(machine, |_, _| false)?;
+                Ok(self.stopped(machine, &stops))
+            }
+            Command::Break(addr) => {
+                let pc = self.address(&addr)?;
+                let id = self.insert_breakpoint(machine, pc);
+                Ok(format!("Breakpoint {} at {:#010x}", id, pc))
+            }
+            Command::Watch(addr, len) => {
+                let start = self.address(&addr)?;
+                let id = self.add(Point::Watch(start, len));
+                self.sync_points(machine);
+                Ok(format!(
+                    "Watchpoint {} at {:#010x}, {} bytes",
+                    id, start, len
+                ))
+            }
+            Command::Delete(Some(id)) => {
+                self.points
+                    .remove(&id)
+                    .ok_or(format!("no breakpoint or watchpoint {}", id))?;
+                self.sync_points(machine);
+                Ok(String::new())
+            }
+            Command::Delete(None) => {
+                self.points.clear();
+                self.sync_points(machine);
+                Ok(String::new())
+            }
+            Command::Info(info) => Ok(self.info(machine, info)),
+            Command::Examine(words, addr) => {
+                let addr = self.address(&addr)?;
+                self.examine(machine, words, addr)
+            }
+            Command::Hart(hart) if hart < machine.harts.len() => {
+                self.hart = hart;
+                Ok(format!("Hart {}", hart))
+            }
+            Command::Hart(hart) => Err(format!("no hart {}", hart)),
+            Command::Record(is_recording) => {
+                machine.record(is_recording);
+                self.journal = if is_recording { Some(Vec::new()) } else { None };
+                Ok(String::new())
+            }
+            Command::ReverseStepInst(count) => {
+                let stops = self.reverse_step_inst(machine, count)?;
+                Ok(self.stopped(machine, &stops))
+            }
+            Command::ReverseContinue => {
+                let stops = self.reverse_continue(machine)?;
+                Ok(self.stopped(machine, &stops))
+            }
+            Command::LastWriter(target) => self.last_writer(&target),
+            Command::Help => Ok(HELP.to_string()),
+            Command::Quit => Ok(String::new()),
+        }
+    }
+
+    /// Hart that `stepi`, registers and memory refer to.
+    pub fn hart(&self) -> usize {
+        self.hart
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.is_finished
+    }
+
+    /// Adds a breakpoint at `pc`, returning its number.
+    pub fn insert_breakpoint(&mut self, machine: &mut Machine, pc: u32) -> usize {
+        let id = self.add(Point::Break(pc));
+        self.sync_points(machine);
+        id
+    }
+
+    /// Deletes the breakpoints at `pc`. Returns false if there were none.
+    pub fn remove_breakpoint(&mut self, machine: &mut Machine, pc: u32) -> bool {
+        let count = self.points.len();
+        self.points.retain(|_, point| *point != Point::Break(pc));
+        self.sync_points(machine);
+        self.points.len() < count
+    }
+
+    /// Deletes every breakpoint and watchpoint and lets the harts run freely
+    /// again.
+    pub fn detach(&mut self, machine: &mut Machine) {
+        self.points.clear();
+        self.sync_points(machine);
+        for hart in machine.harts.iter_mut() {
+            hart.commit_stop = Default::default();
+        }
+    }
+
+    fn address(&self, text: &str) -> Result<u32, String> {
+        let text = text.trim_start_matches('*');
+        self.symbols
+            .get(text)
+            .cloned()
+            .or_else(|| parse_number(text))
+            .ok_or(format!("no symbol '{}'", text))
+    }
+
+    fn add(&mut self, point: Point) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.points.insert(id, point);
+        id
+    }
+
+    /// Sets the breakpoints and watchpoints of every hart's commit.
+    fn sync_points(&self, machine: &mut Machine) {
+        for hart in machine.harts.iter_mut() {
+            let stop = &mut hart.commit_stop;
+            stop.breakpoints.clear();
+            stop.watchpoints.clear();
+            for point in self.points.values() {
+                match *point {
+                    Point::Break(pc) => {
+                        stop.breakpoints.insert(pc);
+                    }
+                    Point::Watch(start, len) => stop.watchpoints.push((start, len)),
+                }
+            }
+        }
+    }
+
+    /// Runs until `count` instructions of the current hart have retired,
+    /// `is_done` or something stops the machine.
+    pub fn step_inst<F>(
+        &mut self,
+        machine: &mut Machine,
+        count: usize,
+        mut is_done: F,
+    ) -> Result<Vec<Stop>, String>
+    where
+        F: FnMut(usize) -> bool,
+    {
+        let hart = self.hart;
+        machine.harts[hart].commit_stop.budget = Some(count);
+        let stops = self.resume(machine, |machine, clocks| {
+            machine.harts[hart].commit_stop.budget == Some(0) || is_done(clocks)
+        });
+        machine.harts[hart].commit_stop.budget = None;
+        stops
+    }
+
+    /// Runs clocks until `is_done`, given the clocks run so far, or until
+    /// something stops the machine. Returns what stopped it, which is
+    /// nothing when `is_done` did.
+    pub fn resume<F>(&mut self, machine: &mut Machine, mut is_done: F) -> Result<Vec<Stop>, String>
+    where
+        F: FnMut(&Machine, usize) -> bool,
+    {
+        if self.is_finished {
+            return Err("The program is not running".to_string());
+        }
+        // 멈춘 breakpoint의 명령어부터 다시 retire함
+        for hart in machine.harts.iter_mut() {
+            hart.commit_stop.resume_at = hart.commit_stop.hit.take();
+        }
+        let mut clocks = 0;
+        loop {
+            let (retired, is_finished) = machine.run_clock();
+            clocks += 1;
+            if let Some(journal) = self.journal.as_mut() {
+                journal.extend(machine.take_journal());
+            }
+            if is_finished {
+                self.is_finished = true;
+                let code = machine.threads.exit_code().unwrap_or(0);
+                return Ok(vec![Stop::Finished(code)]);
+            }
+            let mut stops = self.watchpoints_hit(&retired);
+            stops.extend(self.breakpoints_hit(machine));
+            if !stops.is_empty() || is_done(machine, clocks) {
+                return Ok(stops);
+            }
+        }
+    }
+
+    /// Undoes instructions until `count` of the current hart have been.
+    pub fn reverse_step_inst(
+        &mut self,
+        machine: &mut Machine,
+        count: usize,
+    ) -> Result<Vec<Stop>, String> {
+        let hart = self.hart;
+        let mut undone = 0;
+        self.go_back(machine, |_, record| {
+            undone += (record.hart == hart) as usize;
+            if undone == count {
+                Some(Vec::new())
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Undoes instructions until one at a breakpoint or a store to a watched
+    /// range has been.
+    pub fn reverse_continue(&mut self, machine: &mut Machine) -> Result<Vec<Stop>, String> {
+        self.go_back(machine, |points, record| {
+            let stops = points_undone(points, record);
+            if stops.is_empty() {
+                None
+            } else {
+                Some(stops)
+            }
+        })
+    }
+
+    /// Pops undo records, newest first, until `is_done` returns what stopped
+    /// it or the journal runs out, then restarts every hart from the oldest
+    /// instruction it undid.
+    fn go_back<F>(&mut self, machine: &mut Machine, mut is_done: F) -> Result<Vec<Stop>, String>
+    where
+        F: FnMut(&BTreeMap<usize, Point>, &UndoRecord) -> Option<Vec<Stop>>,
+    {
+        if self.is_finished {
+            return Err("The program is not running".to_string());
+        }
+        let journal = match self.journal.as_mut() {
+            Some(journal) => journal,
+            None => return Err("Not recording, use 'record' first".to_string()),
+        };
+        // retire 전에 메모리를 이미 쓴 ROB head의 store도 되돌림
+        for hart in machine.harts.iter() {
+            if let Some(write) = hart.rob.iter().next().and_then(MemWrite::of) {
+                write.restore(&mut machine.mem_sys.memory);
+            }
+        }
+        let mut pcs: Vec<_> = machine.harts.iter().map(|hart| hart.next_pc()).collect();
+        let stops = loop {
+            let record = match journal.pop() {
+                Some(record) => record,
+                None => break vec![Stop::RecordStart],
+            };
+            if !record.is_reversible {
+                let stop = Stop::Irreversible {
+                    hart: record.hart,
+                    pc: record.pc,
+                };
+                journal.push(record);
+                break vec![stop];
+            }
+            let hart = record.hart;
+            record.undo(&mut machine.harts[hart].reg, &mut machine.mem_sys.memory);
+            if let Some(write) = record.mem {
+                machine.mem_sys.written_functionally(hart, write.paddr);
+            }
+            pcs[hart] = record.pc;
+            if let Some(stops) = is_done(&self.points, &record) {
+                break stops;
+            }
+        };
+        for (hart, pc) in machine.harts.iter_mut().zip(pcs) {
+            hart.restart_at(pc, &mut machine.mem_sys);
+            // 앞으로 continue하면 이 breakpoint는 지나감
+            let stop = &mut hart.commit_stop;
+            stop.hit = Some(pc).filter(|pc| stop.breakpoints.contains(pc));
+        }
+        Ok(stops)
+    }
+
+    /// Shows the last recorded instruction that wrote a register of the
+    /// current hart, or the memory at an address.
+    fn last_writer(&self, target: &str) -> Result<String, String> {
+        let journal = self
+            .journal
+            .as_ref()
+            .ok_or("Not recording, use 'record' first")?;
+        let (record, value) = match parse_reg(target) {
+            Some(reg) => journal
+                .iter()
+                .rev()
+                .filter(|record| record.hart == self.hart)
+                .find_map(|record| {
+                    let write = record.regs.iter().find(|write| write.reg as usize == reg)?;
+                    Some((record, write.new))
+                }),
+            None => {
+                let addr = self.address(target)?;
+                journal.iter().rev().find_map(|record| {
+                    let write = record.mem.filter(|write| write.overlaps(addr, 1))?;
+                    Some((record, write.new))
+                })
+            }
+        }
+        .ok_or(format!("nothing recorded wrote {}", target))?;
+        Ok(format!(
+            "{:#x} by {:#010x} {}, hart {} at clock {}",
+            value, record.pc, record.inst, record.hart, record.clock
+        ))
+    }
+
+    fn watchpoints_hit(&self, retired: &[Vec<(usize, ReorderBufferEntry)>]) -> Vec<Stop> {
+        let mut stops = Vec::new();
+        for (hart, entries) in retired.iter().enumerate() {
+            for (_, entry) in entries {
+                let (addr, _, value) = match entry.mem_write() {
+                    Some(write) => write,
+                    None => continue,
+                };
+                for (&id, point) in self.points.iter() {
+                    match *point {
+                        Point::Watch(start, len) if entry.writes_to(start, len) => {
+                            stops.push(Stop::Watchpoint {
+                                id,
+                                hart,
+                                pc: entry.pc,
+                                addr,
+                                value,
+                            })
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        stops
+    }
+
+    fn breakpoints_hit(&self, machine: &Machine) -> Vec<Stop> {
+        machine
+            .harts
+            .iter()
+            .filter_map(|hart| hart.commit_stop.hit.map(|pc| (hart.hart_id, pc)))
+            .filter_map(|(hart, pc)| {
+                let id = self
+                    .points
+                    .iter()
+                    .find(|(_, point)| **point == Point::Break(pc))?
+                    .0;
+                Some(Stop::Breakpoint { id: *id, hart, pc })
+            })
+            .collect()
+    }
+
+    fn stopped(&self, machine: &Machine, stops: &[Stop]) -> String {
+        let mut text = String::new();
+        for stop in stops {
+            writeln!(text, "{}", stop).unwrap();
+        }
+        if !self.is_finished {
+            let hart = &machine.harts[self.hart];
+            write!(
+                text,
+                "Clock {}, hart {} next at {:#010x}",
+                machine.clock,
+                self.hart,
+                hart.next_pc()
+            )
+            .unwrap();
+            if let Some(name) = self.symbol_at(hart.next_pc()) {
+                write!(text, " <{}>", name).unwrap();
+            }
+        }
+        text.trim_end().to_string()
+    }
+
+    fn symbol_at(&self, addr: u32) -> Option<&str> {
+        self.symbols
+            .iter()
+            .filter(|(_, &value)| value <= addr)
+            .max_by_key(|(_, &value)| value)
+            .filter(|(_, &value)| addr - value < 0x1000)
+            .map(|(name, _)| name.as_str())
+    }
+
+    fn info(&self, machine: &Machine, info: Info) -> String {
+        let hart = &machine.harts[self.hart];
+        let mut text = String::new();
+        match info {
+            Info::Regs => {
+                writeln!(text, "pc   {:#010x}", hart.next_pc()).unwrap();
+                for (i, name) in REG_NAMES.iter().enumerate() {
+                    write!(text, "{:<4} {:#010x}", name, hart.reg.gpr[i].read()).unwrap();
+                    text.push(if i % 4 == 3 { '\n' } else { ' ' });
+                }
+            }
+            Info::Rob => {
+                for (pos, entry) in hart.rob.iter().enumerate() {
+                    let state = if entry.is_completed() { "done" } else { "wait" };
+                    write!(
+                        text,
+                        "{:>3} {:#010x} {:<28} {}",
+                        pos,
+                        entry.pc,
+                        entry.inst.to_string(),
+                        state
+                    )
+                    .unwrap();
+                    if let Some(value) = entry.reg_value {
+                        write!(text, " {:#x}", value).unwrap();
+                    }
+                    text.push('\n');
+                }
+            }
+            Info::Rs => {
+                let mut entries: Vec<_> = hart
+                    .rs
+                    .entries()
+                    .map(|entry| (rob_position(hart, entry.rob_index), entry))
+                    .collect();
+                entries.sort_by_key(|(pos, _)| *pos);
+                for (pos, entry) in entries {
+                    writeln!(
+                        text,
+                        "rob {:>3} {:<28} {:<8} {} {}",
+                        pos,
+                        entry.inst.to_string(),
+                        format!("{:?}", entry.status),
+                        operand(hart, entry.operand.0),
+                        operand(hart, entry.operand.1)
+                    )
+                    .unwrap();
+                }
+            }
+            Info::Lsq => {
+                let mut loads: Vec<_> = hart
+                    .rs
+                    .load_buffer()
+                    .entries()
+                    .map(|entry| (rob_position(hart, entry.rob_index), entry))
+                    .collect();
+                loads.sort_by_key(|(pos, _)| *pos);
+                for (pos, entry) in loads {
+                    write!(
+                        text,
+                        "load  rob {:>3} {:<8} paddr {:#010x}",
+                        pos,
+                        format!("{:?}", entry.status),
+                        entry.paddr
+                    )
+                    .unwrap();
+                    match entry.value {
+                        Ok(value) => writeln!(text, " value {:#x}", value),
+                        Err(ref exception) => writeln!(text, " {:?}", exception),
+                    }
+                    .unwrap();
+                }
+                let stores =
+                    hart.rob.iter().enumerate().filter(|(_, entry)| {
+                        matches!(entry.inst.opcode, Opcode::Store | Opcode::Amo)
+                    });
+                for (pos, entry) in stores {
+                    writeln!(
+                        text,
+                        "store rob {:>3} {:<28} addr {} value {}",
+                        pos,
+                        entry.inst.to_string(),
+                        operand(hart, entry.addr),
+                        operand(hart, entry.mem_value)
+                    )
+                    .unwrap();
+                }
+            }
+            Info::Break => {
+                for (id, point) in self.points.iter() {
+                    match point {
+                        Point::Break(pc) => writeln!(text, "{:>3} break {:#010x}", id, pc),
+                        Point::Watch(start, len) => {
+                            writeln!(text, "{:>3} watch {:#010x} {} bytes", id, start, len)
+                        }
+                    }
+                    .unwrap();
+                }
+            }
+        }
+        text.trim_end().to_string()
+    }
+
+    /// Reads `words` words at virtual address `addr`, as the current hart
+    /// sees memory.
+    fn examine(&self, machine: &Machine, words: usize, addr: u32) -> Result<String, String> {
+        let hart = &machine.harts[self.hart];
+        let memory = &machine.mem_sys.memory;
+        let mut text = String::new();
+        for i in 0..words {
+            let vaddr = addr.wrapping_add(4 * i as u32);
+            if i % 4 == 0 {
+                if i > 0 {
+                    text.push('\n');
+                }
+                write!(text, "{:#010x}:", vaddr).unwrap();
+            }
+            let word = Mmu::translate_now(vaddr, AccessType::Load, &hart.reg.csr, memory)
+                .and_then(|paddr| memory.read::<u32>(paddr))
+                .map_err(|_| format!("Cannot access memory at {:#010x}", vaddr))?;
+            write!(text, " {:#010x}", word).unwrap();
+        }
+        Ok(text)
+    }
+}
+
+/// Breakpoints at an undone instruction and watchpoints on what it stored.
+fn points_undone(points: &BTreeMap<usize, Point>, record: &UndoRecord) -> Vec<Stop> {
+    points
+        .iter()
+        .filter_map(|(&id, point)| match (*point, record.mem) {
+            (Point::Break(pc), _) if pc == record.pc => Some(Stop::Breakpoint {
+                id,
+                hart: record.hart,
+                pc,
+            }),
+            (Point::Watch(start, len), Some(write)) if write.overlaps(start, len) => {
+                Some(Stop::Watchpoint {
+                    id,
+                    hart: record.hart,
+                    pc: record.pc,
+                    addr: write.vaddr,
+                    value: write.new,
+                })
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Position in the ROB, counted from the head, of the entry `rob_index`.
+fn rob_position(hart: &Pipeline, rob_index: usize) -> usize {
+    hart.rob
+        .iter_with_id()
+        .position(|(id, _)| id == rob_index)
+        .unwrap_or(usize::MAX)
+}
+
+fn operand(hart: &Pipeline, operand: Operand) -> String {
+    match operand {
+        Operand::Value(value) => format!("{:#x}", value),
+        Operand::Rob(rob_index) => format!("rob{}", rob_position(hart, rob_index)),
+        Operand::None => "-".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use machine::tests::{with_program, STORE_LOOP};
+    use pipeline::PipelineConfig;
+
+    #[test]
+    fn breakpoints_steps_and_watchpoints() {
+        let mut machine = with_program(&STORE_LOOP, Default::default());
+        let symbols = vec![("loop".to_string(), 8)].into_iter().collect();
+        let mut debugger = Debugger::new(symbols);
+        let mut run = |machine: &mut Machine, command: &str| {
+            debugger.run(machine, command.parse().unwrap()).unwrap()
+        };
+
+        run(&mut machine, "break loop");
+        assert!(run(&mut machine, "continue").starts_with("Breakpoint 1, hart 0 at 0x00000008"));
+        assert_eq!(machine.harts[0].next_pc(), 8);
+        assert_eq!(machine.harts[0].reg.gpr[10].read(), 0);
+        // 다시 멈추기 전에 한 바퀴를 돎
+        run(&mut machine, "continue");
+        assert_eq!(machine.harts[0].reg.gpr[10].read(), 1);
+
+        run(&mut machine, "delete");
+        run(&mut machine, "stepi 2");
+        assert_eq!(machine.harts[0].reg.gpr[10].read(), 2);
+        assert_eq!(machine.harts[0].next_pc(), 0x10);
+
+        run(&mut machine, "watch 0x100");
+        let stopped = run(&mut machine, "c");
+        assert!(stopped.starts_with("Watchpoint 2, hart 0 at 0x0000000c wrote 0x3 to 0x00000100"));
+        assert_eq!(
+            run(&mut machine, "x/2 0x100"),
+            "0x00000100: 0x00000003 0x00000000"
+        );
+
+        assert!(run(&mut machine, "info regs").contains("a0   0x00000003"));
+        assert_eq!(
+            "x/y 0".parse::<Command>(),
+            Err("invalid count 'y'".to_string())
+        );
+        assert!("frobnicate".parse::<Command>().is_err());
+    }
+
+    #[test]
+    fn breakpoint_behind_a_mispredicted_branch() {
+        // li a0, 0; li a1, 3; loop: addi a0, a0, 1; bne a0, a1, loop; li a2, 7
+        let program = [
+            0x0000_0513u32,
+            0x0030_0593,
+            0x0015_0513,
+            0xfeb5_1ee3,
+            0x0070_0613,
+        ];
+        let mut machine = with_program(&program, Default::default());
+        let mut debugger = Debugger::new(HashMap::new());
+
+        debugger.run(&mut machine, "break 0x10".parse().unwrap()).unwrap();
+        let stopped = debugger.run(&mut machine, Command::Continue).unwrap();
+        assert!(stopped.starts_with("Breakpoint 1, hart 0 at 0x00000010"));
+        // wrong path에서 fetch된 li a2, 7에서는 멈추지 않음
+        assert_eq!(machine.harts[0].next_pc(), 0x10);
+        assert_eq!(machine.harts[0].reg.gpr[10].read(), 3);
+    }
+
+    #[test]
+    fn reverse_execution() {
+        let mut machine = with_program(&STORE_LOOP, Default::default());
+        let mut debugger = Debugger::new(HashMap::new());
+        let mut run = |machine: &mut Machine, command: &str| {
+            debugger.run(machine, command.parse().unwrap())
+        };
+
+        assert!(run(&mut machine, "rsi").is_err());
+        run(&mut machine, "record").unwrap();
+        run(&mut machine, "stepi 11").unwrap();
+        assert_eq!(machine.harts[0].reg.gpr[10].read(), 3);
+        assert_eq!(machine.mem_sys.memory.read::<u32>(0x100).unwrap(), 3);
+        let writer = run(&mut machine, "last-writer a0").unwrap();
+        assert!(writer.starts_with("0x3 by 0x00000008 addi a0, a0, 1, hart 0 at clock"));
+        assert_eq!(run(&mut machine, "last-writer x10").unwrap(), writer);
+        assert!(run(&mut machine, "last-writer 0x100")
+            .unwrap()
+            .starts_with("0x3 by 0x0000000c sw"));
+
+        // j, sw, addi를 되돌림
+        run(&mut machine, "reverse-stepi 3").unwrap();
+        assert_eq!(machine.harts[0].reg.gpr[10].read(), 2);
+        assert_eq!(machine.mem_sys.memory.read::<u32>(0x100).unwrap(), 2);
+        assert_eq!(machine.harts[0].next_pc(), 8);
+
+        run(&mut machine, "watch 0x100").unwrap();
+        let stopped = run(&mut machine, "reverse-continue").unwrap();
+        assert!(stopped.starts_with("Watchpoint 1, hart 0 at 0x0000000c wrote 0x2"));
+        assert_eq!(machine.mem_sys.memory.read::<u32>(0x100).unwrap(), 1);
+        assert_eq!(machine.harts[0].next_pc(), 0xc);
+
+        run(&mut machine, "delete").unwrap();
+        run(&mut machine, "break 0x4").unwrap();
+        let stopped = run(&mut machine, "rc").unwrap();
+        assert!(stopped.starts_with("Breakpoint 2, hart 0 at 0x00000004"));
+        assert_eq!(machine.harts[0].reg.gpr[11].read(), 0);
+        assert!(run(&mut machine, "rc")
+            .unwrap()
+            .starts_with("No more reverse-execution history"));
+        assert_eq!(machine.harts[0].next_pc(), 0);
+
+        // 처음부터 다시 실행하면 같은 값을 씀
+        run(&mut machine, "delete").unwrap();
+        run(&mut machine, "stepi 11").unwrap();
+        assert_eq!(machine.harts[0].reg.gpr[10].read(), 3);
+        assert_eq!(machine.mem_sys.memory.read::<u32>(0x100).unwrap(), 3);
+    }
+
+    #[test]
+    fn reverse_stops_at_system_instructions() {
+        // li a0, 1; csrr a1, mhartid; li a0, 2; li a0, 2; j .
+        let program = [
+            0x0010_0513u32,
+            0xf140_25f3,
+            0x0020_0513,
+            0x0020_0513,
+            0x0000_006f,
+        ];
+        // M-mode에서 csrr을 실행함
+        let config = PipelineConfig {
+            full_system: true,
+            ..Default::default()
+        };
+        let mut machine = with_program(&program, config);
+        let mut debugger = Debugger::new(HashMap::new());
+        let mut run = |machine: &mut Machine, command: &str| {
+            debugger.run(machine, command.parse().unwrap()).unwrap()
+        };
+
+        run(&mut machine, "record");
+        run(&mut machine, "stepi 4");
+        // 같은 값을 쓴 두 번째 li가 마지막으로 씀
+        assert!(run(&mut machine, "last-writer a0").starts_with("0x2 by 0x0000000c"));
+        let stopped = run(&mut machine, "reverse-stepi 5");
+        assert!(stopped.starts_with(
+            "Hart 0 can't go back past the trap or system instruction at 0x00000004"
+        ));
+        assert_eq!(machine.harts[0].next_pc(), 8);
+        assert_eq!(machine.harts[0].reg.gpr[10].read(), 1);
+        assert!(run(&mut machine, "rc").starts_with("Hart 0 can't go back"));
+    }
+}
@@ -0,0 +1,189 @@
+//! Textbook-style pipeline diagrams.
+//!
+//! One row per retired instruction in program order and one column per
+//! clock, with a letter for the stage the instruction was in: `F` when it was
+//! fetched, `I` when it was issued, `E` for each clock it spent executing, `W`
+//! when its result was written back and `C` when it committed. `-` marks the
+//! clocks it spent waiting in between, in the fetch queue, for its operands
+//! or for older instructions to commit.
+
+use pipeline::lifecycle::InstRecord;
+use std::fmt;
+use std::str::FromStr;
+
+/// Addresses `[start, end)`, written as `start:end` in hex.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PcRange {
+    pub start: u32,
+    pub end: u32,
+}
+
+impl PcRange {
+    pub fn contains(&self, pc: u32) -> bool {
+        self.start <= pc && pc < self.end
+    }
+}
+
+impl FromStr for PcRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |addr: &str| {
+            let addr = addr.trim_start_matches("0x");
+            u32::from_str_radix(addr, 16).map_err(|_| format!("invalid address '{}'", addr))
+        };
+        match s.find(':') {
+            Some(colon) => Ok(PcRange {
+                start: parse(&s[..colon])?,
+                end: parse(&s[colon + 1..])?,
+            }),
+            None => Err(format!("expected start:end, got '{}'", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PipelineDiagram {
+    /// Instructions to show.
+    limit: usize,
+    pc_range: Option<PcRange>,
+    rows: Vec<InstRecord>,
+}
+
+impl PipelineDiagram {
+    pub fn new(limit: usize, pc_range: Option<PcRange>) -> Self {
+        PipelineDiagram {
+            limit,
+            pc_range,
+            rows: Vec::new(),
+        }
+    }
+
+    /// Keeps `record` if it retired inside the PC range and there is room.
+    /// Records must come in the order the instructions retired.
+    pub fn record(&mut self, record: InstRecord) {
+        let is_shown = !record.flushed
+            && self
+                .pc_range
+                .is_none_or(|range| range.contains(record.pc));
+        if is_shown && !self.is_full() {
+            self.rows.push(record);
+        }
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.rows.len() >= self.limit
+    }
+
+    /// Stage letter of each clock from `first` to the commit of `record`.
+    fn stages(record: &InstRecord, first: usize) -> Vec<char> {
+        let times = &record.times;
+        let mut cells = vec![' '; record.ended - first + 1];
+        let mut paint = |clock: usize, stage: char| cells[clock - first] = stage;
+
+        for clock in times.fetched..record.ended {
+            paint(clock, '-');
+        }
+        paint(times.fetched, 'F');
+        if let Some(issued) = times.issued {
+            paint(issued, 'I');
+        }
+        if let (Some(executed), Some(completed)) = (times.executed, times.completed) {
+            for clock in executed..completed {
+                paint(clock, 'E');
+            }
+            paint(completed, 'W');
+        }
+        paint(record.ended, 'C');
+        cells
+    }
+}
+
+impl fmt::Display for PipelineDiagram {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let first = match self.rows.iter().map(|row| row.times.fetched).min() {
+            Some(first) => first,
+            None => return writeln!(f, "No instruction retired"),
+        };
+        let last = self.rows.iter().map(|row| row.ended).max().unwrap();
+        let width = last.to_string().len() + 1;
+        let show_hart = self.rows.iter().any(|row| row.hart != 0);
+        let label = |row: &InstRecord| {
+            let hart = if show_hart {
+                format!("{} ", row.hart)
+            } else {
+                String::new()
+            };
+            format!("{}{:08x} {:?}", hart, row.pc, row.inst.function)
+        };
+        let label_width = self.rows.iter().map(|row| label(row).len()).max().unwrap();
+
+        write!(f, "{:1$}", "", label_width + 1)?;
+        for clock in first..=last {
+            write!(f, "{:>1$}", clock, width)?;
+        }
+        writeln!(f)?;
+        for row in self.rows.iter() {
+            write!(f, "{:1$} ", label(row), label_width)?;
+            for stage in Self::stages(row, first) {
+                write!(f, "{:>1$}", stage, width)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use instruction::Instruction;
+    use pipeline::lifecycle::Timestamps;
+
+    fn retired(pc: u32, times: Timestamps, ended: usize) -> InstRecord {
+        InstRecord {
+            hart: 0,
+            pc,
+            inst: Instruction::default(),
+            times,
+            ended,
+            flushed: false,
+        }
+    }
+
+    #[test]
+    fn stages_and_stalls() {
+        let add = Timestamps {
+            seq: 0,
+            fetched: 1,
+            issued: Some(3),
+            ready: Some(3),
+            executed: Some(4),
+            completed: Some(5),
+        };
+        // 4 clock 걸리는 mul을 기다리며 commit이 밀림
+        let mul = Timestamps {
+            seq: 1,
+            fetched: 1,
+            issued: Some(3),
+            ready: Some(3),
+            executed: Some(4),
+            completed: Some(8),
+        };
+        let mut diagram = PipelineDiagram::new(2, "0:10008".parse().ok());
+        diagram.record(retired(0x10000, add, 6));
+        diagram.record(retired(0x10004, mul, 9));
+        diagram.record(retired(0x10000, add, 10));
+        assert!(diagram.is_full());
+
+        let text = diagram.to_string();
+        let cells: Vec<String> = text
+            .lines()
+            .skip(1)
+            .map(|line| line[14..].split_whitespace().collect())
+            .collect();
+        assert_eq!(cells, vec!["F-IEWC", "F-IEEEEWC"]);
+        assert_eq!("1:2".parse(), Ok(PcRange { start: 1, end: 2 }));
+        assert!("12".parse::<PcRange>().is_err());
+    }
+}
@@ -0,0 +1,594 @@
+//! GDB remote serial protocol stub.
+//!
+//! `gdb` connects with `target remote :<port>` and drives the machine
+//! through the debugger. Registers are the architectural ones, memory is
+//! `ProcessMemory` as the selected hart sees it, breakpoints stop before the
+//! instruction retires and a single step retires one instruction, so gdb
+//! only sees the state between two retired instructions. Each hart is a
+//! thread, numbered from 1.
+
+use debugger::{Command, Debugger, Stop};
+use machine::Machine;
+use memory::mmu::{AccessType, Mmu};
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+
+/// General purpose registers and `pc`, in the order of gdb's RISC-V
+/// register numbers.
+const REG_COUNT: usize = 33;
+const PC_REG: usize = 32;
+
+/// Clocks between checks for an interrupt from gdb while running.
+const INTERRUPT_CHECK_CLOCKS: usize = 1024;
+
+/// The link to gdb.
+pub trait Connection: Read + Write {
+    /// Whether gdb has sent an interrupt (Ctrl-C) since the last packet.
+    fn interrupted(&mut self) -> io::Result<bool>;
+}
+
+/// Any other byte is left for the next packet to read.
+impl Connection for TcpStream {
+    fn interrupted(&mut self) -> io::Result<bool> {
+        self.set_nonblocking(true)?;
+        let mut byte = [0];
+        let peeked = self.peek(&mut byte);
+        self.set_nonblocking(false)?;
+        match peeked {
+            Ok(0) => Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(_) if byte[0] == 0x03 => self.read_exact(&mut byte).map(|_| true),
+            Ok(_) => Ok(false),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl<C: Connection> Connection for &mut C {
+    fn interrupted(&mut self) -> io::Result<bool> {
+        (**self).interrupted()
+    }
+}
+
+/// How the session with gdb ended.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum End {
+    /// gdb detached or went away, and the program runs on.
+    Detached,
+    Killed,
+    Finished,
+}
+
+pub struct GdbStub<C: Connection> {
+    conn: C,
+    debugger: Debugger,
+    /// Hart whose registers and memory gdb reads and writes.
+    hart: usize,
+    /// Kind of the breakpoint gdb inserted at each address, 0 for software
+    /// and 1 for hardware.
+    breakpoints: HashMap<u32, u8>,
+    last_stop: String,
+}
+
+impl<C: Connection> GdbStub<C> {
+    pub fn new(conn: C) -> Self {
+        GdbStub {
+            conn,
+            debugger: Debugger::new(HashMap::new()),
+            hart: 0,
+            breakpoints: HashMap::new(),
+            last_stop: "S05".to_string(),
+        }
+    }
+
+    /// Answers gdb until it detaches or kills the program, or the program
+    /// ends.
+    pub fn serve(&mut self, machine: &mut Machine) -> io::Result<End> {
+        while let Some(packet) = self.read_packet()? {
+            let reply = match packet.as_bytes().first() {
+                Some(b'k') => return Ok(End::Killed),
+                Some(b'D') => {
+                    self.write_packet("OK")?;
+                    break;
+                }
+                Some(b'c') | Some(b's') => {
+                    if let Some(pc) = parse_hex(&packet[1..]) {
+                        self.restart_hart(machine, pc);
+                    }
+                    self.resume(machine, packet.starts_with('s'))?
+                }
+                _ => self.handle(machine, &packet),
+            };
+            self.write_packet(&reply)?;
+        }
+        if self.debugger.is_finished() {
+            return Ok(End::Finished);
+        }
+        self.debugger.detach(machine);
+        Ok(End::Detached)
+    }
+
+    /// Reply to a packet that doesn't resume the machine.
+    fn handle(&mut self, machine: &mut Machine, packet: &str) -> String {
+        // 빈 packet은 지원하지 않는 packet처럼 빈 packet으로 답함
+        let kind = match packet.chars().next() {
+            Some(kind) => kind,
+            None => return String::new(),
+        };
+        let args = &packet[kind.len_utf8()..];
+        match kind {
+            '?' => self.last_stop.clone(),
+            'g' => (0..REG_COUNT)
+                .map(|reg| hex_word(self.read_reg(machine, reg)))
+                .collect(),
+            'G' => {
+                for reg in 0..REG_COUNT {
+                    match args.get(reg * 8..reg * 8 + 8).and_then(parse_word) {
+                        Some(value) => self.write_reg(machine, reg, value),
+                        None => return "E01".to_string(),
+                    }
+                }
+                "OK".to_string()
+            }
+            'p' => match usize::from_str_radix(args, 16) {
+                Ok(reg) if reg < REG_COUNT => hex_word(self.read_reg(machine, reg)),
+                _ => "E01".to_string(),
+            },
+            'P' => {
+                let mut fields = args.splitn(2, '=');
+                let reg = fields
+                    .next()
+                    .and_then(|reg| usize::from_str_radix(reg, 16).ok());
+                match (reg, fields.next().and_then(parse_word)) {
+                    (Some(reg), Some(value)) if reg < REG_COUNT => {
+                        self.write_reg(machine, reg, value);
+                        "OK".to_string()
+                    }
+                    _ => "E01".to_string(),
+                }
+            }
+            'm' => match parse_range(args) {
+                Some((addr, len)) => self.read_memory(machine, addr, len),
+                None => "E01".to_string(),
+            },
+            'M' => {
+                let mut fields = args.splitn(2, ':');
+                let range = fields.next().and_then(parse_range);
+                match (range, fields.next().and_then(parse_bytes)) {
+                    (Some((addr, len)), Some(ref bytes)) if bytes.len() == len => {
+                        self.write_memory(machine, addr, bytes)
+                    }
+                    _ => "E01".to_string(),
+                }
+            }
+            'Z' | 'z' => {
+                let mut fields = args.split(',');
+                let point = fields.next().and_then(|kind| kind.parse::<u8>().ok());
+                let addr = fields.next().and_then(parse_hex);
+                match (point, addr) {
+                    (Some(point @ 0), Some(addr)) | (Some(point @ 1), Some(addr)) => {
+                        if kind == 'Z' {
+                            self.debugger.insert_breakpoint(machine, addr);
+                            self.breakpoints.insert(addr, point);
+                        } else {
+                            self.debugger.remove_breakpoint(machine, addr);
+                            self.breakpoints.remove(&addr);
+                        }
+                        "OK".to_string()
+                    }
+                    // watchpoint는 지원하지 않으므로 gdb가 직접 확인함
+                    _ => String::new(),
+                }
+            }
+            // H 뒤에 operation과 thread id가 옴
+            'H' => match args
+                .get(1..)
+                .and_then(|thread| self.parse_thread(machine, thread))
+            {
+                Some(hart) => {
+                    self.hart = hart;
+                    if args.starts_with('c') {
+                        self.debugger.run(machine, Command::Hart(hart)).ok();
+                    }
+                    "OK".to_string()
+                }
+                None => "E01".to_string(),
+            },
+            'T' => match self.parse_thread(machine, args) {
+                Some(_) => "OK".to_string(),
+                None => "E01".to_string(),
+            },
+            'q' => self.query(machine, args),
+            _ => String::new(),
+        }
+    }
+
+    fn query(&self, machine: &Machine, query: &str) -> String {
+        if query.starts_with("Supported") {
+            "PacketSize=4000;swbreak+;hwbreak+".to_string()
+        } else if query == "Attached" {
+            "1".to_string()
+        } else if query == "C" {
+            format!("QC{:x}", self.hart + 1)
+        } else if query == "fThreadInfo" {
+            let threads: Vec<_> = (1..=machine.harts.len())
+                .map(|thread| format!("{:x}", thread))
+                .collect();
+            format!("m{}", threads.join(","))
+        } else if query == "sThreadInfo" {
+            "l".to_string()
+        } else {
+            String::new()
+        }
+    }
+
+    /// Hart of a thread id. 0 and -1 stand for any thread.
+    fn parse_thread(&self, machine: &Machine, thread: &str) -> Option<usize> {
+        match thread {
+            "0" | "-1" => Some(self.hart),
+            _ => match usize::from_str_radix(thread, 16) {
+                Ok(thread) if thread >= 1 && thread <= machine.harts.len() => Some(thread - 1),
+                _ => None,
+            },
+        }
+    }
+
+    fn resume(&mut self, machine: &mut Machine, is_step: bool) -> io::Result<String> {
+        let conn = &mut self.conn;
+        let mut interrupted = Ok(false);
+        let mut is_done = |clocks: usize| {
+            if clocks.is_multiple_of(INTERRUPT_CHECK_CLOCKS) {
+                interrupted = conn.interrupted();
+            }
+            !matches!(interrupted, Ok(false))
+        };
+        let stops = if is_step {
+            self.debugger.step_inst(machine, 1, is_done)
+        } else {
+            self.debugger.resume(machine, |_, clocks| is_done(clocks))
+        };
+        let is_interrupted = interrupted?;
+
+        let stops = match stops {
+            Ok(stops) => stops,
+            // 이미 끝난 program
+            Err(_) => return Ok(self.last_stop.clone()),
+        };
+        let reply = match stops.first() {
+            Some(Stop::Finished(code)) => format!("W{:02x}", code & 0xff),
+            Some(Stop::Breakpoint { hart, pc, .. }) => {
+                self.hart = *hart;
+                let kind = match self.breakpoints.get(pc) {
+                    Some(1) => "hwbreak",
+                    _ => "swbreak",
+                };
+                format!("T05thread:{:x};{}:;", hart + 1, kind)
+            }
+            Some(Stop::Watchpoint { hart, .. }) => format!("T05thread:{:x};", hart + 1),
+            None if is_interrupted => format!("T02thread:{:x};", self.hart + 1),
+            None | Some(Stop::RecordStart) | Some(Stop::Irreversible { .. }) => {
+                format!("T05thread:{:x};", self.hart + 1)
+            }
+        };
+        self.last_stop = reply.clone();
+        Ok(reply)
+    }
+
+    fn read_reg(&self, machine: &Machine, reg: usize) -> u32 {
+        let hart = &machine.harts[self.hart];
+        match reg {
+            PC_REG => hart.next_pc(),
+            _ => hart.reg.gpr[reg].read(),
+        }
+    }
+
+    /// Writes an architectural register. Instructions in flight have read
+    /// the old value, so the hart starts again from the next instruction.
+    fn write_reg(&mut self, machine: &mut Machine, reg: usize, value: u32) {
+        let hart = &mut machine.harts[self.hart];
+        let pc = match reg {
+            PC_REG => value,
+            _ => {
+                hart.reg.gpr[reg].write(value);
+                hart.next_pc()
+            }
+        };
+        self.restart_hart(machine, pc);
+    }
+
+    fn restart_hart(&mut self, machine: &mut Machine, pc: u32) {
+        machine.harts[self.hart].restart_at(pc, &mut machine.mem_sys);
+    }
+
+    fn read_memory(&self, machine: &Machine, addr: u32, len: usize) -> String {
+        let csr = &machine.harts[self.hart].reg.csr;
+        let memory = &machine.mem_sys.memory;
+        let mut reply = String::new();
+        for i in 0..len {
+            let vaddr = addr.wrapping_add(i as u32);
+            let byte = Mmu::translate_now(vaddr, AccessType::Load, csr, memory)
+                .and_then(|paddr| memory.read::<u8>(paddr));
+            match byte {
+                Ok(byte) => reply += &format!("{:02x}", byte),
+                // 읽은 데까지만 돌려줌
+                Err(_) if i > 0 => break,
+                Err(_) => return "E14".to_string(),
+            }
+        }
+        reply
+    }
+
+    /// Writes memory regardless of page permissions. Every hart starts
+    /// again from its next instruction, as it may have fetched or loaded
+    /// the old contents.
+    fn write_memory(&mut self, machine: &mut Machine, addr: u32, bytes: &[u8]) -> String {
+        for (i, &byte) in bytes.iter().enumerate() {
+            let vaddr = addr.wrapping_add(i as u32);
+            let csr = &machine.harts[self.hart].reg.csr;
+            let written = Mmu::translate_now(vaddr, AccessType::Load, csr, &machine.mem_sys.memory)
+                .and_then(|paddr| machine.mem_sys.memory.write(paddr, byte).map(|_| paddr));
+            match written {
+                Ok(paddr) => machine.mem_sys.written_functionally(self.hart, paddr),
+                Err(_) => return "E14".to_string(),
+            }
+        }
+        for hart in machine.harts.iter_mut() {
+            let pc = hart.next_pc();
+            hart.restart_at(pc, &mut machine.mem_sys);
+        }
+        "OK".to_string()
+    }
+
+    /// Reads the next packet, acknowledging it. Returns `None` once gdb has
+    /// closed the connection.
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            // packet 시작까지 ack와 interrupt를 건너뜀
+            match self.read_byte()? {
+                Some(b'$') => {}
+                Some(_) => continue,
+                None => return Ok(None),
+            }
+            let mut data = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    Some(b'#') => break,
+                    Some(byte) => data.push(byte),
+                    None => return Ok(None),
+                }
+            }
+            let mut checksum = [0; 2];
+            self.conn.read_exact(&mut checksum)?;
+            let expected = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|checksum| u8::from_str_radix(checksum, 16).ok());
+            if expected == Some(checksum_of(&data)) {
+                self.conn.write_all(b"+")?;
+                return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+            }
+            self.conn.write_all(b"-")?;
+        }
+    }
+
+    /// Sends a packet until gdb acknowledges it.
+    fn write_packet(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+        loop {
+            self.conn.write_all(packet.as_bytes())?;
+            self.conn.flush()?;
+            match self.read_byte()? {
+                Some(b'-') => continue,
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        let mut byte = [0];
+        match self.conn.read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte))
+}
+
+fn parse_hex(text: &str) -> Option<u32> {
+    u32::from_str_radix(text, 16).ok()
+}
+
+/// `addr,len` in hex.
+fn parse_range(text: &str) -> Option<(u32, usize)> {
+    let mut fields = text.split(',');
+    let addr = fields.next().and_then(parse_hex)?;
+    let len = fields
+        .next()
+        .and_then(|len| usize::from_str_radix(len, 16).ok())?;
+    Some((addr, len))
+}
+
+fn parse_bytes(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| {
+            text.get(i..i + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+        })
+        .collect()
+}
+
+/// A register value, which gdb sends in target byte order.
+fn parse_word(text: &str) -> Option<u32> {
+    parse_hex(text).map(u32::swap_bytes)
+}
+
+fn hex_word(value: u32) -> String {
+    format!("{:08x}", value.swap_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use machine::tests::{with_program, STORE_LOOP};
+    use std::io::Cursor;
+
+    struct Script {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for Script {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Script {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Connection for Script {
+        fn interrupted(&mut self) -> io::Result<bool> {
+            Ok(false)
+        }
+    }
+
+    fn packet(data: &str) -> String {
+        format!("${}#{:02x}", data, checksum_of(data.as_bytes()))
+    }
+
+    /// Sends `packets` and returns how the session ended and the replies.
+    fn replies(machine: &mut Machine, packets: &[&str]) -> (End, Vec<String>) {
+        // 보낸 packet마다 ack를 받음
+        let input: String = packets
+            .iter()
+            .map(|data| format!("{}+", packet(data)))
+            .collect();
+        let mut script = Script {
+            input: Cursor::new(input.into_bytes()),
+            output: Vec::new(),
+        };
+        let end = GdbStub::new(&mut script).serve(machine).unwrap();
+
+        let output = String::from_utf8(script.output).unwrap();
+        let replies = output
+            .split('$')
+            .skip(1)
+            .map(|reply| reply.split('#').next().unwrap().to_string())
+            .collect();
+        (end, replies)
+    }
+
+    #[test]
+    fn session() {
+        let mut machine = with_program(&STORE_LOOP, Default::default());
+
+        let packets = [
+            "qSupported:swbreak+",
+            "?",
+            "Z0,8,4",
+            "c",
+            "pa",
+            "p20",
+            "c",
+            "pa",
+            "z0,8,4",
+            "s",
+            "s",
+            "p20",
+            "m100,4",
+            "Pa=05000000",
+            "s",
+            "s",
+            "m100,4",
+            "M104,2:abcd",
+            "m104,4",
+            "m1000,4",
+            "k",
+        ];
+        let (end, replies) = replies(&mut machine, &packets);
+        assert_eq!(end, End::Killed);
+        assert_eq!(
+            replies,
+            vec![
+                "PacketSize=4000;swbreak+;hwbreak+",
+                "S05",
+                "OK",
+                "T05thread:1;swbreak:;",
+                "00000000",
+                "08000000",
+                "T05thread:1;swbreak:;",
+                "01000000",
+                "OK",
+                "T05thread:1;",
+                "T05thread:1;",
+                "10000000",
+                "02000000",
+                "OK",
+                "T05thread:1;",
+                "T05thread:1;",
+                "02000000",
+                "OK",
+                "abcd0000",
+                "E14",
+            ]
+        );
+    }
+
+    #[test]
+    fn interrupt_leaves_other_bytes() {
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut gdb = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut stub, _) = listener.accept().unwrap();
+        let timeout = Some(std::time::Duration::from_secs(5));
+        stub.set_read_timeout(timeout).unwrap();
+        assert!(!stub.interrupted().unwrap());
+
+        // byte가 도착할 때까지 기다림
+        gdb.write_all(b"$").unwrap();
+        let mut byte = [0];
+        stub.peek(&mut byte).unwrap();
+        assert!(!stub.interrupted().unwrap());
+        stub.read_exact(&mut byte).unwrap();
+        assert_eq!(&byte, b"$");
+
+        gdb.write_all(&[0x03]).unwrap();
+        while !stub.interrupted().unwrap() {}
+        gdb.write_all(b"+").unwrap();
+        stub.read_exact(&mut byte).unwrap();
+        assert_eq!(&byte, b"+");
+    }
+
+    #[test]
+    fn malformed_packets() {
+        let mut machine = with_program(&STORE_LOOP, Default::default());
+        let packets = [
+            "",
+            "H",
+            "Hg",
+            "\u{e9}",
+            "M100,2:a\u{20ac}",
+            "p",
+            "m100",
+            "k",
+        ];
+        let (end, replies) = replies(&mut machine, &packets);
+        assert_eq!(end, End::Killed);
+        assert_eq!(replies, vec!["", "E01", "E01", "", "E01", "E01", "E01"]);
+    }
+}
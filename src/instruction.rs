@@ -22,6 +22,8 @@ pub struct Instruction {
 
 impl Instruction {
     /// Constructs a new `Instruction`.
+    ///
+    /// Panics if `value` isn't a supported instruction.
     pub fn new(value: u32) -> Instruction {
         // convert unnecessary instruction to NOP
         if let 0x003027f3 | 0x00351073 = value {
@@ -41,9 +43,23 @@ impl Instruction {
         }
     }
 
+    /// Constructs a new `Instruction`, or returns `None` if `value` can't be
+    /// decoded.
+    pub fn decode(value: u32) -> Option<Instruction> {
+        let opcode = Opcode::decode(value)?;
+        let fields = Fields::new(value, opcode.into(), opcode);
+        Function::decode(value, &fields, opcode)?;
+        Some(Instruction::new(value))
+    }
+
     pub fn is_nop(&self) -> bool {
         self.value == consts::NOP
     }
+
+    /// Address of the CSR accessed by a Zicsr instruction.
+    pub fn csr_addr(&self) -> u16 {
+        (self.fields.imm.unwrap_or(0) & 0xfff) as u16
+    }
 }
 
 impl Default for Instruction {
@@ -53,6 +69,54 @@ impl Default for Instruction {
     }
 }
 
+/// ABI names of the integer registers.
+pub const REG_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+
+impl std::fmt::Display for Instruction {
+    /// Disassembles the instruction in assembler syntax with ABI register
+    /// names, e.g. `addi a0, sp, -16`. Jump and branch targets are written
+    /// as offsets from the instruction.
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.is_nop() {
+            return write!(f, "nop");
+        }
+        let fields = &self.fields;
+        let reg = |r: Option<u8>| REG_NAMES[r.unwrap_or(0) as usize];
+        let (rd, rs1, rs2) = (reg(fields.rd), reg(fields.rs1), reg(fields.rs2));
+        let imm = fields.imm.unwrap_or(0) as i32;
+
+        write!(f, "{}", self.function.mnemonic())?;
+        match self.opcode {
+            Opcode::Lui | Opcode::AuiPc => write!(f, " {}, {:#x}", rd, imm as u32 >> 12),
+            Opcode::Jal => write!(f, " {}, {}", rd, imm),
+            Opcode::Jalr | Opcode::Load => write!(f, " {}, {}({})", rd, imm, rs1),
+            Opcode::Branch => write!(f, " {}, {}, {}", rs1, rs2, imm),
+            Opcode::Store => write!(f, " {}, {}({})", rs2, imm, rs1),
+            Opcode::OpImm => write!(f, " {}, {}, {}", rd, rs1, imm),
+            Opcode::Op => write!(f, " {}, {}, {}", rd, rs1, rs2),
+            Opcode::Amo => match self.function {
+                Function::Lrw => write!(f, " {}, ({})", rd, rs1),
+                _ => write!(f, " {}, {}, ({})", rd, rs2, rs1),
+            },
+            Opcode::System => match self.function {
+                Function::Csrrw | Function::Csrrs | Function::Csrrc => {
+                    write!(f, " {}, {:#x}, {}", rd, self.csr_addr(), rs1)
+                }
+                Function::Csrrwi | Function::Csrrsi | Function::Csrrci => {
+                    write!(f, " {}, {:#x}, {}", rd, self.csr_addr(), fields.rs1.unwrap_or(0))
+                }
+                Function::SfenceVma => write!(f, " {}, {}", rs1, rs2),
+                _ => Ok(()),
+            },
+            _ => Ok(()),
+        }
+    }
+}
+
 /// RISC-V 32I fields (shamt -> imm).
 #[derive(Clone, Debug, Default, PartialEq)]
 pub struct Fields {
@@ -187,7 +251,7 @@ impl Fields {
 }
 
 /// RISC-V 32I opcodes.
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum Opcode {
     Lui,
     AuiPc,
@@ -212,8 +276,16 @@ pub enum Opcode {
 
 impl From<u32> for Opcode {
     fn from(val: u32) -> Self {
+        Opcode::decode(val)
+            .unwrap_or_else(|| panic!("Unknown opcode {:#09b}", val & consts::OPCODE_MASK))
+    }
+}
+
+impl Opcode {
+    /// Decodes the opcode of an instruction, or `None` if it's unknown.
+    pub fn decode(val: u32) -> Option<Self> {
         let opcode = val & consts::OPCODE_MASK;
-        match opcode {
+        let opcode = match opcode {
             0b01_101_11 => Opcode::Lui,
             0b00_101_11 => Opcode::AuiPc,
             0b11_011_11 => Opcode::Jal,
@@ -233,8 +305,9 @@ impl From<u32> for Opcode {
             0b10_010_11 => Opcode::Fnmsub,
             0b10_011_11 => Opcode::Fnmadd,
             0b10_100_11 => Opcode::OpFp,
-            _ => panic!("Unknown opcode {:#09b}", opcode),
-        }
+            _ => return None,
+        };
+        Some(opcode)
     }
 }
 
@@ -272,7 +345,7 @@ impl From<Opcode> for Format {
 }
 
 /// RISC-V 32I mnemonics.
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum Function {
     /// Load upper immediate
     Lui,
@@ -358,6 +431,26 @@ pub enum Function {
     Fencei,
     Ecall,
     Ebreak,
+    /// Atomic read/write CSR
+    Csrrw,
+    /// Atomic read and set bits in CSR
+    Csrrs,
+    /// Atomic read and clear bits in CSR
+    Csrrc,
+    /// Atomic read/write CSR with immediate
+    Csrrwi,
+    /// Atomic read and set bits in CSR with immediate
+    Csrrsi,
+    /// Atomic read and clear bits in CSR with immediate
+    Csrrci,
+    /// Supervisor memory-management fence
+    SfenceVma,
+    /// Return from M-mode trap
+    Mret,
+    /// Return from S-mode trap
+    Sret,
+    /// Wait for interrupt, executed as a NOP
+    Wfi,
     Mul,
     Mulh,
     Mulhsu,
@@ -406,9 +499,39 @@ pub enum Function {
 }
 
 impl Function {
+    /// Assembler mnemonic, e.g. `amoadd.w` for `Amoaddw`.
+    pub fn mnemonic(self) -> String {
+        let name = format!("{:?}", self).to_lowercase();
+        match self {
+            Function::Fencei => "fence.i".to_string(),
+            Function::SfenceVma => "sfence.vma".to_string(),
+            Function::Lrw | Function::Scw => format!("{}.w", &name[..2]),
+            Function::Amoswapw
+            | Function::Amoaddw
+            | Function::Amoxorw
+            | Function::Amoandw
+            | Function::Amoorw
+            | Function::Amominw
+            | Function::Amomaxw
+            | Function::Amominuw
+            | Function::Amomaxuw => format!("{}.w", &name[..name.len() - 1]),
+            _ => name,
+        }
+    }
+
     pub fn new(inst: u32, fields: &Fields, opcode: Opcode) -> Function {
+        Function::decode(inst, fields, opcode).unwrap_or_else(|| {
+            panic!(
+                "Failed to decode instruction {:#0x}, fields: {:x?}",
+                inst, fields
+            )
+        })
+    }
+
+    /// Decodes the mnemonic, or returns `None` for an unknown encoding.
+    pub fn decode(inst: u32, fields: &Fields, opcode: Opcode) -> Option<Function> {
         // Check opcode-only functions
-        match opcode {
+        let function = match opcode {
             Opcode::Lui => Function::Lui,
             Opcode::AuiPc => Function::AuiPc,
             Opcode::Jal => Function::Jal,
@@ -466,7 +589,21 @@ impl Function {
                     (Opcode::MiscMem, Some(0b000), _) => Function::Fence,
                     (Opcode::MiscMem, Some(0b001), _) => Function::Fencei,
                     (Opcode::System, Some(0b0), _) if fields.imm == Some(1) => Function::Ebreak,
+                    (Opcode::System, Some(0b0), _)
+                        if (inst & consts::FUNCT7_MASK) >> consts::FUNCT7_SHIFT == 0b000_1001 =>
+                    {
+                        Function::SfenceVma
+                    }
+                    (Opcode::System, Some(0b0), _) if fields.imm == Some(0x302) => Function::Mret,
+                    (Opcode::System, Some(0b0), _) if fields.imm == Some(0x102) => Function::Sret,
+                    (Opcode::System, Some(0b0), _) if fields.imm == Some(0x105) => Function::Wfi,
                     (Opcode::System, Some(0b0), _) => Function::Ecall,
+                    (Opcode::System, Some(0b001), _) => Function::Csrrw,
+                    (Opcode::System, Some(0b010), _) => Function::Csrrs,
+                    (Opcode::System, Some(0b011), _) => Function::Csrrc,
+                    (Opcode::System, Some(0b101), _) => Function::Csrrwi,
+                    (Opcode::System, Some(0b110), _) => Function::Csrrsi,
+                    (Opcode::System, Some(0b111), _) => Function::Csrrci,
                     (Opcode::Op, Some(0b000), Some(0b1)) => Function::Mul,
                     (Opcode::Op, Some(0b001), Some(0b1)) => Function::Mulh,
                     (Opcode::Op, Some(0b010), Some(0b1)) => Function::Mulhsu,
@@ -536,13 +673,11 @@ impl Function {
                     (Opcode::OpFp, Some(0b000), Some(0b111_1000)) if fields.rs2 == Some(0b0) => {
                         Function::Fmvwx
                     }
-                    _ => panic!(
-                        "Failed to decode instruction {:#0x}, fields: {:x?}",
-                        inst, fields
-                    ),
+                    _ => return None,
                 }
             }
-        }
+        };
+        Some(function)
     }
 }
 
@@ -560,4 +695,27 @@ mod tests {
         assert_eq!(insn.fields.imm, Some(0));
     }
 
+    /// Garbage words decode to `None` instead of panicking
+    #[test]
+    fn decode_invalid() {
+        assert!(Instruction::decode(0x0).is_none());
+        assert!(Instruction::decode(0xffff_ffff).is_none());
+        assert_eq!(
+            Instruction::decode(consts::NOP).map(|insn| insn.function),
+            Some(Function::Addi)
+        );
+    }
+
+    #[test]
+    fn disassembly() {
+        let text = |value| Instruction::new(value).to_string();
+        assert_eq!(text(consts::NOP), "nop");
+        assert_eq!(text(0xff01_0513), "addi a0, sp, -16");
+        assert_eq!(text(0x00a5_81a3), "sb a0, 3(a1)");
+        assert_eq!(text(0xfe20_8ee3), "beq ra, sp, -4");
+        assert_eq!(text(0x0000_12b7), "lui t0, 0x1");
+        assert_eq!(text(0x00a5_a62f), "amoadd.w a2, a0, (a1)");
+        assert_eq!(text(0x1005_a52f), "lr.w a0, (a1)");
+        assert_eq!(text(0x3420_2a73), "csrrs s4, 0x342, zero");
+    }
 }
@@ -0,0 +1,180 @@
+//! Kanata logs for the Konata pipeline viewer.
+//!
+//! A Kanata log is a list of commands grouped by clock: `I` introduces an
+//! instruction, `L` labels it, `S` and `E` start and end its stages and `R`
+//! retires or flushes it. Records arrive when instructions leave the pipeline,
+//! which is not in fetch order. The writer holds them until every instruction
+//! fetched up to a clock has left, then numbers them in fetch order and writes
+//! out the clocks that can no longer change.
+
+use pipeline::lifecycle::InstRecord;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+const RETIRED: u32 = 0;
+const FLUSHED: u32 = 1;
+
+pub struct KanataWriter<W: Write> {
+    out: W,
+    /// Records without an id yet, by fetch clock.
+    pending: BTreeMap<usize, Vec<InstRecord>>,
+    /// Commands of the records with an id, by clock.
+    commands: BTreeMap<usize, Vec<String>>,
+    next_id: usize,
+    next_retire_id: usize,
+    /// Clock of the commands written last.
+    clock: Option<usize>,
+}
+
+impl<W: Write> KanataWriter<W> {
+    pub fn new(mut out: W) -> io::Result<Self> {
+        writeln!(out, "Kanata\t0004")?;
+        Ok(KanataWriter {
+            out,
+            pending: BTreeMap::new(),
+            commands: BTreeMap::new(),
+            next_id: 0,
+            next_retire_id: 0,
+            clock: None,
+        })
+    }
+
+    pub fn record(&mut self, record: InstRecord) {
+        self.pending
+            .entry(record.times.fetched)
+            .or_default()
+            .push(record);
+    }
+
+    /// Writes every clock before `clock`. Instructions fetched before it
+    /// must all have been recorded.
+    pub fn advance(&mut self, clock: usize) -> io::Result<()> {
+        let later = self.pending.split_off(&clock);
+        for (_, mut records) in std::mem::replace(&mut self.pending, later) {
+            records.sort_by_key(|record| (record.hart, record.times.seq));
+            for record in records {
+                self.number(record);
+            }
+        }
+
+        let later = self.commands.split_off(&clock);
+        for (clock, commands) in std::mem::replace(&mut self.commands, later) {
+            match self.clock {
+                None => writeln!(self.out, "C=\t{}", clock)?,
+                Some(last) => writeln!(self.out, "C\t{}", clock - last)?,
+            }
+            self.clock = Some(clock);
+            for command in commands {
+                writeln!(self.out, "{}", command)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes out everything recorded and returns the output.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.advance(usize::MAX)?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    /// Gives `record` the next id and turns it into commands.
+    fn number(&mut self, record: InstRecord) {
+        let id = self.next_id;
+        self.next_id += 1;
+        let times = &record.times;
+        self.push(
+            times.fetched,
+            format!("I\t{}\t{}\t{}", id, times.seq, record.hart),
+        );
+        self.push(
+            times.fetched,
+            format!(
+                "L\t{}\t0\t{:08x}: {:?} {}",
+                id, record.pc, record.inst.function, record.inst.fields
+            ),
+        );
+
+        // 기록되지 않은 단계는 건너뛰고, 길이가 0인 단계는 그리지 않음
+        let starts: Vec<_> = [
+            ("F", Some(times.fetched)),
+            ("Is", times.issued),
+            ("Rdy", times.ready),
+            ("Ex", times.executed),
+            ("Cm", times.completed),
+        ]
+        .iter()
+        .filter_map(|&(stage, start)| start.map(|start| (stage, start)))
+        .collect();
+        for (i, &(stage, start)) in starts.iter().enumerate() {
+            let end = starts.get(i + 1).map_or(record.ended, |&(_, next)| next);
+            if start < end {
+                self.push(start, format!("S\t{}\t0\t{}", id, stage));
+                self.push(end, format!("E\t{}\t0\t{}", id, stage));
+            }
+        }
+
+        let command = if record.flushed {
+            format!("R\t{}\t0\t{}", id, FLUSHED)
+        } else {
+            self.next_retire_id += 1;
+            format!("R\t{}\t{}\t{}", id, self.next_retire_id - 1, RETIRED)
+        };
+        self.push(record.ended, command);
+    }
+
+    fn push(&mut self, clock: usize, command: String) {
+        self.commands.entry(clock).or_default().push(command);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use instruction::Instruction;
+    use pipeline::lifecycle::Timestamps;
+
+    fn record(seq: usize, fetched: usize, ended: usize, flushed: bool) -> InstRecord {
+        InstRecord {
+            hart: 0,
+            pc: 0x100 + 4 * seq as u32,
+            inst: Instruction::default(),
+            times: Timestamps {
+                seq,
+                fetched,
+                issued: Some(fetched + 2),
+                ready: Some(fetched + 2),
+                executed: Some(fetched + 2),
+                completed: if flushed { None } else { Some(fetched + 4) },
+            },
+            ended,
+            flushed,
+        }
+    }
+
+    #[test]
+    fn records_are_written_in_fetch_order() {
+        let mut kanata = KanataWriter::new(Vec::new()).unwrap();
+        // 늦게 fetch된 명령어가 먼저 flush됨
+        kanata.record(record(1, 2, 6, true));
+        kanata.advance(2).unwrap();
+        kanata.record(record(0, 1, 7, false));
+        let log = String::from_utf8(kanata.finish().unwrap()).unwrap();
+        let lines: Vec<_> = log.lines().collect();
+
+        assert_eq!(lines[0], "Kanata\t0004");
+        assert_eq!(lines[1], "C=\t1");
+        assert_eq!(lines[2], "I\t0\t0\t0");
+        assert!(lines.contains(&"I\t1\t1\t0"));
+        assert!(lines.contains(&"R\t0\t0\t0"));
+        assert!(lines.contains(&"R\t1\t0\t1"));
+        // ready와 execute 시작이 같은 clock이면 Rdy 단계는 생략
+        assert!(!log.contains("Rdy"));
+        let clocks: usize = lines
+            .iter()
+            .filter_map(|line| line.strip_prefix("C\t"))
+            .map(|delta| delta.parse::<usize>().unwrap())
+            .sum();
+        assert_eq!(clocks, 6);
+    }
+}
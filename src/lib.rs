@@ -1,11 +1,23 @@
 //! Simulator components for RISC-V 32I instruction set.
 
 pub mod alu;
+pub mod checkpoint;
+pub mod commit_diff;
+pub mod commit_log;
 pub mod consts;
+pub mod csr;
+pub mod debugger;
+pub mod diagram;
+pub mod gdb;
 pub mod instruction;
+pub mod kanata;
+pub mod machine;
 pub mod memory;
 pub mod pipeline;
 pub mod register;
+pub mod report;
+pub mod simpoint;
+pub mod vcd;
 
 extern crate byteorder;
 extern crate goblin;
@@ -0,0 +1,232 @@
+//! A machine of one or more harts sharing a memory system.
+
+use consts;
+use instruction::Instruction;
+use memory::system::MemorySystem;
+use memory::ProcessMemory;
+use pipeline::journal::UndoRecord;
+use pipeline::lifecycle::InstRecord;
+use pipeline::reorder_buffer::ReorderBufferEntry;
+use pipeline::thread::{ThreadContext, ThreadTable};
+use pipeline::{Pipeline, PipelineConfig};
+
+/// Harts stepping in lockstep over a shared memory system.
+#[derive(Debug)]
+pub struct Machine {
+    pub harts: Vec<Pipeline>,
+    pub mem_sys: MemorySystem,
+    pub threads: ThreadTable,
+    pub clock: usize,
+    /// Clock detailed simulation started from, after a restored checkpoint
+    /// or fast-forwarding.
+    pub start_clock: usize,
+}
+
+impl Machine {
+    /// Constructs a machine running the program's main thread on hart 0.
+    /// The other harts wait for threads created by `clone`, unless
+    /// `boot_all_harts` starts each of them at `entry_point` too, with the
+    /// same stack pointer and its hart id in `a0`.
+    pub fn new(
+        entry_point: u32,
+        memory: ProcessMemory,
+        hart_count: usize,
+        boot_all_harts: bool,
+        config: PipelineConfig,
+    ) -> Machine {
+        let stack_pointer = memory.stack_pointer_init;
+        let mut harts: Vec<_> = (0..hart_count)
+            .map(|hart_id| Pipeline::new(hart_id, config.clone()))
+            .collect();
+        let mut threads = ThreadTable::new(nix::unistd::getpid().as_raw() as u32);
+        let booted = if boot_all_harts { hart_count } else { 1 };
+        for hart in harts.iter_mut().take(booted) {
+            let mut gpr = [0; 32];
+            gpr[2] = stack_pointer;
+            gpr[consts::SYSCALL_ARG1_REG] = hart.hart_id as u32;
+            hart.resume(ThreadContext {
+                tid: threads.new_tid(),
+                pc: entry_point,
+                gpr,
+                clear_child_tid: 0,
+            });
+        }
+
+        Machine {
+            harts,
+            mem_sys: MemorySystem::new(
+                memory,
+                hart_count,
+                config.dram,
+                config.l1d,
+                config.clint,
+            ),
+            threads,
+            clock: 0,
+            start_clock: 0,
+        }
+    }
+
+    /// Runs every hart for one clock. Returns what each hart retired, and
+    /// true once any hart has ended the program.
+    pub fn run_clock(&mut self) -> (Vec<Vec<(usize, ReorderBufferEntry)>>, bool) {
+        self.clock += 1;
+        let arrived = self.mem_sys.tick(self.clock);
+        let mem_sys = &mut self.mem_sys;
+        let threads = &mut self.threads;
+        let mut is_finished = false;
+        let retired = self
+            .harts
+            .iter_mut()
+            .zip(arrived.iter())
+            .map(|(hart, arrived)| {
+                let (retired, hart_finished) = hart.run_clock(mem_sys, threads, arrived);
+                is_finished |= hart_finished;
+                retired
+            })
+            .collect();
+        (retired, self.end_clock(is_finished))
+    }
+
+    /// Runs one instruction on every hart functionally. The pipelines must
+    /// be drained. Returns the PC and instruction each hart ran, and true
+    /// once any hart has ended the program.
+    pub fn step(&mut self) -> (Vec<Option<(u32, Instruction)>>, bool) {
+        self.clock += 1;
+        self.mem_sys.tick(self.clock);
+        let mem_sys = &mut self.mem_sys;
+        let threads = &mut self.threads;
+        let executed = self
+            .harts
+            .iter_mut()
+            .map(|hart| hart.step(mem_sys, threads))
+            .collect();
+        let is_finished = self.threads.is_finished();
+        (executed, self.end_clock(is_finished))
+    }
+
+    /// Runs at least `count` instructions functionally, or until the
+    /// program ends. Returns the number run and true if the program ended.
+    pub fn fast_forward(&mut self, count: usize) -> (usize, bool) {
+        let mut executed = 0;
+        let mut is_finished = false;
+        while executed < count && !is_finished {
+            let (steps, finished) = self.step();
+            executed += steps.iter().filter(|step| step.is_some()).count();
+            is_finished = finished;
+        }
+        for hart in self.harts.iter_mut() {
+            hart.reg.sync_phys_regs();
+        }
+        (executed, is_finished)
+    }
+
+    /// Puts ready threads on idle harts and checks how the program ended.
+    fn end_clock(&mut self, mut is_finished: bool) -> bool {
+        for hart in self.harts.iter_mut().filter(|hart| hart.tid.is_none()) {
+            match self.threads.next_ready() {
+                Some(thread) => hart.resume(thread),
+                None => break,
+            }
+        }
+        // HTIF로 보낸 종료 요청
+        let htif_exit = self.mem_sys.htif.as_ref().and_then(|htif| htif.exit_code());
+        if let Some(code) = htif_exit {
+            self.threads.exit_group(code);
+            is_finished = true;
+        }
+        if self.threads.is_deadlocked() {
+            panic!("Every thread is waiting on a futex");
+        }
+        is_finished
+    }
+
+    /// Stops or resumes fetching on every hart, to reach a point with no
+    /// instruction in flight.
+    pub fn drain(&mut self, draining: bool) {
+        for hart in self.harts.iter_mut() {
+            hart.draining = draining;
+        }
+    }
+
+    pub fn is_drained(&self) -> bool {
+        self.harts.iter().all(|hart| hart.is_drained())
+    }
+
+    /// Starts or stops keeping an undo journal on every hart.
+    pub fn record(&mut self, is_recording: bool) {
+        for hart in self.harts.iter_mut() {
+            hart.journal = if is_recording { Some(Vec::new()) } else { None };
+        }
+    }
+
+    /// Takes the undo records kept since the last call, hart by hart.
+    pub fn take_journal(&mut self) -> Vec<UndoRecord> {
+        let mut records = Vec::new();
+        for hart in self.harts.iter_mut() {
+            if let Some(hart_records) = hart.journal.as_mut() {
+                records.append(hart_records);
+            }
+        }
+        records
+    }
+
+    /// Takes the lifecycle records kept since the last call, along with the
+    /// clock before which every fetched instruction has been recorded.
+    pub fn take_lifecycle(&mut self) -> (Vec<InstRecord>, usize) {
+        let mut records = Vec::new();
+        let mut settled = self.clock + 1;
+        for hart in self.harts.iter_mut() {
+            if let Some(hart_records) = hart.lifecycle.as_mut() {
+                records.append(hart_records);
+            }
+            if let Some(fetched) = hart.oldest_fetched() {
+                settled = std::cmp::min(settled, fetched);
+            }
+        }
+        (records, settled)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// `li a0, 0; li a1, 0x100; loop: addi a0, a0, 1; sw a0, 0(a1); j loop`
+    pub const STORE_LOOP: [u32; 5] = [
+        0x0000_0513,
+        0x1000_0593,
+        0x0015_0513,
+        0x00a5_a023,
+        0xff9f_f06f,
+    ];
+
+    /// A machine with one hart running `program` from address 0, in 0x200
+    /// bytes of memory.
+    pub fn with_program(program: &[u32], config: PipelineConfig) -> Machine {
+        let mut memory = ProcessMemory::default();
+        memory.data.resize(0x200, 0);
+        memory.v_address_range = (0, 0x200);
+        memory.stack_range = (0xffff_0000, 0);
+        memory.write_slice(0, program).unwrap();
+        Machine::new(0, memory, 1, true, config)
+    }
+
+    #[test]
+    fn amo_is_atomic_across_harts() {
+        // li t0, 1; lui t2, 1; amoadd.w zero, t0, (t2); j .
+        let program = [0x0010_0293u32, 0x0000_13b7, 0x0053_a02f, 0x0000_006f];
+        let mut memory = ProcessMemory::default();
+        memory.data.resize(0x2000, 0);
+        memory.v_address_range = (0, 0x2000);
+        memory.stack_range = (0xffff_0000, 0);
+        memory.write_slice(0, &program).unwrap();
+
+        let mut machine = Machine::new(0, memory, 4, true, Default::default());
+        for _ in 0..500 {
+            machine.run_clock();
+        }
+        assert_eq!(machine.mem_sys.memory.read::<u32>(0x1000).unwrap(), 4);
+        assert_eq!(machine.mem_sys.stats.bus_read_exclusives, 4);
+    }
+}
@@ -5,10 +5,30 @@ extern crate riscv_5stage_simulator;
 extern crate structopt;
 
 use lazy_static::lazy_static;
+use riscv_5stage_simulator::checkpoint;
+use riscv_5stage_simulator::commit_diff;
+use riscv_5stage_simulator::commit_log::CommitLogWriter;
+use riscv_5stage_simulator::debugger::{self, Command, Debugger};
+use riscv_5stage_simulator::diagram::{PcRange, PipelineDiagram};
+use riscv_5stage_simulator::gdb::{self, GdbStub};
+use riscv_5stage_simulator::kanata::KanataWriter;
+use riscv_5stage_simulator::machine::Machine;
+use riscv_5stage_simulator::memory::cache::CacheConfig;
+use riscv_5stage_simulator::memory::clint::ClintConfig;
+use riscv_5stage_simulator::memory::dram::{DramConfig, PagePolicy};
+use riscv_5stage_simulator::memory::htif::Htif;
+use riscv_5stage_simulator::memory::mmu::MmuConfig;
+use riscv_5stage_simulator::memory::uart::{Uart, UART_BASE, UART_SIZE};
 use riscv_5stage_simulator::memory::ProcessMemory;
-use riscv_5stage_simulator::pipeline::Pipeline;
+use riscv_5stage_simulator::pipeline::fetch::FetchConfig;
+use riscv_5stage_simulator::pipeline::rename::{RenameConfig, RenameModel};
+use riscv_5stage_simulator::pipeline::PipelineConfig;
+use riscv_5stage_simulator::report::{Report, StatsFormat};
+use riscv_5stage_simulator::simpoint::{self, BbvWriter, SimPoint};
+use riscv_5stage_simulator::vcd::{SignalFilter, VcdWriter};
 use std::fs::File;
 use std::io::prelude::*;
+use std::io::{BufReader, BufWriter};
 use std::path::PathBuf;
 use structopt::StructOpt;
 
@@ -23,6 +43,152 @@ struct Opt {
     #[structopt(long = "print-debug-info")]
     /// Prints informations for debugging
     print_debug_info: bool,
+    #[structopt(long = "harts", default_value = "1")]
+    /// Number of harts sharing the memory system
+    harts: usize,
+    #[structopt(long = "boot-all-harts")]
+    /// Starts every hart at the entry point instead of leaving the others to threads created by clone
+    boot_all_harts: bool,
+    #[structopt(long = "full-system")]
+    /// Boots in M-mode, lets ecall trap to the guest's handler instead of the host and maps the UART at 0x10000000
+    full_system: bool,
+    #[structopt(long = "dram-channels", default_value = "1")]
+    /// Number of DRAM channels
+    dram_channels: usize,
+    #[structopt(long = "dram-banks", default_value = "8")]
+    /// Number of banks per DRAM channel
+    dram_banks: usize,
+    #[structopt(long = "dram-row-size", default_value = "2048")]
+    /// Size of a DRAM row buffer in bytes
+    dram_row_size: usize,
+    #[structopt(long = "dram-page-policy", default_value = "open")]
+    /// Row buffer policy, open or closed
+    dram_page_policy: PagePolicy,
+    #[structopt(long = "dram-trcd", default_value = "14")]
+    /// Activate to column command delay in clocks
+    dram_t_rcd: usize,
+    #[structopt(long = "dram-tcas", default_value = "14")]
+    /// Column command to data delay in clocks
+    dram_t_cas: usize,
+    #[structopt(long = "dram-trp", default_value = "14")]
+    /// Precharge delay in clocks
+    dram_t_rp: usize,
+    #[structopt(long = "l1d-size", default_value = "32768")]
+    /// Size of each hart's L1 data cache in bytes
+    l1d_size: usize,
+    #[structopt(long = "l1d-ways", default_value = "4")]
+    /// Associativity of the L1 data cache
+    l1d_ways: usize,
+    #[structopt(long = "l1d-line-size", default_value = "64")]
+    /// Size of an L1 cache line in bytes
+    l1d_line_size: usize,
+    #[structopt(long = "l1d-latency", default_value = "2")]
+    /// L1 data cache hit latency in clocks
+    l1d_latency: usize,
+    #[structopt(long = "bus-latency", default_value = "10")]
+    /// Extra clocks for an upgrade or a cache-to-cache transfer
+    bus_latency: usize,
+    #[structopt(long = "mtime-divider", default_value = "10")]
+    /// Clocks per increment of the CLINT's mtime
+    mtime_divider: usize,
+    #[structopt(long = "itlb-entries", default_value = "32")]
+    /// Number of instruction TLB entries
+    itlb_entries: usize,
+    #[structopt(long = "dtlb-entries", default_value = "32")]
+    /// Number of data TLB entries
+    dtlb_entries: usize,
+    #[structopt(long = "fetch-width", default_value = "2")]
+    /// Number of instructions fetched per clock
+    fetch_width: usize,
+    #[structopt(long = "fetch-queue-size", default_value = "8")]
+    /// Capacity of the fetch queue
+    fetch_queue_size: usize,
+    #[structopt(long = "fetch-depth", default_value = "2")]
+    /// Clocks between fetching an instruction and issuing it
+    fetch_depth: usize,
+    #[structopt(long = "rename-model", default_value = "rob")]
+    /// Register renaming through the ROB (rob) or a physical register file (prf)
+    rename_model: RenameModel,
+    #[structopt(long = "phys-regs", default_value = "64")]
+    /// Number of physical registers in the prf rename model
+    phys_regs: usize,
+    #[structopt(long = "kanata", parse(from_os_str))]
+    /// Writes the lifecycle of every instruction as a Kanata log for the Konata viewer
+    kanata: Option<PathBuf>,
+    #[structopt(long = "pipeline-diagram")]
+    /// Prints a pipeline diagram of the first N retired instructions
+    pipeline_diagram: Option<usize>,
+    #[structopt(long = "pipeline-diagram-pc", requires = "pipeline_diagram")]
+    /// Only shows instructions at addresses in start:end (hex) in the pipeline diagram
+    pipeline_diagram_pc: Option<PcRange>,
+    #[structopt(long = "vcd", parse(from_os_str))]
+    /// Writes a Value Change Dump of the PC, registers and pipeline occupancy of every hart
+    vcd: Option<PathBuf>,
+    #[structopt(long = "vcd-signals")]
+    /// Signals to dump, separated by commas, e.g. pc,x1*,rob_* (default: all)
+    vcd_signals: Option<SignalFilter>,
+    #[structopt(long = "commit-log", parse(from_os_str))]
+    /// Writes every retired instruction in the format of Spike's --log-commits
+    commit_log: Option<PathBuf>,
+    #[structopt(long = "stats-format", default_value = "text")]
+    /// Format of the statistics printed at the end of the run, text, json or csv
+    stats_format: StatsFormat,
+    #[structopt(long = "stats-file", parse(from_os_str))]
+    /// Writes the statistics to a file instead of stderr
+    stats_file: Option<PathBuf>,
+    #[structopt(long = "checkpoint-at-cycle")]
+    /// Takes a checkpoint once the clock reaches N
+    checkpoint_at_cycle: Option<usize>,
+    #[structopt(long = "checkpoint-at-inst")]
+    /// Takes a checkpoint once N instructions have retired
+    checkpoint_at_inst: Option<usize>,
+    #[structopt(
+        long = "checkpoint-file",
+        default_value = "casim.ckpt",
+        parse(from_os_str)
+    )]
+    /// File the checkpoint is written to
+    checkpoint_file: PathBuf,
+    #[structopt(long = "restore", parse(from_os_str))]
+    /// Starts from a checkpoint taken with the same program and options
+    restore: Option<PathBuf>,
+    #[structopt(long = "fast-forward")]
+    /// Runs N instructions functionally before simulating in detail
+    fast_forward: Option<usize>,
+    #[structopt(long = "bbv", parse(from_os_str))]
+    /// Runs the whole program functionally and writes a basic block vector per interval for SimPoint
+    bbv: Option<PathBuf>,
+    #[structopt(long = "interval", default_value = "10000000")]
+    /// Instructions per basic block vector and per simulation point
+    interval: usize,
+    #[structopt(long = "simpoints", parse(from_os_str), requires = "simpoint_weights")]
+    /// Simulates only the intervals SimPoint chose and reports their weighted IPC
+    simpoints: Option<PathBuf>,
+    #[structopt(long = "simpoint-weights", parse(from_os_str))]
+    /// Weights of the simulation points
+    simpoint_weights: Option<PathBuf>,
+    #[structopt(long = "warmup", default_value = "0")]
+    /// Instructions simulated in detail before each simulation point
+    warmup: usize,
+    #[structopt(long = "debug")]
+    /// Runs the program under an interactive debugger
+    debug: bool,
+    #[structopt(long = "gdb-port")]
+    /// Waits for gdb to connect on a localhost port and runs the program under it
+    gdb_port: Option<u16>,
+}
+
+/// casim diff: compares two commit logs
+#[derive(StructOpt, Debug)]
+#[structopt(name = "casim diff")]
+struct DiffOpt {
+    #[structopt(parse(from_os_str))]
+    left: PathBuf,
+    #[structopt(parse(from_os_str))]
+    right: PathBuf,
+    #[structopt(long = "context", default_value = "10")]
+    /// Number of instructions to show before the divergence
+    context: usize,
 }
 
 lazy_static! {
@@ -30,25 +196,356 @@ lazy_static! {
 }
 
 fn main() {
+    if std::env::args().nth(1).as_deref() == Some("diff") {
+        diff(DiffOpt::from_iter(std::env::args().skip(1)));
+    }
     unsafe { riscv_5stage_simulator::PRINT_DEBUG_INFO = OPTS.print_debug_info };
     unsafe { riscv_5stage_simulator::PRINT_STEPS = OPTS.print_steps };
 
     let mut f_data = Vec::new();
-    let process_image;
+    let mut process_image;
     let elf;
 
     let mut f = File::open(&OPTS.elf_binary).expect("error opening file");
     f.read_to_end(&mut f_data).expect("Can't read from a file");
     elf = goblin::elf::Elf::parse(&f_data).expect("It's not a elf binary file");
     process_image = ProcessMemory::new(&elf, &f_data, OPTS.elf_binary.to_str().unwrap());
+    // user mode에서는 brk로 늘어난 heap이 UART 주소와 겹칠 수 있음
+    if OPTS.full_system {
+        process_image
+            .mmio
+            .map(UART_BASE, UART_SIZE, Box::new(Uart::new()));
+    }
 
-    let mut pipeline = Pipeline::new(elf.entry as u32, process_image);
+    let config = PipelineConfig {
+        dram: DramConfig {
+            channels: OPTS.dram_channels,
+            banks: OPTS.dram_banks,
+            row_size: OPTS.dram_row_size,
+            page_policy: OPTS.dram_page_policy,
+            t_rcd: OPTS.dram_t_rcd,
+            t_cas: OPTS.dram_t_cas,
+            t_rp: OPTS.dram_t_rp,
+            ..Default::default()
+        },
+        l1d: CacheConfig {
+            size: OPTS.l1d_size,
+            ways: OPTS.l1d_ways,
+            line_size: OPTS.l1d_line_size,
+            hit_latency: OPTS.l1d_latency,
+            bus_latency: OPTS.bus_latency,
+        },
+        clint: ClintConfig {
+            clocks_per_tick: OPTS.mtime_divider,
+        },
+        mmu: MmuConfig {
+            itlb_entries: OPTS.itlb_entries,
+            dtlb_entries: OPTS.dtlb_entries,
+        },
+        fetch: FetchConfig {
+            width: OPTS.fetch_width,
+            queue_size: OPTS.fetch_queue_size,
+            depth: OPTS.fetch_depth,
+        },
+        rename: RenameConfig {
+            model: OPTS.rename_model,
+            phys_regs: OPTS.phys_regs,
+        },
+        full_system: OPTS.full_system,
+        trace_lifecycle: OPTS.kanata.is_some() || OPTS.pipeline_diagram.is_some(),
+    };
+    if let Err(e) = config.dram.check().and_then(|_| config.l1d.check()) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+    let mut machine = Machine::new(
+        elf.entry as u32,
+        process_image,
+        OPTS.harts,
+        OPTS.boot_all_harts,
+        config,
+    );
+    machine.mem_sys.htif = Htif::from_elf(&elf);
+    if let Some(ref path) = OPTS.restore {
+        let file = File::open(path).expect("Can't open the checkpoint");
+        checkpoint::restore(&mut machine, BufReader::new(file))
+            .unwrap_or_else(|e| panic!("Can't restore {}: {}", path.display(), e));
+    }
+    if let Some(ref path) = OPTS.bbv {
+        write_bbv(machine, path);
+    }
+    if let Some(ref path) = OPTS.simpoints {
+        simulate_points(machine, path);
+    }
+    let mut is_finished = false;
+    if let Some(count) = OPTS.fast_forward {
+        is_finished = machine.fast_forward(count).1;
+        machine.start_clock = machine.clock;
+    }
+    if OPTS.debug {
+        debug(machine, debugger::symbols(&elf));
+    }
+    if let Some(port) = OPTS.gdb_port {
+        serve_gdb(&mut machine, port);
+    }
+    let mut kanata = OPTS.kanata.as_ref().map(|path| {
+        let file = File::create(path).expect("Can't create the Kanata log");
+        KanataWriter::new(BufWriter::new(file)).expect("Can't write the Kanata log")
+    });
+    let mut vcd = OPTS.vcd.as_ref().map(|path| {
+        let file = File::create(path).expect("Can't create the VCD file");
+        let filter = OPTS.vcd_signals.clone().unwrap_or_default();
+        VcdWriter::new(BufWriter::new(file), OPTS.harts, &filter).expect("Can't write the VCD file")
+    });
+    let mut commit_log = OPTS.commit_log.as_ref().map(|path| {
+        let file = File::create(path).expect("Can't create the commit log");
+        CommitLogWriter::new(BufWriter::new(file))
+    });
+    let mut diagram = OPTS
+        .pipeline_diagram
+        .map(|limit| PipelineDiagram::new(limit, OPTS.pipeline_diagram_pc));
+    let mut checkpoint_pending =
+        OPTS.checkpoint_at_cycle.is_some() || OPTS.checkpoint_at_inst.is_some();
+    let mut retired_count = 0;
 
+    while !is_finished {
+        let (retired, finished) = machine.run_clock();
+        is_finished = finished;
+        if checkpoint_pending {
+            retired_count += retired.iter().map(|entries| entries.len()).sum::<usize>();
+            let cycle_due = OPTS
+                .checkpoint_at_cycle
+                .is_some_and(|cycle| machine.clock >= cycle);
+            let inst_due = OPTS
+                .checkpoint_at_inst
+                .is_some_and(|inst| retired_count >= inst);
+            if (cycle_due || inst_due) && !is_finished {
+                // 진행 중인 명령어가 모두 빠질 때까지 fetch를 멈춤
+                machine.drain(true);
+                if machine.is_drained() {
+                    let file =
+                        File::create(&OPTS.checkpoint_file).expect("Can't create the checkpoint");
+                    checkpoint::save(&machine, BufWriter::new(file))
+                        .expect("Can't write the checkpoint");
+                    eprintln!(
+                        "Checkpoint at clock {} written to {}",
+                        machine.clock,
+                        OPTS.checkpoint_file.display()
+                    );
+                    machine.drain(false);
+                    checkpoint_pending = false;
+                }
+            }
+        }
+        if let Some(commit_log) = commit_log.as_mut() {
+            for (hart, entries) in retired.iter().enumerate() {
+                for (_, entry) in entries {
+                    commit_log
+                        .record(hart, entry)
+                        .expect("Can't write the commit log");
+                }
+            }
+        }
+        if let Some(vcd) = vcd.as_mut() {
+            vcd.sample(&machine, &retired)
+                .expect("Can't write the VCD file");
+        }
+        let (records, settled) = machine.take_lifecycle();
+        for record in records {
+            if let Some(diagram) = diagram.as_mut() {
+                diagram.record(record.clone());
+            }
+            if let Some(kanata) = kanata.as_mut() {
+                kanata.record(record);
+            }
+        }
+        if let Some(kanata) = kanata.as_mut() {
+            kanata
+                .advance(settled)
+                .expect("Can't write the Kanata log");
+        }
+    }
+    let report = Report::new(&machine);
+    let stats = match OPTS.stats_format {
+        StatsFormat::Text => text_stats(&machine),
+        StatsFormat::Json => report.to_json(),
+        StatsFormat::Csv => report.to_csv(),
+    };
+    match OPTS.stats_file {
+        Some(ref path) => std::fs::write(path, stats).expect("Can't write the statistics"),
+        None => eprint!("{}", stats),
+    }
+    if let Some(diagram) = diagram {
+        eprint!("{}", diagram);
+    }
+    if let Some(vcd) = vcd {
+        vcd.finish().expect("Can't write the VCD file");
+    }
+    if let Some(kanata) = kanata {
+        kanata.finish().expect("Can't write the Kanata log");
+    }
+    if let Some(commit_log) = commit_log {
+        commit_log.finish().expect("Can't write the commit log");
+    }
+
+    if machine.mem_sys.htif.is_some() {
+        // riscv-tests는 실패한 테스트 번호를 exit code로 보고함
+        match machine.threads.exit_code() {
+            Some(0) => eprintln!("HTIF: PASS"),
+            Some(test) => eprintln!("HTIF: FAIL (test #{})", test),
+            None => eprintln!("HTIF: FAIL (no exit code)"),
+        }
+    }
+    std::process::exit(machine.threads.exit_code().unwrap_or(0) as i32);
+}
+
+/// Runs the program functionally to the end, writing its basic block
+/// vectors.
+fn write_bbv(mut machine: Machine, path: &PathBuf) -> ! {
+    let file = File::create(path).expect("Can't create the BBV file");
+    let mut bbv = BbvWriter::new(BufWriter::new(file), OPTS.interval, OPTS.harts);
     loop {
-        let (_, is_finished) = pipeline.run_clock();
+        let (steps, is_finished) = machine.step();
+        for (hart, step) in steps.iter().enumerate() {
+            if let Some((pc, inst)) = step {
+                bbv.record(hart, *pc, inst)
+                    .expect("Can't write the BBV file");
+            }
+        }
         if is_finished {
-            eprintln!("Total Clock: {}", pipeline.clock);
             break;
         }
     }
+    let (_, executed) = bbv.finish().expect("Can't write the BBV file");
+    eprintln!(
+        "{} instructions in {} intervals of {}",
+        executed,
+        executed.div_ceil(OPTS.interval),
+        OPTS.interval
+    );
+    std::process::exit(machine.threads.exit_code().unwrap_or(0) as i32);
+}
+
+/// Simulates the simulation points in detail and reports their IPCs.
+fn simulate_points(mut machine: Machine, path: &PathBuf) -> ! {
+    let read = |path: &PathBuf| {
+        std::fs::read_to_string(path)
+            .unwrap_or_else(|e| panic!("Can't read {}: {}", path.display(), e))
+    };
+    let weights = OPTS.simpoint_weights.as_ref().unwrap();
+    let points = SimPoint::parse(&read(path), &read(weights)).unwrap_or_else(|e| panic!("{}", e));
+    let samples = simpoint::simulate(&mut machine, &points, OPTS.interval, OPTS.warmup);
+    for sample in samples.iter() {
+        eprintln!("SimPoint {}", sample);
+    }
+    if samples.len() < points.len() {
+        eprintln!(
+            "The program ended before {} of the simulation points",
+            points.len() - samples.len()
+        );
+    }
+    eprintln!("Weighted IPC: {:.2}", simpoint::weighted_ipc(&samples));
+    std::process::exit(0);
+}
+
+/// Reads debugger commands from stdin until `quit` or the end of input. An
+/// empty line repeats the last command.
+fn debug(mut machine: Machine, symbols: std::collections::HashMap<String, u32>) -> ! {
+    let mut debugger = Debugger::new(symbols);
+    let stdin = std::io::stdin();
+    let mut last = None;
+    loop {
+        print!("(casim) ");
+        std::io::stdout().flush().expect("Can't write to stdout");
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).expect("Can't read stdin") == 0 {
+            break;
+        }
+        let command = match line.trim() {
+            "" => match last.clone() {
+                Some(command) => command,
+                None => continue,
+            },
+            line => match line.parse::<Command>() {
+                Ok(command) => command,
+                Err(e) => {
+                    println!("{}", e);
+                    continue;
+                }
+            },
+        };
+        if command == Command::Quit {
+            break;
+        }
+        match debugger.run(&mut machine, command.clone()) {
+            Ok(ref text) if text.is_empty() => {}
+            Ok(text) => println!("{}", text),
+            Err(e) => println!("{}", e),
+        }
+        last = Some(command);
+    }
+    std::process::exit(machine.threads.exit_code().unwrap_or(0) as i32);
+}
+
+/// Lets gdb drive the machine. Returns only if gdb detaches before the
+/// program ends, to run the rest of it.
+fn serve_gdb(machine: &mut Machine, port: u16) {
+    let listener = std::net::TcpListener::bind(("127.0.0.1", port))
+        .unwrap_or_else(|e| panic!("Can't listen on port {}: {}", port, e));
+    eprintln!("Waiting for gdb on port {}", port);
+    let (stream, _) = listener.accept().expect("Can't accept the connection");
+    stream.set_nodelay(true).expect("Can't set up the connection");
+    match GdbStub::new(stream).serve(machine) {
+        Ok(gdb::End::Detached) => {}
+        Ok(gdb::End::Killed) => std::process::exit(0),
+        Ok(gdb::End::Finished) => {
+            std::process::exit(machine.threads.exit_code().unwrap_or(0) as i32)
+        }
+        Err(e) => panic!("Lost the connection to gdb: {}", e),
+    }
+}
+
+/// Exits with 0 if the commit logs match and 1 if they diverge.
+fn diff(opts: DiffOpt) -> ! {
+    let open = |path: &PathBuf| {
+        let file = File::open(path)
+            .unwrap_or_else(|e| panic!("Can't open {}: {}", path.display(), e));
+        BufReader::new(file)
+    };
+    let (total, divergence) = commit_diff::diff(open(&opts.left), open(&opts.right), opts.context)
+        .expect("Can't read the commit logs");
+    match divergence {
+        Some(divergence) => {
+            print!("{}", divergence);
+            std::process::exit(1);
+        }
+        None => {
+            println!("No divergence in {} instructions", total);
+            std::process::exit(0);
+        }
+    }
+}
+
+fn text_stats(machine: &Machine) -> String {
+    let mut text = format!("Total Clock: {}\n", machine.clock);
+    text += &format!("DRAM: {}\n", machine.mem_sys.dram.stats);
+    text += &format!("Coherence: {}\n", machine.mem_sys.stats);
+    for (hart, cache) in machine.harts.iter().zip(machine.mem_sys.caches.iter()) {
+        text += &format!("Hart {} MMU: {}\n", hart.hart_id, hart.mmu.stats);
+        text += &format!("Hart {} L1D: {}\n", hart.hart_id, cache.stats);
+        text += &format!(
+            "Hart {} interrupts: {}\n",
+            hart.hart_id, hart.interrupts.stats
+        );
+        text += &format!("Hart {} rename: {}\n", hart.hart_id, hart.rename_stats);
+        for section in hart.stats.sections(machine.clock - machine.start_clock) {
+            text += &format!(
+                "Hart {} {}: {}\n",
+                hart.hart_id,
+                section.name.replace('_', " "),
+                section
+            );
+        }
+    }
+    text
 }
@@ -0,0 +1,230 @@
+//! Private L1 data cache tracking MESI coherence states.
+//!
+//! The cache only keeps tags and states. Data always lives in
+//! `ProcessMemory`, so a cache decides how long an access takes but never
+//! what value it returns.
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MesiState {
+    Modified,
+    Exclusive,
+    Shared,
+    Invalid,
+}
+
+#[derive(Debug, Clone)]
+pub struct CacheConfig {
+    /// Capacity in bytes.
+    pub size: usize,
+    pub ways: usize,
+    pub line_size: usize,
+    /// Clocks for a hit.
+    pub hit_latency: usize,
+    /// Extra clocks for a bus transaction that doesn't need DRAM, i.e. an
+    /// upgrade or a cache-to-cache transfer.
+    pub bus_latency: usize,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            size: 32 * 1024,
+            ways: 4,
+            line_size: 64,
+            hit_latency: 2,
+            bus_latency: 10,
+        }
+    }
+}
+
+impl CacheConfig {
+    /// Rejects a geometry `line_addr` and `set_of` can't index with.
+    pub fn check(&self) -> Result<(), String> {
+        if !self.line_size.is_power_of_two() {
+            return Err(format!(
+                "The cache line size must be a power of two (got {})",
+                self.line_size
+            ));
+        }
+        if self.ways == 0 {
+            return Err("The cache needs at least one way".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct CacheStats {
+    pub hits: usize,
+    pub misses: usize,
+    /// Writes to a `Shared` line that had to invalidate the other copies.
+    pub upgrades: usize,
+    /// Dirty lines written back on eviction or when another hart read them.
+    pub writebacks: usize,
+    /// Lines invalidated by writes from other harts.
+    pub invalidations: usize,
+}
+
+impl fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let accesses = self.hits + self.misses;
+        let hit_rate = if accesses == 0 {
+            0.0
+        } else {
+            self.hits as f64 / accesses as f64 * 100.0
+        };
+        write!(
+            f,
+            "hits: {}, misses: {}, hit rate: {:.2}%, upgrades: {}, writebacks: {}, invalidations: {}",
+            self.hits, self.misses, hit_rate, self.upgrades, self.writebacks, self.invalidations
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Line {
+    /// Address of the first byte of the line.
+    addr: u32,
+    state: MesiState,
+    last_used: usize,
+}
+
+/// A set-associative cache with LRU replacement.
+#[derive(Debug, Clone)]
+pub struct Cache {
+    pub config: CacheConfig,
+    sets: Vec<Vec<Line>>,
+    use_counter: usize,
+    pub stats: CacheStats,
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Cache::new(CacheConfig::default())
+    }
+}
+
+impl Cache {
+    pub fn new(config: CacheConfig) -> Self {
+        let set_count = std::cmp::max(config.size / (config.line_size * config.ways), 1);
+        Cache {
+            sets: vec![Vec::new(); set_count],
+            config,
+            use_counter: 0,
+            stats: Default::default(),
+        }
+    }
+
+    /// Address of the line holding `addr`.
+    pub fn line_addr(&self, addr: u32) -> u32 {
+        addr & !(self.config.line_size as u32 - 1)
+    }
+
+    fn set_of(&self, line: u32) -> usize {
+        (line as usize / self.config.line_size) % self.sets.len()
+    }
+
+    fn find(&self, line: u32) -> Option<&Line> {
+        self.sets[self.set_of(line)]
+            .iter()
+            .find(|entry| entry.addr == line)
+    }
+
+    fn find_mut(&mut self, line: u32) -> Option<&mut Line> {
+        let set = self.set_of(line);
+        self.sets[set].iter_mut().find(|entry| entry.addr == line)
+    }
+
+    pub fn state(&self, line: u32) -> MesiState {
+        self.find(line)
+            .map(|entry| entry.state)
+            .unwrap_or(MesiState::Invalid)
+    }
+
+    /// Marks the line as most recently used.
+    pub fn touch(&mut self, line: u32) {
+        self.use_counter += 1;
+        let counter = self.use_counter;
+        if let Some(entry) = self.find_mut(line) {
+            entry.last_used = counter;
+        }
+    }
+
+    /// Changes the state of a line that is present. `Invalid` drops it.
+    pub fn set_state(&mut self, line: u32, state: MesiState) {
+        if state == MesiState::Invalid {
+            let set = self.set_of(line);
+            self.sets[set].retain(|entry| entry.addr != line);
+        } else if let Some(entry) = self.find_mut(line) {
+            entry.state = state;
+        }
+    }
+
+    /// Brings a line in, returning the evicted victim and its state if the
+    /// set was full.
+    pub fn fill(&mut self, line: u32, state: MesiState) -> Option<(u32, MesiState)> {
+        let set_idx = self.set_of(line);
+        let ways = self.config.ways;
+        let set = &mut self.sets[set_idx];
+        let victim = if set.len() >= ways {
+            let pos = set
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(pos, _)| pos)
+                .unwrap();
+            let victim = set.swap_remove(pos);
+            Some((victim.addr, victim.state))
+        } else {
+            None
+        };
+        set.push(Line {
+            addr: line,
+            state,
+            last_used: 0,
+        });
+        self.touch(line);
+        victim
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bad_geometry_is_rejected() {
+        assert!(CacheConfig::default().check().is_ok());
+        for &(ways, line_size) in &[(4, 0), (4, 48), (0, 64)] {
+            let config = CacheConfig {
+                ways,
+                line_size,
+                ..Default::default()
+            };
+            assert!(config.check().is_err());
+        }
+    }
+
+    #[test]
+    fn lru_victim_is_evicted() {
+        let mut cache = Cache::new(CacheConfig {
+            size: 256,
+            ways: 2,
+            line_size: 64,
+            ..Default::default()
+        });
+        // 2개의 set이므로 0x0, 0x80, 0x100은 모두 같은 set
+        assert_eq!(cache.fill(0x0, MesiState::Exclusive), None);
+        assert_eq!(cache.fill(0x80, MesiState::Modified), None);
+        cache.touch(0x0);
+        assert_eq!(
+            cache.fill(0x100, MesiState::Shared),
+            Some((0x80, MesiState::Modified))
+        );
+        assert_eq!(cache.state(0x0), MesiState::Exclusive);
+        assert_eq!(cache.state(0x80), MesiState::Invalid);
+        assert_eq!(cache.line_addr(0x13f), 0x100);
+    }
+}
@@ -0,0 +1,168 @@
+//! Core-local interruptor.
+//!
+//! The CLINT holds the machine timer shared by every hart, and a timer
+//! compare register and a software interrupt bit per hart, all mapped at
+//! `CLINT_BASE` with the SiFive layout. Only aligned word accesses are
+//! supported; the 64-bit registers are accessed as two halves.
+
+use checkpoint::{invalid, Decoder, Encoder, Snapshot};
+use pipeline::exception::Exception;
+use std::io;
+
+pub const CLINT_BASE: u32 = 0x0200_0000;
+pub const CLINT_SIZE: u32 = 0x0001_0000;
+const MSIP_OFFSET: u32 = 0x0;
+const MTIMECMP_OFFSET: u32 = 0x4000;
+const MTIME_OFFSET: u32 = 0xbff8;
+
+#[derive(Debug, Clone)]
+pub struct ClintConfig {
+    /// Clocks per `mtime` increment.
+    pub clocks_per_tick: usize,
+}
+
+impl Default for ClintConfig {
+    fn default() -> Self {
+        ClintConfig {
+            clocks_per_tick: 10,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Clint {
+    pub config: ClintConfig,
+    pub mtime: u64,
+    mtimecmp: Vec<u64>,
+    msip: Vec<bool>,
+}
+
+impl Clint {
+    pub fn new(config: ClintConfig, hart_count: usize) -> Self {
+        Clint {
+            config,
+            mtime: 0,
+            mtimecmp: vec![u64::MAX; hart_count],
+            msip: vec![false; hart_count],
+        }
+    }
+
+    pub fn contains(&self, addr: u32) -> bool {
+        (CLINT_BASE..CLINT_BASE + CLINT_SIZE).contains(&addr)
+    }
+
+    /// Advances `mtime` to match `clock`.
+    pub fn tick(&mut self, clock: usize) {
+        self.mtime = (clock / std::cmp::max(self.config.clocks_per_tick, 1)) as u64;
+    }
+
+    pub fn timer_pending(&self, hart: usize) -> bool {
+        self.mtime >= self.mtimecmp[hart]
+    }
+
+    pub fn software_pending(&self, hart: usize) -> bool {
+        self.msip[hart]
+    }
+
+    /// Finds the register at `addr`, as (register, hart, upper half).
+    fn decode(&self, addr: u32) -> Option<(u32, usize, bool)> {
+        let offset = addr.wrapping_sub(CLINT_BASE);
+        if addr & 0x3 != 0 || !self.contains(addr) {
+            return None;
+        }
+        let hart_count = self.msip.len() as u32;
+        if offset < MSIP_OFFSET + 4 * hart_count {
+            Some((MSIP_OFFSET, (offset / 4) as usize, false))
+        } else if (MTIMECMP_OFFSET..MTIMECMP_OFFSET + 8 * hart_count).contains(&offset) {
+            let offset = offset - MTIMECMP_OFFSET;
+            Some((MTIMECMP_OFFSET, (offset / 8) as usize, offset % 8 == 4))
+        } else if (MTIME_OFFSET..MTIME_OFFSET + 8).contains(&offset) {
+            Some((MTIME_OFFSET, 0, offset == MTIME_OFFSET + 4))
+        } else {
+            None
+        }
+    }
+
+    fn half(value: u64, upper: bool) -> u32 {
+        if upper {
+            (value >> 32) as u32
+        } else {
+            value as u32
+        }
+    }
+
+    fn with_half(value: u64, half: u32, upper: bool) -> u64 {
+        if upper {
+            (value & 0xffff_ffff) | (u64::from(half) << 32)
+        } else {
+            (value & !0xffff_ffff) | u64::from(half)
+        }
+    }
+
+    pub fn read(&self, addr: u32) -> Result<u32, Exception> {
+        match self.decode(addr) {
+            Some((MSIP_OFFSET, hart, _)) => Ok(self.msip[hart] as u32),
+            Some((MTIMECMP_OFFSET, hart, upper)) => Ok(Self::half(self.mtimecmp[hart], upper)),
+            Some((_, _, upper)) => Ok(Self::half(self.mtime, upper)),
+            None => Err(Exception::LoadAccessFault(addr)),
+        }
+    }
+
+    pub fn write(&mut self, addr: u32, value: u32) -> Result<(), Exception> {
+        match self.decode(addr) {
+            Some((MSIP_OFFSET, hart, _)) => self.msip[hart] = value & 1 != 0,
+            Some((MTIMECMP_OFFSET, hart, upper)) => {
+                self.mtimecmp[hart] = Self::with_half(self.mtimecmp[hart], value, upper)
+            }
+            // mtime은 clock을 따라가므로 쓰기는 무시
+            Some(_) => {}
+            None => return Err(Exception::StoreAccessFault(addr)),
+        }
+        Ok(())
+    }
+}
+
+/// `mtime` follows the clock and isn't saved.
+impl Snapshot for Clint {
+    fn save(&self, out: &mut Encoder) {
+        out.usize(self.mtimecmp.len());
+        for (&mtimecmp, &msip) in self.mtimecmp.iter().zip(self.msip.iter()) {
+            out.u64(mtimecmp);
+            out.bool(msip);
+        }
+    }
+
+    fn restore(&mut self, input: &mut Decoder) -> io::Result<()> {
+        if input.usize()? != self.mtimecmp.len() {
+            return Err(invalid("checkpoint has a different number of harts"));
+        }
+        for (mtimecmp, msip) in self.mtimecmp.iter_mut().zip(self.msip.iter_mut()) {
+            *mtimecmp = input.u64()?;
+            *msip = input.bool()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timer_and_software_interrupts() {
+        let mut clint = Clint::new(ClintConfig { clocks_per_tick: 4 }, 2);
+        clint.write(CLINT_BASE + MTIMECMP_OFFSET + 8, 3).unwrap();
+        clint.write(CLINT_BASE + MTIMECMP_OFFSET + 12, 0).unwrap();
+        clint.tick(11);
+        assert_eq!(clint.read(CLINT_BASE + MTIME_OFFSET).unwrap(), 2);
+        assert!(!clint.timer_pending(0));
+        assert!(!clint.timer_pending(1));
+        clint.tick(12);
+        assert!(clint.timer_pending(1));
+
+        clint.write(CLINT_BASE + 4, 1).unwrap();
+        assert!(clint.software_pending(1));
+        assert!(!clint.software_pending(0));
+        assert!(clint.read(CLINT_BASE + 2).is_err());
+    }
+}
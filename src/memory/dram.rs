@@ -0,0 +1,392 @@
+//! DRAM controller timing model.
+//!
+//! Models main memory as a set of channels, each holding several banks with
+//! a single row buffer. Requests queue per channel and are scheduled
+//! first-ready, first-come-first-served (FR-FCFS): a request hitting an open
+//! row goes before older requests that would need an activate.
+//!
+//! Only timing lives here. Data is still read from and written to
+//! `ProcessMemory` by the functional units once a request completes.
+
+use consts::MEM_CYCLE;
+use std::fmt;
+use std::str::FromStr;
+
+/// Row-buffer management policy.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PagePolicy {
+    /// Rows stay open after an access, so later hits only pay tCAS.
+    Open,
+    /// Rows are precharged right after every access.
+    Closed,
+}
+
+impl FromStr for PagePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "open" => Ok(PagePolicy::Open),
+            "closed" => Ok(PagePolicy::Closed),
+            _ => Err(format!(
+                "unknown page policy '{}', expected open or closed",
+                s
+            )),
+        }
+    }
+}
+
+/// Geometry and timing of the DRAM, in CPU clocks.
+#[derive(Debug, Clone)]
+pub struct DramConfig {
+    pub channels: usize,
+    /// Banks per channel.
+    pub banks: usize,
+    /// Bytes held by one row buffer.
+    pub row_size: usize,
+    pub page_policy: PagePolicy,
+    /// Activate to column command delay.
+    pub t_rcd: usize,
+    /// Column command to first data delay.
+    pub t_cas: usize,
+    /// Precharge delay.
+    pub t_rp: usize,
+    /// Clocks a burst occupies the data bus.
+    pub t_burst: usize,
+}
+
+impl Default for DramConfig {
+    fn default() -> Self {
+        DramConfig {
+            channels: 1,
+            banks: 8,
+            row_size: 2048,
+            page_policy: PagePolicy::Open,
+            t_rcd: 14,
+            t_cas: 14,
+            t_rp: 14,
+            t_burst: 4,
+        }
+    }
+}
+
+impl DramConfig {
+    /// Rejects a geometry `map_address` would divide by zero with.
+    pub fn check(&self) -> Result<(), String> {
+        if self.channels == 0 || self.banks == 0 || self.row_size == 0 {
+            return Err(format!(
+                "DRAM needs at least one channel, one bank and a non-empty row (got {} channels, {} banks, {} byte rows)",
+                self.channels, self.banks, self.row_size
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Owner of a memory request within a hart, reported back when a read
+/// completes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Requester {
+    /// A load or `lr.w`, keyed by its ROB index.
+    Load(usize),
+    /// A store, `sc.w` or AMO executing at the ROB head, keyed by its ROB
+    /// index. It completes once the cache line is owned.
+    Store(usize),
+    /// A dirty cache line written back to memory. Writes are posted, so
+    /// nobody waits for them.
+    Writeback,
+    /// A page-table entry read, keyed by the walk step that issued it.
+    PageWalk(usize),
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct DramStats {
+    pub reads: usize,
+    pub writes: usize,
+    pub row_hits: usize,
+    /// Accesses to a precharged bank.
+    pub row_misses: usize,
+    /// Accesses that had to close another open row first.
+    pub row_conflicts: usize,
+    pub total_read_latency: usize,
+}
+
+impl fmt::Display for DramStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let avg_latency = if self.reads == 0 {
+            0.0
+        } else {
+            self.total_read_latency as f64 / self.reads as f64
+        };
+        write!(
+            f,
+            "reads: {}, writes: {}, row hits: {}, row misses: {}, row conflicts: {}, avg read latency: {:.2}",
+            self.reads, self.writes, self.row_hits, self.row_misses, self.row_conflicts, avg_latency
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Request {
+    hart: usize,
+    requester: Requester,
+    is_write: bool,
+    bank: usize,
+    row: u32,
+    arrival: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Bank {
+    open_row: Option<u32>,
+    /// First clock the bank can accept a new command.
+    ready_at: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Channel {
+    queue: Vec<Request>,
+    banks: Vec<Bank>,
+    bus_free_at: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct DramController {
+    config: DramConfig,
+    channels: Vec<Channel>,
+    /// Scheduled reads as (completion clock, arrival clock, hart, requester).
+    in_flight: Vec<(usize, usize, usize, Requester)>,
+    clock: usize,
+    pub stats: DramStats,
+}
+
+impl Default for DramController {
+    fn default() -> Self {
+        DramController::new(DramConfig::default())
+    }
+}
+
+impl DramController {
+    pub fn new(config: DramConfig) -> Self {
+        let channel = Channel {
+            queue: Vec::new(),
+            banks: vec![Bank::default(); config.banks],
+            bus_free_at: 0,
+        };
+        DramController {
+            channels: vec![channel; config.channels],
+            config,
+            in_flight: Vec::new(),
+            clock: 0,
+            stats: Default::default(),
+        }
+    }
+
+    /// Splits an address into (channel, bank, row).
+    fn map_address(&self, addr: u32) -> (usize, usize, u32) {
+        let row_index = addr as usize / self.config.row_size;
+        let channel = row_index % self.config.channels;
+        let bank = (row_index / self.config.channels) % self.config.banks;
+        let row = (row_index / self.config.channels / self.config.banks) as u32;
+        (channel, bank, row)
+    }
+
+    /// Queues an access. Reads are reported by `tick` once their data is back.
+    pub fn request(&mut self, hart: usize, requester: Requester, addr: u32, is_write: bool) {
+        let (channel, bank, row) = self.map_address(addr);
+        self.channels[channel].queue.push(Request {
+            hart,
+            requester,
+            is_write,
+            bank,
+            row,
+            arrival: self.clock,
+        });
+    }
+
+    /// Advances the controller to `clock` and returns the (hart, requester)
+    /// pairs whose reads completed.
+    pub fn tick(&mut self, clock: usize) -> Vec<(usize, Requester)> {
+        self.clock = clock;
+        for channel_idx in 0..self.channels.len() {
+            self.schedule(channel_idx);
+        }
+
+        let mut completed = Vec::new();
+        let stats = &mut self.stats;
+        self.in_flight
+            .retain(|&(done_at, arrival, hart, requester)| {
+                if done_at <= clock {
+                    stats.total_read_latency += done_at - arrival;
+                    completed.push((hart, requester));
+                    false
+                } else {
+                    true
+                }
+            });
+        completed
+    }
+
+    /// Issues at most one request per channel per clock, FR-FCFS.
+    fn schedule(&mut self, channel_idx: usize) {
+        let clock = self.clock;
+        let config = &self.config;
+        let channel = &mut self.channels[channel_idx];
+
+        let is_ready = |req: &Request| channel.banks[req.bank].ready_at <= clock;
+        let is_row_hit = |req: &Request| channel.banks[req.bank].open_row == Some(req.row);
+        // The queue is kept in arrival order, so the first match is the oldest.
+        let picked = channel
+            .queue
+            .iter()
+            .position(|req| is_ready(req) && is_row_hit(req))
+            .or_else(|| channel.queue.iter().position(is_ready));
+        let req = match picked {
+            Some(pos) => channel.queue.remove(pos),
+            None => return,
+        };
+
+        let bank = &mut channel.banks[req.bank];
+        let activate_delay = match bank.open_row {
+            Some(row) if row == req.row => {
+                self.stats.row_hits += 1;
+                0
+            }
+            Some(_) => {
+                self.stats.row_conflicts += 1;
+                config.t_rp + config.t_rcd
+            }
+            None => {
+                self.stats.row_misses += 1;
+                config.t_rcd
+            }
+        };
+        let column_at = clock + activate_delay;
+        let data_at = std::cmp::max(column_at + config.t_cas, channel.bus_free_at);
+        channel.bus_free_at = data_at + config.t_burst;
+
+        match config.page_policy {
+            PagePolicy::Open => {
+                bank.open_row = Some(req.row);
+                bank.ready_at = column_at + config.t_burst;
+            }
+            PagePolicy::Closed => {
+                bank.open_row = None;
+                bank.ready_at = data_at + config.t_burst + config.t_rp;
+            }
+        }
+
+        if req.is_write {
+            self.stats.writes += 1;
+        } else {
+            self.stats.reads += 1;
+            let done_at = data_at + config.t_burst + MEM_CYCLE;
+            self.in_flight
+                .push((done_at, req.arrival, req.hart, req.requester));
+        }
+    }
+
+    /// Forgets every pending load and store of `hart` after a pipeline flush.
+    /// Bank state is kept, since the squashed accesses still occupied the DRAM.
+    pub fn squash(&mut self, hart: usize) {
+        let is_squashed = |req_hart: usize, requester: &Requester| {
+            req_hart == hart && matches!(requester, Requester::Load(_) | Requester::Store(_))
+        };
+        for channel in self.channels.iter_mut() {
+            channel
+                .queue
+                .retain(|req| !is_squashed(req.hart, &req.requester));
+        }
+        self.in_flight
+            .retain(|(_, _, req_hart, requester)| !is_squashed(*req_hart, requester));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zero_sized_geometry_is_rejected() {
+        assert!(DramConfig::default().check().is_ok());
+        for config in &[
+            DramConfig {
+                channels: 0,
+                ..Default::default()
+            },
+            DramConfig {
+                banks: 0,
+                ..Default::default()
+            },
+            DramConfig {
+                row_size: 0,
+                ..Default::default()
+            },
+        ] {
+            assert!(config.check().is_err());
+        }
+    }
+
+    fn run_until_done(dram: &mut DramController, start: usize) -> usize {
+        (start..start + 1000)
+            .find(|&clock| !dram.tick(clock).is_empty())
+            .unwrap()
+    }
+
+    #[test]
+    fn row_hit_is_faster_than_conflict() {
+        let config = DramConfig::default();
+        let mut dram = DramController::new(config.clone());
+
+        dram.request(0, Requester::Load(1), 0x100, false);
+        let first = run_until_done(&mut dram, 0);
+        dram.request(0, Requester::Load(2), 0x140, false);
+        let hit = run_until_done(&mut dram, first) - first;
+
+        let conflicting = (config.row_size * config.channels * config.banks) as u32;
+        dram.request(0, Requester::Load(3), conflicting, false);
+        let done = run_until_done(&mut dram, first + hit);
+        let conflict = done - first - hit;
+
+        assert_eq!(dram.stats.row_misses, 1);
+        assert_eq!(dram.stats.row_hits, 1);
+        assert_eq!(dram.stats.row_conflicts, 1);
+        assert!(hit < first);
+        assert!(first < conflict);
+    }
+
+    #[test]
+    fn row_hit_bypasses_older_miss() {
+        let config = DramConfig::default();
+        let mut dram = DramController::new(config.clone());
+        dram.request(0, Requester::Load(1), 0, false);
+        run_until_done(&mut dram, 0);
+
+        let other_row = (config.row_size * config.channels * config.banks) as u32;
+        dram.request(0, Requester::Load(2), other_row, false);
+        dram.request(0, Requester::Load(3), 0x10, false);
+        let mut order = Vec::new();
+        for clock in 100..1000 {
+            order.extend(dram.tick(clock));
+        }
+        assert_eq!(
+            order,
+            vec![(0, Requester::Load(3)), (0, Requester::Load(2))]
+        );
+    }
+
+    #[test]
+    fn closed_page_never_hits() {
+        let config = DramConfig {
+            page_policy: PagePolicy::Closed,
+            ..Default::default()
+        };
+        let mut dram = DramController::new(config);
+        for (i, clock) in [0usize, 200, 400].iter().enumerate() {
+            dram.request(0, Requester::Load(i), 0x20 * i as u32, false);
+            run_until_done(&mut dram, *clock);
+        }
+        assert_eq!(dram.stats.row_hits, 0);
+        assert_eq!(dram.stats.row_misses, 3);
+    }
+}
@@ -0,0 +1,174 @@
+//! Host-target interface.
+//!
+//! Programs built for Spike, such as riscv-tests, talk to the host through
+//! the 64-bit `tohost` and `fromhost` words found in the ELF symbol table.
+//! A command is taken once the upper word of `tohost` is written, which RV32
+//! programs do last. Commands are decoded the way Spike does:
+//!
+//! - device 0, command 0 with bit 0 set exits with the rest of the payload
+//!   as the code, which riscv-tests set to the failing test number;
+//! - device 0, command 0 otherwise points to a block of eight 64-bit words
+//!   holding a system call number and its arguments, to run on the host;
+//! - device 1, command 1 writes the low byte of the payload to the console.
+
+use checkpoint::{Decoder, Encoder, Snapshot};
+use memory::ProcessMemory;
+use pipeline::exception::Exception;
+use pipeline::thread::ENOSYS;
+use std::io::{self, Write};
+
+const SYS_WRITE: u64 = 64;
+const SYS_EXIT: u64 = 93;
+
+#[derive(Debug, Clone)]
+pub struct Htif {
+    pub tohost: u32,
+    pub fromhost: Option<u32>,
+    exit_code: Option<u32>,
+}
+
+impl Htif {
+    /// Looks up `tohost` and `fromhost`. Returns `None` if the program has
+    /// no `tohost`.
+    pub fn from_elf(elf: &goblin::elf::Elf) -> Option<Htif> {
+        let find = |name: &str| {
+            elf.syms
+                .iter()
+                .find(|sym| match elf.strtab.get(sym.st_name) {
+                    Some(Ok(sym_name)) => sym_name == name,
+                    _ => false,
+                })
+                .map(|sym| sym.st_value as u32)
+        };
+        find("tohost").map(|tohost| Htif {
+            tohost,
+            fromhost: find("fromhost"),
+            exit_code: None,
+        })
+    }
+
+    /// Exit code sent by the program, once it has sent one.
+    pub fn exit_code(&self) -> Option<u32> {
+        self.exit_code
+    }
+
+    /// Handles a store to `addr` after memory has been updated.
+    pub fn store(&mut self, addr: u32, mem: &mut ProcessMemory) -> Result<(), Exception> {
+        if addr.wrapping_sub(self.tohost) != 4 {
+            return Ok(());
+        }
+        let command = mem.read::<u64>(self.tohost)?;
+        if command == 0 {
+            return Ok(());
+        }
+        let device = command >> 56;
+        let cmd = (command >> 48) & 0xff;
+        let payload = command & 0xffff_ffff_ffff;
+
+        let response = match (device, cmd) {
+            (0, 0) if payload & 1 == 1 => {
+                self.exit_code = Some((payload >> 1) as u32);
+                None
+            }
+            (0, 0) => {
+                self.proxy_syscall(payload as u32, mem)?;
+                Some(1)
+            }
+            (1, 1) => {
+                let mut stdout = std::io::stdout();
+                stdout.write_all(&[payload as u8]).unwrap();
+                stdout.flush().unwrap();
+                Some((device << 56) | (cmd << 48) | 0x100 | (payload & 0xff))
+            }
+            _ => None,
+        };
+        mem.write(self.tohost, 0u64)?;
+        if let (Some(fromhost), Some(response)) = (self.fromhost, response) {
+            mem.write(fromhost, response)?;
+        }
+        Ok(())
+    }
+
+    fn proxy_syscall(&mut self, magic_mem: u32, mem: &mut ProcessMemory) -> Result<(), Exception> {
+        let mut args = [0u64; 8];
+        for (i, arg) in args.iter_mut().enumerate() {
+            *arg = mem.read::<u64>(magic_mem.wrapping_add(8 * i as u32))?;
+        }
+        let ret_val = match args[0] {
+            SYS_WRITE => {
+                let bytes = mem.read_bytes(args[2] as u32, args[3] as usize)?;
+                match nix::unistd::write(args[1] as i32, bytes) {
+                    Ok(written) => written as u64,
+                    Err(_) => u64::MAX,
+                }
+            }
+            SYS_EXIT => {
+                self.exit_code = Some(args[1] as u32);
+                0
+            }
+            _ => u64::from(ENOSYS).wrapping_neg(),
+        };
+        mem.write(magic_mem, ret_val)
+    }
+}
+
+/// `tohost` and `fromhost` come from the ELF and aren't saved.
+impl Snapshot for Htif {
+    fn save(&self, out: &mut Encoder) {
+        out.bool(self.exit_code.is_some());
+        out.u32(self.exit_code.unwrap_or(0));
+    }
+
+    fn restore(&mut self, input: &mut Decoder) -> io::Result<()> {
+        let has_exited = input.bool()?;
+        let exit_code = input.u32()?;
+        self.exit_code = if has_exited { Some(exit_code) } else { None };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exit_is_taken_on_upper_word() {
+        let mut mem = ProcessMemory::default();
+        mem.data.resize(0x100, 0);
+        mem.v_address_range = (0, 0x100);
+        mem.stack_range = (0xffff_0000, 0);
+        let mut htif = Htif {
+            tohost: 0x40,
+            fromhost: Some(0x48),
+            exit_code: None,
+        };
+
+        // riscv-tests의 실패 보고: (TESTNUM << 1) | 1
+        mem.write(0x40, 7u32).unwrap();
+        htif.store(0x40, &mut mem).unwrap();
+        assert_eq!(htif.exit_code(), None);
+        mem.write(0x44, 0u32).unwrap();
+        htif.store(0x44, &mut mem).unwrap();
+        assert_eq!(htif.exit_code(), Some(3));
+        assert_eq!(mem.read::<u64>(0x40).unwrap(), 0);
+    }
+
+    #[test]
+    fn syscall_block_at_the_top_of_memory_faults() {
+        let mut mem = ProcessMemory::default();
+        mem.data.resize(0x100, 0);
+        mem.v_address_range = (0x1000, 0x1100);
+        mem.stack.resize(0x1_0000, 0);
+        mem.stack_range = (0xffff_0000, 0);
+        let mut htif = Htif {
+            tohost: 0x1040,
+            fromhost: None,
+            exit_code: None,
+        };
+
+        // 두 번째 인자부터는 주소가 0으로 돌아가 읽을 수 없음
+        mem.write(0x1040, 0xffff_fff8u32).unwrap();
+        mem.write(0x1044, 0u32).unwrap();
+        assert!(htif.store(0x1044, &mut mem).is_err());
+    }
+}
@@ -0,0 +1,91 @@
+//! Memory-mapped I/O.
+//!
+//! Devices are mapped to physical address ranges on a bus that
+//! `ProcessMemory` checks before RAM. Reading a device register may have side
+//! effects, so loads from the bus only run at the ROB head. The CLINT is wired
+//! to the harts directly and isn't on this bus.
+
+use checkpoint::{invalid, Decoder, Encoder, Snapshot};
+use std::fmt;
+use std::io;
+
+/// A device with registers on the MMIO bus. Offsets are relative to the
+/// start of the device's range.
+pub trait Device: fmt::Debug + Snapshot {
+    fn read(&mut self, offset: u32, size: usize) -> u32;
+    fn write(&mut self, offset: u32, size: usize, value: u32);
+}
+
+#[derive(Debug)]
+struct Mapping {
+    base: u32,
+    size: u32,
+    device: Box<dyn Device>,
+}
+
+impl Mapping {
+    fn contains(&self, addr: u32) -> bool {
+        addr.wrapping_sub(self.base) < self.size
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MmioBus {
+    mappings: Vec<Mapping>,
+}
+
+impl MmioBus {
+    /// Maps `device` to `size` bytes starting at `base`.
+    pub fn map(&mut self, base: u32, size: u32, device: Box<dyn Device>) {
+        self.mappings.push(Mapping { base, size, device });
+    }
+
+    pub fn contains(&self, addr: u32) -> bool {
+        self.mappings.iter().any(|mapping| mapping.contains(addr))
+    }
+
+    fn find(&mut self, addr: u32) -> Option<&mut Mapping> {
+        self.mappings
+            .iter_mut()
+            .find(|mapping| mapping.contains(addr))
+    }
+
+    /// Reads a device register, or returns `None` if nothing is mapped at
+    /// `addr`.
+    pub fn read(&mut self, addr: u32, size: usize) -> Option<u32> {
+        self.find(addr)
+            .map(|mapping| mapping.device.read(addr - mapping.base, size))
+    }
+
+    /// Writes a device register, or returns `None` if nothing is mapped at
+    /// `addr`.
+    pub fn write(&mut self, addr: u32, size: usize, value: u32) -> Option<()> {
+        self.find(addr)
+            .map(|mapping| mapping.device.write(addr - mapping.base, size, value))
+    }
+}
+
+/// The devices are restored in the order they were mapped, so the bus has
+/// to be set up the same way as when the checkpoint was taken.
+impl Snapshot for MmioBus {
+    fn save(&self, out: &mut Encoder) {
+        out.usize(self.mappings.len());
+        for mapping in self.mappings.iter() {
+            out.u32(mapping.base);
+            mapping.device.save(out);
+        }
+    }
+
+    fn restore(&mut self, input: &mut Decoder) -> io::Result<()> {
+        if input.usize()? != self.mappings.len() {
+            return Err(invalid("checkpoint has different devices"));
+        }
+        for mapping in self.mappings.iter_mut() {
+            if input.u32()? != mapping.base {
+                return Err(invalid("checkpoint has different devices"));
+            }
+            mapping.device.restore(input)?;
+        }
+        Ok(())
+    }
+}
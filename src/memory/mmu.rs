@@ -0,0 +1,701 @@
+//! Sv32 address translation.
+//!
+//! Separate instruction and data TLBs sit in front of a single hardware
+//! page-table walker. The walker reads page-table entries from DRAM, bypassing
+//! the L1, one level at a time, so a TLB miss costs real memory latency.
+//! Accessed and dirty bits are never updated by hardware: a leaf with A clear,
+//! or with D clear on a store, raises a page fault for the guest to handle.
+//! M-mode accesses are never translated. Physical addresses are then checked
+//! against the PMP, and so is every page-table entry the walker reads, as an
+//! S-mode load. A walk the PMP stops raises an access fault.
+
+use csr::{CsrFile, Privilege, MSTATUS_MXR, MSTATUS_SUM, SATP_PPN_MASK};
+use memory::dram::Requester;
+use memory::pmp::Pmp;
+use memory::system::MemorySystem;
+use memory::ProcessMemory;
+use pipeline::exception::Exception;
+use std::collections::VecDeque;
+use std::fmt;
+
+const PAGE_SHIFT: u32 = 12;
+const PAGE_OFFSET_MASK: u32 = 0xfff;
+const MEGAPAGE_OFFSET_MASK: u32 = 0x003f_ffff;
+const VPN0_MASK: u32 = 0x3ff;
+const PTE_SIZE: u32 = 4;
+
+const PTE_V: u32 = 1 << 0;
+const PTE_R: u32 = 1 << 1;
+const PTE_W: u32 = 1 << 2;
+const PTE_X: u32 = 1 << 3;
+const PTE_U: u32 = 1 << 4;
+const PTE_A: u32 = 1 << 6;
+const PTE_D: u32 = 1 << 7;
+const PTE_PPN_SHIFT: u32 = 10;
+
+/// Kind of memory access being translated.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AccessType {
+    Fetch,
+    Load,
+    /// Stores and AMOs.
+    Store,
+}
+
+impl AccessType {
+    fn page_fault(self, vaddr: u32) -> Exception {
+        match self {
+            AccessType::Fetch => Exception::InstructionPageFault(vaddr),
+            AccessType::Load => Exception::LoadPageFault(vaddr),
+            AccessType::Store => Exception::StorePageFault(vaddr),
+        }
+    }
+
+    fn access_fault(self, vaddr: u32) -> Exception {
+        match self {
+            AccessType::Fetch => Exception::InstructionAccessFault(vaddr),
+            AccessType::Load => Exception::LoadAccessFault(vaddr),
+            AccessType::Store => Exception::StoreAccessFault(vaddr),
+        }
+    }
+
+    fn tlb_kind(self) -> TlbKind {
+        match self {
+            AccessType::Fetch => TlbKind::Instruction,
+            _ => TlbKind::Data,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TlbKind {
+    Instruction,
+    Data,
+}
+
+/// Outcome of a translation attempt.
+#[derive(Debug, Clone, Copy)]
+pub enum Translation {
+    Done(u32),
+    /// The TLB missed and the walker hasn't finished yet; retry later.
+    Pending,
+    Fault(Exception),
+}
+
+#[derive(Debug, Clone)]
+pub struct MmuConfig {
+    pub itlb_entries: usize,
+    pub dtlb_entries: usize,
+}
+
+impl Default for MmuConfig {
+    fn default() -> Self {
+        MmuConfig {
+            itlb_entries: 32,
+            dtlb_entries: 32,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TlbEntry {
+    vpn: u32,
+    megapage: bool,
+    ppn: u32,
+    flags: u32,
+    last_used: usize,
+}
+
+impl TlbEntry {
+    fn matches(&self, vpn: u32) -> bool {
+        if self.megapage {
+            self.vpn >> 10 == vpn >> 10
+        } else {
+            self.vpn == vpn
+        }
+    }
+
+    fn physical_address(&self, vaddr: u32) -> u32 {
+        if self.megapage {
+            ((self.ppn >> 10) << 22) | (vaddr & MEGAPAGE_OFFSET_MASK)
+        } else {
+            (self.ppn << PAGE_SHIFT) | (vaddr & PAGE_OFFSET_MASK)
+        }
+    }
+
+    fn allows(&self, access: AccessType, csr: &CsrFile) -> bool {
+        let is_user_page = self.flags & PTE_U != 0;
+        let privilege_ok = match csr.privilege {
+            Privilege::User => is_user_page,
+            // S-mode은 SUM이 켜져 있을 때만 user page에 load/store 가능
+            _ => {
+                !is_user_page || (access != AccessType::Fetch && csr.mstatus & MSTATUS_SUM != 0)
+            }
+        };
+        let readable = self.flags & PTE_R != 0
+            || (csr.mstatus & MSTATUS_MXR != 0 && self.flags & PTE_X != 0);
+        let needed = match access {
+            AccessType::Fetch => PTE_X | PTE_A,
+            AccessType::Load => PTE_A,
+            AccessType::Store => PTE_W | PTE_A | PTE_D,
+        };
+        privilege_ok
+            && self.flags & needed == needed
+            && (access != AccessType::Load || readable)
+    }
+}
+
+/// A fully associative TLB with LRU replacement.
+#[derive(Debug, Clone)]
+pub struct Tlb {
+    capacity: usize,
+    entries: Vec<TlbEntry>,
+    use_counter: usize,
+}
+
+impl Tlb {
+    pub fn new(capacity: usize) -> Self {
+        Tlb {
+            capacity: std::cmp::max(capacity, 1),
+            entries: Vec::new(),
+            use_counter: 0,
+        }
+    }
+
+    fn lookup(&mut self, vpn: u32) -> Option<TlbEntry> {
+        self.use_counter += 1;
+        let counter = self.use_counter;
+        self.entries
+            .iter_mut()
+            .find(|entry| entry.matches(vpn))
+            .map(|entry| {
+                entry.last_used = counter;
+                *entry
+            })
+    }
+
+    fn insert(&mut self, entry: TlbEntry) {
+        if self.entries.len() >= self.capacity {
+            let victim = self
+                .entries
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(idx, _)| idx)
+                .unwrap();
+            self.entries.swap_remove(victim);
+        }
+        self.entries.push(entry);
+    }
+
+    pub fn flush(&mut self) {
+        self.entries.clear();
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct MmuStats {
+    pub itlb_hits: usize,
+    pub itlb_misses: usize,
+    pub dtlb_hits: usize,
+    pub dtlb_misses: usize,
+    pub walk_mem_accesses: usize,
+    pub walks_done: usize,
+    pub total_walk_latency: usize,
+    pub page_faults: usize,
+}
+
+impl fmt::Display for MmuStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let avg_walk_latency = if self.walks_done == 0 {
+            0.0
+        } else {
+            self.total_walk_latency as f64 / self.walks_done as f64
+        };
+        write!(
+            f,
+            "ITLB hits: {}, ITLB misses: {}, DTLB hits: {}, DTLB misses: {}, walk memory accesses: {}, avg walk latency: {:.2}, page faults: {}",
+            self.itlb_hits,
+            self.itlb_misses,
+            self.dtlb_hits,
+            self.dtlb_misses,
+            self.walk_mem_accesses,
+            avg_walk_latency,
+            self.page_faults
+        )
+    }
+}
+
+/// Why a walk failed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum WalkFault {
+    Page,
+    /// The PMP denied reading a page-table entry.
+    Access,
+}
+
+#[derive(Debug, Clone)]
+struct Walk {
+    id: usize,
+    vpn: u32,
+    kind: TlbKind,
+    level: u32,
+    pte_addr: u32,
+    started: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct Mmu {
+    pub itlb: Tlb,
+    pub dtlb: Tlb,
+    walk: Option<Walk>,
+    walk_queue: VecDeque<(u32, TlbKind)>,
+    /// Walks that ended in a fault, waiting to be picked up by `translate`.
+    failed_walks: Vec<(u32, TlbKind, WalkFault)>,
+    next_walk_id: usize,
+    clock: usize,
+    pub stats: MmuStats,
+}
+
+impl Default for Mmu {
+    fn default() -> Self {
+        Mmu::new(MmuConfig::default())
+    }
+}
+
+impl Mmu {
+    pub fn new(config: MmuConfig) -> Self {
+        Mmu {
+            itlb: Tlb::new(config.itlb_entries),
+            dtlb: Tlb::new(config.dtlb_entries),
+            walk: None,
+            walk_queue: VecDeque::new(),
+            failed_walks: Vec::new(),
+            next_walk_id: 0,
+            clock: 0,
+            stats: Default::default(),
+        }
+    }
+
+    /// Translates `vaddr` under the current `satp` and privilege level. On a
+    /// TLB miss a walk is queued and `Pending` is returned until it completes.
+    /// Accesses the PMP denies fault.
+    pub fn translate(&mut self, vaddr: u32, access: AccessType, csr: &CsrFile) -> Translation {
+        match self.translate_page(vaddr, access, csr) {
+            Translation::Done(paddr) if !csr.pmp.allows(paddr, access, csr.privilege) => {
+                Translation::Fault(access.access_fault(vaddr))
+            }
+            translation => translation,
+        }
+    }
+
+    /// Translates `vaddr` at once by walking the page table in `memory`,
+    /// bypassing the TLBs and taking no time, as fast-forwarding does.
+    pub fn translate_now(
+        vaddr: u32,
+        access: AccessType,
+        csr: &CsrFile,
+        memory: &ProcessMemory,
+    ) -> Result<u32, Exception> {
+        let paddr = if csr.is_translating() {
+            Self::walk_now(vaddr, access, csr, memory)?
+        } else {
+            vaddr
+        };
+        if csr.pmp.allows(paddr, access, csr.privilege) {
+            Ok(paddr)
+        } else {
+            Err(access.access_fault(vaddr))
+        }
+    }
+
+    fn walk_now(
+        vaddr: u32,
+        access: AccessType,
+        csr: &CsrFile,
+        memory: &ProcessMemory,
+    ) -> Result<u32, Exception> {
+        let fault = access.page_fault(vaddr);
+        let vpn = vaddr >> PAGE_SHIFT;
+        let root = (csr.satp & SATP_PPN_MASK) << PAGE_SHIFT;
+        let mut pte_addr = root.wrapping_add((vpn >> 10) * PTE_SIZE);
+        for &megapage in [true, false].iter() {
+            if !csr.pmp.allows(pte_addr, AccessType::Load, Privilege::Supervisor) {
+                return Err(access.access_fault(vaddr));
+            }
+            let pte = memory.read::<u32>(pte_addr).map_err(|_| fault)?;
+            if pte & PTE_V == 0 || (pte & PTE_R == 0 && pte & PTE_W != 0) {
+                return Err(fault);
+            }
+            let ppn = pte >> PTE_PPN_SHIFT;
+            if pte & (PTE_R | PTE_X) != 0 {
+                if megapage && ppn & VPN0_MASK != 0 {
+                    return Err(fault);
+                }
+                let entry = TlbEntry {
+                    vpn,
+                    megapage,
+                    ppn,
+                    flags: pte & 0xff,
+                    last_used: 0,
+                };
+                return if entry.allows(access, csr) {
+                    Ok(entry.physical_address(vaddr))
+                } else {
+                    Err(fault)
+                };
+            }
+            pte_addr = (ppn << PAGE_SHIFT).wrapping_add((vpn & VPN0_MASK) * PTE_SIZE);
+        }
+        Err(fault)
+    }
+
+    fn translate_page(&mut self, vaddr: u32, access: AccessType, csr: &CsrFile) -> Translation {
+        if !csr.is_translating() {
+            return Translation::Done(vaddr);
+        }
+
+        let vpn = vaddr >> PAGE_SHIFT;
+        let kind = access.tlb_kind();
+        let hit = match kind {
+            TlbKind::Instruction => self.itlb.lookup(vpn),
+            TlbKind::Data => self.dtlb.lookup(vpn),
+        };
+        if let Some(entry) = hit {
+            match kind {
+                TlbKind::Instruction => self.stats.itlb_hits += 1,
+                TlbKind::Data => self.stats.dtlb_hits += 1,
+            }
+            return if entry.allows(access, csr) {
+                Translation::Done(entry.physical_address(vaddr))
+            } else {
+                self.stats.page_faults += 1;
+                Translation::Fault(access.page_fault(vaddr))
+            };
+        }
+
+        let failed = self
+            .failed_walks
+            .iter()
+            .position(|&(failed_vpn, failed_kind, _)| (failed_vpn, failed_kind) == (vpn, kind));
+        if let Some(pos) = failed {
+            return match self.failed_walks.remove(pos).2 {
+                WalkFault::Page => {
+                    self.stats.page_faults += 1;
+                    Translation::Fault(access.page_fault(vaddr))
+                }
+                WalkFault::Access => Translation::Fault(access.access_fault(vaddr)),
+            };
+        }
+
+        let is_walking = self
+            .walk
+            .as_ref()
+            .is_some_and(|walk| walk.vpn == vpn && walk.kind == kind)
+            || self.walk_queue.contains(&(vpn, kind));
+        if !is_walking {
+            match kind {
+                TlbKind::Instruction => self.stats.itlb_misses += 1,
+                TlbKind::Data => self.stats.dtlb_misses += 1,
+            }
+            self.walk_queue.push_back((vpn, kind));
+        }
+        Translation::Pending
+    }
+
+    /// Advances the page-table walker of `hart` by one clock.
+    pub fn tick(
+        &mut self,
+        clock: usize,
+        hart: usize,
+        arrived: &[Requester],
+        mem_sys: &mut MemorySystem,
+        csr: &CsrFile,
+    ) {
+        self.clock = clock;
+        let pte_arrived = self
+            .walk
+            .as_ref()
+            .is_some_and(|walk| arrived.contains(&Requester::PageWalk(walk.id)));
+        if pte_arrived {
+            self.step_walk(hart, mem_sys, &csr.pmp);
+        }
+
+        if self.walk.is_none() {
+            if let Some((vpn, kind)) = self.walk_queue.pop_front() {
+                let root = (csr.satp & SATP_PPN_MASK) << PAGE_SHIFT;
+                let walk = Walk {
+                    id: 0,
+                    vpn,
+                    kind,
+                    level: 1,
+                    pte_addr: root.wrapping_add((vpn >> 10) * PTE_SIZE),
+                    started: clock,
+                };
+                self.request_pte(walk, hart, mem_sys, &csr.pmp);
+            }
+        }
+    }
+
+    fn request_pte(&mut self, mut walk: Walk, hart: usize, mem_sys: &mut MemorySystem, pmp: &Pmp) {
+        // page table 읽기도 S-mode load로 PMP 검사를 받음
+        if !pmp.allows(walk.pte_addr, AccessType::Load, Privilege::Supervisor) {
+            return self.finish_walk(&walk, Err(WalkFault::Access));
+        }
+        walk.id = self.next_walk_id;
+        self.next_walk_id += 1;
+        self.stats.walk_mem_accesses += 1;
+        mem_sys.read_uncached(hart, Requester::PageWalk(walk.id), walk.pte_addr);
+        self.walk = Some(walk);
+    }
+
+    fn step_walk(&mut self, hart: usize, mem_sys: &mut MemorySystem, pmp: &Pmp) {
+        let mut walk = self.walk.take().unwrap();
+        let pte = match mem_sys.memory.read::<u32>(walk.pte_addr) {
+            Ok(pte) => pte,
+            Err(_) => return self.finish_walk(&walk, Err(WalkFault::Page)),
+        };
+
+        if pte & PTE_V == 0 || (pte & PTE_R == 0 && pte & PTE_W != 0) {
+            return self.finish_walk(&walk, Err(WalkFault::Page));
+        }
+
+        let ppn = pte >> PTE_PPN_SHIFT;
+        if pte & (PTE_R | PTE_X) != 0 {
+            let megapage = walk.level == 1;
+            if megapage && ppn & VPN0_MASK != 0 {
+                return self.finish_walk(&walk, Err(WalkFault::Page));
+            }
+            let entry = TlbEntry {
+                vpn: walk.vpn,
+                megapage,
+                ppn,
+                flags: pte & 0xff,
+                last_used: 0,
+            };
+            return self.finish_walk(&walk, Ok(entry));
+        }
+
+        if walk.level == 0 {
+            return self.finish_walk(&walk, Err(WalkFault::Page));
+        }
+        walk.level -= 1;
+        walk.pte_addr = (ppn << PAGE_SHIFT).wrapping_add((walk.vpn & VPN0_MASK) * PTE_SIZE);
+        self.request_pte(walk, hart, mem_sys, pmp);
+    }
+
+    fn finish_walk(&mut self, walk: &Walk, entry: Result<TlbEntry, WalkFault>) {
+        self.stats.walks_done += 1;
+        self.stats.total_walk_latency += self.clock - walk.started;
+        match (entry, walk.kind) {
+            (Ok(entry), TlbKind::Instruction) => self.itlb.insert(entry),
+            (Ok(entry), TlbKind::Data) => self.dtlb.insert(entry),
+            (Err(fault), kind) => self.failed_walks.push((walk.vpn, kind, fault)),
+        }
+    }
+
+    /// Drops every cached translation, as `sfence.vma` does.
+    pub fn flush(&mut self) {
+        self.itlb.flush();
+        self.dtlb.flush();
+        self.walk = None;
+        self.walk_queue.clear();
+        self.failed_walks.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use csr::{PMPADDR0, PMPCFG0, SATP_MODE_SV32};
+    use memory::ProcessMemory;
+
+    const ROOT: u32 = 0x2000;
+    const LEAF_TABLE: u32 = 0x3000;
+
+    /// Maps virtual page 0x40001 to physical page 0x5 through a two-level
+    /// table, and megapage 0x2 to physical 0x0040_0000.
+    fn page_tables() -> MemorySystem {
+        let mut mem = ProcessMemory::default();
+        mem.data.resize(0x10000, 0);
+        mem.v_address_range = (0, 0x10000);
+        mem.stack_range = (0xffff_0000, 0);
+        let pointer = ((LEAF_TABLE >> PAGE_SHIFT) << PTE_PPN_SHIFT) | PTE_V;
+        mem.write(ROOT + (0x40001 >> 10) * 4, pointer).unwrap();
+        let leaf = (0x5 << PTE_PPN_SHIFT) | PTE_V | PTE_R | PTE_A;
+        mem.write(LEAF_TABLE + (0x40001 & VPN0_MASK) * 4, leaf)
+            .unwrap();
+        let mega = (0x400 << PTE_PPN_SHIFT) | PTE_V | PTE_R | PTE_W | PTE_X | PTE_A | PTE_D;
+        mem.write(ROOT + 2 * 4, mega).unwrap();
+        MemorySystem::new(
+            mem,
+            1,
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        )
+    }
+
+    fn supervisor_csr() -> CsrFile {
+        let mut csr = CsrFile::default();
+        // PMP로 전체 메모리를 열어 둠
+        csr.write(PMPADDR0, !0).unwrap();
+        csr.write(PMPCFG0, 0x1f).unwrap();
+        csr.privilege = Privilege::Supervisor;
+        csr.satp = SATP_MODE_SV32 | (ROOT >> PAGE_SHIFT);
+        csr
+    }
+
+    fn translate_blocking(
+        mmu: &mut Mmu,
+        mem_sys: &mut MemorySystem,
+        vaddr: u32,
+        access: AccessType,
+    ) -> Translation {
+        let csr = supervisor_csr();
+        for clock in 0..10000 {
+            match mmu.translate(vaddr, access, &csr) {
+                Translation::Pending => {}
+                result => return result,
+            }
+            let arrived = mem_sys.tick(clock);
+            mmu.tick(clock, 0, &arrived[0], mem_sys, &csr);
+        }
+        panic!("walk never finished");
+    }
+
+    #[test]
+    fn walk_fills_tlb() {
+        let mut mem_sys = page_tables();
+        let mut mmu = Mmu::default();
+
+        let vaddr = 0x4000_1234;
+        match translate_blocking(&mut mmu, &mut mem_sys, vaddr, AccessType::Load) {
+            Translation::Done(paddr) => assert_eq!(paddr, 0x5234),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(mmu.stats.dtlb_misses, 1);
+        assert_eq!(mmu.stats.walk_mem_accesses, 2);
+
+        match mmu.translate(vaddr + 4, AccessType::Load, &supervisor_csr()) {
+            Translation::Done(paddr) => assert_eq!(paddr, 0x5238),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(mmu.stats.dtlb_hits, 2);
+    }
+
+    #[test]
+    fn megapage_translation() {
+        let mut mem_sys = page_tables();
+        let mut mmu = Mmu::default();
+        match translate_blocking(&mut mmu, &mut mem_sys, 0x0081_2345, AccessType::Fetch) {
+            Translation::Done(paddr) => assert_eq!(paddr, 0x0041_2345),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(mmu.stats.walk_mem_accesses, 1);
+    }
+
+    #[test]
+    fn permission_and_invalid_faults() {
+        let mut mem_sys = page_tables();
+        let mut mmu = Mmu::default();
+        match translate_blocking(&mut mmu, &mut mem_sys, 0x4000_1000, AccessType::Store) {
+            Translation::Fault(Exception::StorePageFault(addr)) => assert_eq!(addr, 0x4000_1000),
+            other => panic!("unexpected {:?}", other),
+        }
+        match translate_blocking(&mut mmu, &mut mem_sys, 0x1000_0000, AccessType::Fetch) {
+            Translation::Fault(Exception::InstructionPageFault(_)) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(mmu.stats.page_faults, 2);
+    }
+
+    #[test]
+    fn translate_now_walks_in_memory() {
+        let mem_sys = page_tables();
+        let csr = supervisor_csr();
+        let translate = |vaddr, access| Mmu::translate_now(vaddr, access, &csr, &mem_sys.memory);
+        assert_eq!(translate(0x4000_1234, AccessType::Load).ok(), Some(0x5234));
+        assert_eq!(translate(0x0081_2345, AccessType::Fetch).ok(), Some(0x0041_2345));
+        match translate(0x4000_1000, AccessType::Store) {
+            Err(Exception::StorePageFault(addr)) => assert_eq!(addr, 0x4000_1000),
+            other => panic!("unexpected {:?}", other),
+        }
+        match translate(0x1000_0000, AccessType::Fetch) {
+            Err(Exception::InstructionPageFault(_)) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn bare_mode_is_identity() {
+        let mut mmu = Mmu::default();
+        match mmu.translate(0x1234_5678, AccessType::Load, &CsrFile::default()) {
+            Translation::Done(paddr) => assert_eq!(paddr, 0x1234_5678),
+            other => panic!("unexpected {:?}", other),
+        }
+        // M-mode는 satp와 상관없이 번역하지 않음
+        let csr = CsrFile {
+            privilege: Privilege::Machine,
+            ..supervisor_csr()
+        };
+        match mmu.translate(0x4000_1234, AccessType::Load, &csr) {
+            Translation::Done(paddr) => assert_eq!(paddr, 0x4000_1234),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn page_table_reads_are_pmp_checked() {
+        let mut mem_sys = page_tables();
+        let mut mmu = Mmu::default();
+        // 0x40001의 root PTE만 NA4 entry로 막음
+        let mut csr = CsrFile::default();
+        csr.write(PMPADDR0, (ROOT + (0x40001 >> 10) * 4) >> 2).unwrap();
+        csr.write(PMPADDR0 + 1, !0).unwrap();
+        csr.write(PMPCFG0, 0x1f10).unwrap();
+        let csr = CsrFile {
+            pmp: csr.pmp,
+            ..supervisor_csr()
+        };
+
+        let mut translation = Translation::Pending;
+        for clock in 0..10000 {
+            translation = mmu.translate(0x4000_1234, AccessType::Store, &csr);
+            if !matches!(translation, Translation::Pending) {
+                break;
+            }
+            let arrived = mem_sys.tick(clock);
+            mmu.tick(clock, 0, &arrived[0], &mut mem_sys, &csr);
+        }
+        match translation {
+            Translation::Fault(Exception::StoreAccessFault(addr)) => assert_eq!(addr, 0x4000_1234),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(mmu.stats.walk_mem_accesses, 0);
+        match Mmu::translate_now(0x4000_1234, AccessType::Load, &csr, &mem_sys.memory) {
+            Err(Exception::LoadAccessFault(addr)) => assert_eq!(addr, 0x4000_1234),
+            other => panic!("unexpected {:?}", other),
+        }
+        // 막지 않은 megapage는 그대로 번역됨
+        assert_eq!(
+            Mmu::translate_now(0x0081_2345, AccessType::Fetch, &csr, &mem_sys.memory).ok(),
+            Some(0x0041_2345)
+        );
+    }
+
+    #[test]
+    fn user_bit_is_checked() {
+        let mut mem_sys = page_tables();
+        let mut mmu = Mmu::default();
+        translate_blocking(&mut mmu, &mut mem_sys, 0x0080_0000, AccessType::Load);
+        let user = CsrFile {
+            privilege: Privilege::User,
+            ..supervisor_csr()
+        };
+        match mmu.translate(0x0080_0000, AccessType::Load, &user) {
+            Translation::Fault(Exception::LoadPageFault(_)) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
@@ -5,7 +5,16 @@ use goblin::elf32::program_header::ProgramHeader as Elf32ProgramHeader;
 use pipeline::exception::Exception;
 use std::mem::size_of;
 
+pub mod cache;
+pub mod clint;
 mod consts;
+pub mod dram;
+pub mod htif;
+pub mod mmio;
+pub mod mmu;
+pub mod pmp;
+pub mod system;
+pub mod uart;
 
 #[repr(C)]
 struct AuxVec {
@@ -27,6 +36,8 @@ pub struct ProcessMemory {
     pub data: Vec<u8>,
     pub stack: Vec<u8>,
     pub stack_pointer_init: u32,
+    /// Devices mapped over the address space.
+    pub mmio: mmio::MmioBus,
 }
 
 impl ProcessMemory {
@@ -166,10 +177,7 @@ impl ProcessMemory {
     }
 
     pub fn read_inst(&self, addr: u32) -> Result<u32, Exception> {
-        self.check_address_space(addr)?;
-
-        let offset = (addr - self.v_address_range.0) as usize;
-        let mut data = &(self.data[offset..offset + 4]);
+        let mut data = self.read_bytes(addr, 4)?;
         Ok(data
             .read_u32::<LittleEndian>()
             .expect("Can't read memory as u32 instruction"))
@@ -183,7 +191,12 @@ impl ProcessMemory {
         data_ptr
     }
 
+    /// Borrows memory as bytes. Device registers can't be borrowed, so loads
+    /// from them go through `read_device`.
     pub fn read_bytes(&self, addr: u32, size: usize) -> Result<&[u8], Exception> {
+        if self.mmio.contains(addr) {
+            return Err(Exception::LoadAccessFault(addr));
+        }
         self.check_address_space(addr)?;
 
         let buf;
@@ -194,10 +207,14 @@ impl ProcessMemory {
             buf = &self.stack;
             (addr - self.stack_range.0) as usize
         };
-        Ok(&buf[offset..offset + size])
+        buf.get(offset..offset + size)
+            .ok_or(Exception::WritingToInvalidMemory(addr))
     }
 
     pub fn read_bytes_mut(&mut self, addr: u32, size: usize) -> Result<&mut [u8], Exception> {
+        if self.mmio.contains(addr) {
+            return Err(Exception::LoadAccessFault(addr));
+        }
         self.check_address_space(addr)?;
 
         let buf;
@@ -208,7 +225,8 @@ impl ProcessMemory {
             buf = &mut self.stack;
             (addr - self.stack_range.0) as usize
         };
-        Ok(&mut buf[offset..offset + size])
+        buf.get_mut(offset..offset + size)
+            .ok_or(Exception::WritingToInvalidMemory(addr))
     }
 
     pub fn write<T>(&mut self, addr: u32, value: T) -> Result<(), Exception> {
@@ -218,12 +236,33 @@ impl ProcessMemory {
         self.write_slice(addr, byte_slice)
     }
 
+    /// Reads a device register of `size` bytes.
+    pub fn read_device(&mut self, addr: u32, size: usize) -> Result<u32, Exception> {
+        self.mmio
+            .read(addr, size)
+            .ok_or(Exception::LoadAccessFault(addr))
+    }
+
     pub fn write_slice<T>(&mut self, addr: u32, value: &[T]) -> Result<(), Exception> {
+        let data_size = size_of::<T>();
+        if self.mmio.contains(addr) {
+            let ptr = value.as_ptr() as *const u8;
+            let bytes = unsafe { std::slice::from_raw_parts(ptr, std::mem::size_of_val(value)) };
+            if bytes.len() > 4 {
+                return Err(Exception::StoreAccessFault(addr));
+            }
+            let value = bytes
+                .iter()
+                .rev()
+                .fold(0, |value, &byte| (value << 8) | u32::from(byte));
+            return self
+                .mmio
+                .write(addr, bytes.len(), value)
+                .ok_or(Exception::StoreAccessFault(addr));
+        }
         self.check_address_space(addr)?;
         self.check_write_address_space(addr)?;
 
-        let data_size = size_of::<T>();
-
         let data;
         if addr < self.stack_range.0 {
             let offset = (addr - self.v_address_range.0) as usize;
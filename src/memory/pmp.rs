@@ -0,0 +1,184 @@
+//! Physical memory protection.
+//!
+//! Sixteen entries, each a `pmpaddr` register and a byte of `pmpcfg`, are
+//! matched in order against the physical address of every fetch, load, store
+//! and AMO. The first matching entry decides. S-mode and U-mode accesses that
+//! match no entry fail, while M-mode is only held to locked entries. Regions
+//! have a 4-byte granule, so an access is checked a word at a time.
+
+use checkpoint::{Decoder, Encoder, Snapshot};
+use csr::Privilege;
+use memory::mmu::AccessType;
+use std::io;
+
+pub const PMP_ENTRIES: usize = 16;
+
+const PMP_R: u8 = 1 << 0;
+const PMP_W: u8 = 1 << 1;
+const PMP_X: u8 = 1 << 2;
+const PMP_A_SHIFT: u8 = 3;
+const PMP_L: u8 = 1 << 7;
+const PMP_WRITABLE: u8 = PMP_R | PMP_W | PMP_X | (0b11 << PMP_A_SHIFT) | PMP_L;
+
+/// Address-matching modes of the A field. Zero turns the entry off.
+const PMP_TOR: u8 = 1;
+const PMP_NA4: u8 = 2;
+const PMP_NAPOT: u8 = 3;
+
+#[derive(Debug, Default, Clone)]
+pub struct Pmp {
+    cfg: [u8; PMP_ENTRIES],
+    /// Bits 33:2 of each address, as in `pmpaddr`.
+    addr: [u32; PMP_ENTRIES],
+}
+
+impl Pmp {
+    /// Reads `pmpcfg<n>`, which packs the configuration of four entries.
+    pub fn read_cfg(&self, n: usize) -> u32 {
+        self.cfg[4 * n..4 * n + 4]
+            .iter()
+            .rev()
+            .fold(0, |word, &cfg| (word << 8) | u32::from(cfg))
+    }
+
+    /// Writes `pmpcfg<n>`, skipping locked entries.
+    pub fn write_cfg(&mut self, n: usize, value: u32) {
+        for (i, cfg) in self.cfg[4 * n..4 * n + 4].iter_mut().enumerate() {
+            if *cfg & PMP_L != 0 {
+                continue;
+            }
+            let mut new_cfg = (value >> (8 * i)) as u8 & PMP_WRITABLE;
+            // W만 켜진 조합은 예약되어 있음
+            if new_cfg & PMP_R == 0 {
+                new_cfg &= !PMP_W;
+            }
+            *cfg = new_cfg;
+        }
+    }
+
+    pub fn read_addr(&self, i: usize) -> u32 {
+        self.addr[i]
+    }
+
+    /// Writes `pmpaddr<i>` unless the entry is locked, or it is the lower
+    /// bound of a locked TOR entry.
+    pub fn write_addr(&mut self, i: usize, value: u32) {
+        let is_locked = self.cfg[i] & PMP_L != 0;
+        let bounds_locked_tor = self
+            .cfg
+            .get(i + 1)
+            .is_some_and(|&next| next & PMP_L != 0 && Self::mode(next) == PMP_TOR);
+        if !is_locked && !bounds_locked_tor {
+            self.addr[i] = value;
+        }
+    }
+
+    /// Returns true if the word holding `paddr` may be accessed.
+    pub fn allows(&self, paddr: u32, access: AccessType, privilege: Privilege) -> bool {
+        let addr = u64::from(paddr & !0x3);
+        let matched = (0..PMP_ENTRIES).find(|&i| {
+            self.range(i)
+                .is_some_and(|(start, end)| start <= addr && addr < end)
+        });
+        let cfg = match matched {
+            Some(i) => self.cfg[i],
+            None => return privilege == Privilege::Machine,
+        };
+        if privilege == Privilege::Machine && cfg & PMP_L == 0 {
+            return true;
+        }
+        let needed = match access {
+            AccessType::Fetch => PMP_X,
+            AccessType::Load => PMP_R,
+            AccessType::Store => PMP_W,
+        };
+        cfg & needed != 0
+    }
+
+    fn mode(cfg: u8) -> u8 {
+        (cfg >> PMP_A_SHIFT) & 0b11
+    }
+
+    /// Byte range `[start, end)` matched by entry `i`, or `None` if it is
+    /// off.
+    fn range(&self, i: usize) -> Option<(u64, u64)> {
+        let addr = u64::from(self.addr[i]);
+        match Self::mode(self.cfg[i]) {
+            PMP_TOR => {
+                let start = if i == 0 {
+                    0
+                } else {
+                    u64::from(self.addr[i - 1]) << 2
+                };
+                Some((start, addr << 2))
+            }
+            PMP_NA4 => Some((addr << 2, (addr + 1) << 2)),
+            PMP_NAPOT => {
+                // 하위의 연속된 1 비트 수가 영역의 크기를 나타냄
+                let mask = addr ^ (addr + 1);
+                Some(((addr & !mask) << 2, ((addr | mask) + 1) << 2))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Snapshot for Pmp {
+    fn save(&self, out: &mut Encoder) {
+        for i in 0..PMP_ENTRIES {
+            out.u8(self.cfg[i]);
+            out.u32(self.addr[i]);
+        }
+    }
+
+    fn restore(&mut self, input: &mut Decoder) -> io::Result<()> {
+        for i in 0..PMP_ENTRIES {
+            self.cfg[i] = input.u8()?;
+            self.addr[i] = input.u32()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cfg(mode: u8, perms: u8) -> u8 {
+        (mode << PMP_A_SHIFT) | perms
+    }
+
+    #[test]
+    fn matching_modes_and_lock() {
+        let mut pmp = Pmp::default();
+        // 0: NA4 0x1000, 1: TOR [0x1000, 0x2000), 2: NAPOT 0x8000_0000 + 64KiB
+        pmp.write_addr(0, 0x1000 >> 2);
+        pmp.write_addr(1, 0x2000 >> 2);
+        pmp.write_addr(2, (0x8000_0000 >> 2) | ((0x1_0000 >> 3) - 1));
+        let cfg0 = u32::from(cfg(PMP_NA4, PMP_R))
+            | u32::from(cfg(PMP_TOR, PMP_R | PMP_W)) << 8
+            | u32::from(cfg(PMP_NAPOT, PMP_X)) << 16;
+        pmp.write_cfg(0, cfg0);
+        assert_eq!(pmp.read_cfg(0), cfg0);
+
+        let user = Privilege::User;
+        // 앞쪽 entry가 우선
+        assert!(!pmp.allows(0x1002, AccessType::Store, user));
+        assert!(pmp.allows(0x1004, AccessType::Store, user));
+        assert!(!pmp.allows(0x2000, AccessType::Load, user));
+        assert!(pmp.allows(0x8000_fffc, AccessType::Fetch, user));
+        assert!(!pmp.allows(0x8001_0000, AccessType::Fetch, user));
+        assert!(!pmp.allows(0x8000_0000, AccessType::Load, user));
+        assert!(pmp.allows(0x2000, AccessType::Store, Privilege::Machine));
+
+        // lock되면 M-mode도 검사하며 설정을 바꿀 수 없음
+        pmp.write_cfg(0, cfg0 | u32::from(PMP_L) << 8);
+        assert!(!pmp.allows(0x1004, AccessType::Fetch, Privilege::Machine));
+        pmp.write_addr(0, 0);
+        pmp.write_addr(1, 0);
+        assert_eq!(pmp.read_addr(0), 0x1000 >> 2);
+        assert_eq!(pmp.read_addr(1), 0x2000 >> 2);
+        pmp.write_cfg(0, 0);
+        assert_eq!(pmp.read_cfg(0), u32::from(cfg(PMP_TOR, PMP_R | PMP_W | PMP_L)) << 8);
+    }
+}
@@ -0,0 +1,339 @@
+//! Memory system shared by every hart.
+//!
+//! Each hart has a private L1 data cache. The caches snoop a shared bus and
+//! keep their lines coherent with MESI, and misses that no other cache can
+//! serve go to the DRAM controller. Bus transactions are atomic: the other
+//! caches change state as soon as a request is sent, and only the requester
+//! waits for the data. The requester's line is filled right away too, so a
+//! later access to it hits even while the miss is still in flight. Accesses
+//! to the CLINT and to MMIO devices bypass the caches.
+
+use memory::cache::{Cache, CacheConfig, MesiState};
+use memory::clint::{Clint, ClintConfig};
+use memory::dram::{DramConfig, DramController, Requester};
+use memory::htif::Htif;
+use memory::ProcessMemory;
+use pipeline::exception::Exception;
+use pipeline::semihosting::Semihosting;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AccessKind {
+    Read,
+    /// Stores and AMOs, which need the line in `Modified`.
+    Write,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct CoherenceStats {
+    /// Read misses (BusRd).
+    pub bus_reads: usize,
+    /// Write misses (BusRdX).
+    pub bus_read_exclusives: usize,
+    /// Writes to `Shared` lines (BusUpgr).
+    pub bus_upgrades: usize,
+    /// Lines invalidated in other caches.
+    pub invalidations: usize,
+    /// Misses served by another cache instead of DRAM.
+    pub cache_to_cache: usize,
+    pub writebacks: usize,
+}
+
+impl fmt::Display for CoherenceStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "BusRd: {}, BusRdX: {}, BusUpgr: {}, invalidations: {}, cache-to-cache: {}, writebacks: {}",
+            self.bus_reads,
+            self.bus_read_exclusives,
+            self.bus_upgrades,
+            self.invalidations,
+            self.cache_to_cache,
+            self.writebacks
+        )
+    }
+}
+
+#[derive(Debug)]
+pub struct MemorySystem {
+    pub memory: ProcessMemory,
+    pub dram: DramController,
+    pub clint: Clint,
+    /// Set when the program has a `tohost` symbol.
+    pub htif: Option<Htif>,
+    pub semihosting: Semihosting,
+    /// L1 data cache of each hart.
+    pub caches: Vec<Cache>,
+    /// Line reserved by the last `lr.w` of each hart.
+    reservations: Vec<Option<u32>>,
+    /// Accesses served without DRAM as (completion clock, hart, requester).
+    pending: Vec<(usize, usize, Requester)>,
+    clock: usize,
+    pub stats: CoherenceStats,
+}
+
+impl MemorySystem {
+    pub fn new(
+        memory: ProcessMemory,
+        hart_count: usize,
+        dram: DramConfig,
+        cache: CacheConfig,
+        clint: ClintConfig,
+    ) -> Self {
+        MemorySystem {
+            memory,
+            dram: DramController::new(dram),
+            clint: Clint::new(clint, hart_count),
+            htif: None,
+            semihosting: Default::default(),
+            caches: vec![Cache::new(cache); hart_count],
+            reservations: vec![None; hart_count],
+            pending: Vec::new(),
+            clock: 0,
+            stats: Default::default(),
+        }
+    }
+
+    pub fn hart_count(&self) -> usize {
+        self.caches.len()
+    }
+
+    /// Sends a data access of `hart` through its L1. `requester` is reported
+    /// by `tick` once the access is done.
+    pub fn access(&mut self, hart: usize, requester: Requester, addr: u32, kind: AccessKind) {
+        if self.clint.contains(addr) || self.memory.mmio.contains(addr) {
+            self.complete_at(self.clock + 1, hart, requester);
+            return;
+        }
+        let line = self.caches[hart].line_addr(addr);
+        let hit_latency = self.caches[hart].config.hit_latency;
+        let bus_latency = self.caches[hart].config.bus_latency;
+        if kind == AccessKind::Write {
+            self.break_reservations(hart, line);
+        }
+
+        let state = self.caches[hart].state(line);
+        match (kind, state) {
+            (_, MesiState::Invalid) => {
+                self.caches[hart].stats.misses += 1;
+                match kind {
+                    AccessKind::Read => self.stats.bus_reads += 1,
+                    AccessKind::Write => self.stats.bus_read_exclusives += 1,
+                }
+                let has_copy = self.snoop(hart, line, kind);
+                let new_state = match kind {
+                    AccessKind::Write => MesiState::Modified,
+                    AccessKind::Read if has_copy => MesiState::Shared,
+                    AccessKind::Read => MesiState::Exclusive,
+                };
+                if let Some((victim, MesiState::Modified)) = self.caches[hart].fill(line, new_state)
+                {
+                    self.write_back(hart, victim);
+                }
+
+                if has_copy {
+                    self.stats.cache_to_cache += 1;
+                    self.complete_at(self.clock + hit_latency + bus_latency, hart, requester);
+                } else {
+                    self.dram.request(hart, requester, line, false);
+                }
+            }
+            (AccessKind::Write, MesiState::Exclusive) => {
+                self.caches[hart].stats.hits += 1;
+                self.caches[hart].touch(line);
+                self.caches[hart].set_state(line, MesiState::Modified);
+                self.complete_at(self.clock + hit_latency, hart, requester);
+            }
+            (AccessKind::Write, MesiState::Shared) => {
+                self.caches[hart].stats.hits += 1;
+                self.caches[hart].stats.upgrades += 1;
+                self.stats.bus_upgrades += 1;
+                self.snoop(hart, line, kind);
+                self.caches[hart].touch(line);
+                self.caches[hart].set_state(line, MesiState::Modified);
+                self.complete_at(self.clock + hit_latency + bus_latency, hart, requester);
+            }
+            _ => {
+                self.caches[hart].stats.hits += 1;
+                self.caches[hart].touch(line);
+                self.complete_at(self.clock + hit_latency, hart, requester);
+            }
+        }
+    }
+
+    /// Lets the HTIF see a store once memory has been updated.
+    pub fn stored(&mut self, addr: u32) -> Result<(), Exception> {
+        match self.htif {
+            Some(ref mut htif) => htif.store(addr, &mut self.memory),
+            None => Ok(()),
+        }
+    }
+
+    /// Reads memory without going through the L1, as the page-table walker
+    /// does.
+    pub fn read_uncached(&mut self, hart: usize, requester: Requester, addr: u32) {
+        self.dram.request(hart, requester, addr, false);
+    }
+
+    /// Lets the other caches react to a bus transaction of `hart`. Returns
+    /// true if any of them held the line.
+    fn snoop(&mut self, hart: usize, line: u32, kind: AccessKind) -> bool {
+        let mut has_copy = false;
+        for other in 0..self.caches.len() {
+            if other == hart {
+                continue;
+            }
+            let state = self.caches[other].state(line);
+            if state == MesiState::Invalid {
+                continue;
+            }
+            has_copy = true;
+            match kind {
+                AccessKind::Read => {
+                    // Modified인 line은 메모리에도 반영한 뒤 공유
+                    if state == MesiState::Modified {
+                        self.write_back(other, line);
+                    }
+                    self.caches[other].set_state(line, MesiState::Shared);
+                }
+                AccessKind::Write => {
+                    self.caches[other].set_state(line, MesiState::Invalid);
+                    self.caches[other].stats.invalidations += 1;
+                    self.stats.invalidations += 1;
+                }
+            }
+        }
+        has_copy
+    }
+
+    fn write_back(&mut self, hart: usize, line: u32) {
+        self.caches[hart].stats.writebacks += 1;
+        self.stats.writebacks += 1;
+        self.dram.request(hart, Requester::Writeback, line, true);
+    }
+
+    fn complete_at(&mut self, clock: usize, hart: usize, requester: Requester) {
+        self.pending.push((clock, hart, requester));
+    }
+
+    /// Advances the memory system to `clock` and returns, for each hart, the
+    /// requesters whose accesses completed.
+    pub fn tick(&mut self, clock: usize) -> Vec<Vec<Requester>> {
+        self.clock = clock;
+        self.clint.tick(clock);
+        let mut arrived = vec![Vec::new(); self.hart_count()];
+        for (hart, requester) in self.dram.tick(clock) {
+            arrived[hart].push(requester);
+        }
+        self.pending.retain(|&(done_at, hart, requester)| {
+            if done_at <= clock {
+                arrived[hart].push(requester);
+                false
+            } else {
+                true
+            }
+        });
+        arrived
+    }
+
+    /// Forgets every access in flight for `hart` after its pipeline is
+    /// flushed. Coherence states are kept, as the accesses did reach the bus.
+    pub fn squash(&mut self, hart: usize) {
+        self.dram.squash(hart);
+        self.pending.retain(|&(_, req_hart, requester)| {
+            req_hart != hart || !matches!(requester, Requester::Load(_) | Requester::Store(_))
+        });
+    }
+
+    /// Registers the reservation of an `lr.w` at `addr`.
+    pub fn reserve(&mut self, hart: usize, addr: u32) {
+        self.reservations[hart] = Some(self.caches[hart].line_addr(addr));
+    }
+
+    /// Returns true if an `sc.w` of `hart` at `addr` may still succeed.
+    pub fn holds_reservation(&self, hart: usize, addr: u32) -> bool {
+        self.reservations[hart] == Some(self.caches[hart].line_addr(addr))
+    }
+
+    pub fn clear_reservation(&mut self, hart: usize) {
+        self.reservations[hart] = None;
+    }
+
+    /// Breaks the reservations of the other harts on the line of `addr`, for
+    /// a write of `hart` that doesn't go through the caches.
+    pub fn written_functionally(&mut self, hart: usize, addr: u32) {
+        let line = self.caches[hart].line_addr(addr);
+        self.break_reservations(hart, line);
+    }
+
+    /// A write of `hart` to `line` breaks the reservations of the others.
+    fn break_reservations(&mut self, hart: usize, line: u32) {
+        for (other, reservation) in self.reservations.iter_mut().enumerate() {
+            if other != hart && *reservation == Some(line) {
+                *reservation = None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_until_done(mem_sys: &mut MemorySystem, hart: usize, start: usize) -> usize {
+        (start..start + 1000)
+            .find(|&clock| !mem_sys.tick(clock)[hart].is_empty())
+            .unwrap()
+    }
+
+    fn two_harts() -> MemorySystem {
+        MemorySystem::new(
+            ProcessMemory::default(),
+            2,
+            DramConfig::default(),
+            CacheConfig::default(),
+            ClintConfig::default(),
+        )
+    }
+
+    #[test]
+    fn mesi_transitions() {
+        let mut mem_sys = two_harts();
+        let line = 0x1000;
+
+        mem_sys.access(0, Requester::Load(0), line, AccessKind::Read);
+        let clock = run_until_done(&mut mem_sys, 0, 0);
+        assert_eq!(mem_sys.caches[0].state(line), MesiState::Exclusive);
+
+        mem_sys.access(1, Requester::Load(0), line + 4, AccessKind::Read);
+        let clock = run_until_done(&mut mem_sys, 1, clock);
+        assert_eq!(mem_sys.caches[0].state(line), MesiState::Shared);
+        assert_eq!(mem_sys.caches[1].state(line), MesiState::Shared);
+        assert_eq!(mem_sys.stats.cache_to_cache, 1);
+
+        mem_sys.access(1, Requester::Store(0), line, AccessKind::Write);
+        let clock = run_until_done(&mut mem_sys, 1, clock);
+        assert_eq!(mem_sys.caches[0].state(line), MesiState::Invalid);
+        assert_eq!(mem_sys.caches[1].state(line), MesiState::Modified);
+        assert_eq!(mem_sys.stats.bus_upgrades, 1);
+
+        mem_sys.access(0, Requester::Load(0), line, AccessKind::Read);
+        run_until_done(&mut mem_sys, 0, clock);
+        assert_eq!(mem_sys.caches[0].state(line), MesiState::Shared);
+        assert_eq!(mem_sys.caches[1].state(line), MesiState::Shared);
+        assert_eq!(mem_sys.stats.writebacks, 1);
+        assert_eq!(mem_sys.dram.stats.reads, 1);
+    }
+
+    #[test]
+    fn write_breaks_other_reservations() {
+        let mut mem_sys = two_harts();
+        mem_sys.reserve(0, 0x2000);
+        mem_sys.reserve(1, 0x2000);
+        assert!(mem_sys.holds_reservation(0, 0x2004));
+
+        mem_sys.access(1, Requester::Store(0), 0x2008, AccessKind::Write);
+        assert!(!mem_sys.holds_reservation(0, 0x2000));
+        assert!(mem_sys.holds_reservation(1, 0x2000));
+    }
+}
@@ -0,0 +1,185 @@
+//! 16550-compatible UART.
+//!
+//! Transmitted bytes go straight to the host's stdout. Received bytes come
+//! from the host's stdin, read by a background thread started on the first
+//! access to the receiver so programs that never read don't consume stdin.
+//! Interrupts and the modem lines aren't modelled.
+
+use checkpoint::{Decoder, Encoder, Snapshot};
+use memory::mmio::Device;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::sync::mpsc::{channel, Receiver};
+
+pub const UART_BASE: u32 = 0x1000_0000;
+pub const UART_SIZE: u32 = 0x100;
+
+const RBR_THR_DLL: u32 = 0;
+const IER_DLM: u32 = 1;
+const IIR_FCR: u32 = 2;
+const LCR: u32 = 3;
+const MCR: u32 = 4;
+const LSR: u32 = 5;
+const MSR: u32 = 6;
+const SCR: u32 = 7;
+
+const LCR_DLAB: u8 = 1 << 7;
+const LSR_DATA_READY: u8 = 1 << 0;
+const LSR_THR_EMPTY: u8 = 1 << 5;
+const LSR_TX_EMPTY: u8 = 1 << 6;
+/// IIR value meaning no interrupt is pending.
+const IIR_NO_INTERRUPT: u8 = 0x01;
+
+#[derive(Debug, Default)]
+pub struct Uart {
+    ier: u8,
+    lcr: u8,
+    mcr: u8,
+    scr: u8,
+    divisor: u16,
+    rx_fifo: VecDeque<u8>,
+    stdin: Option<Receiver<u8>>,
+    /// Every byte transmitted so far.
+    pub transmitted: Vec<u8>,
+    /// Connects the UART to the host's stdin and stdout.
+    pub host_io: bool,
+}
+
+impl Uart {
+    pub fn new() -> Self {
+        Uart {
+            host_io: true,
+            ..Default::default()
+        }
+    }
+
+    /// Queues bytes as if they had arrived on the receive line.
+    pub fn receive(&mut self, bytes: &[u8]) {
+        self.rx_fifo.extend(bytes);
+    }
+
+    fn poll_stdin(&mut self) {
+        if self.stdin.is_none() && self.host_io {
+            let (sender, receiver) = channel();
+            std::thread::spawn(move || {
+                for byte in std::io::stdin().lock().bytes() {
+                    match byte {
+                        Ok(byte) if sender.send(byte).is_ok() => {}
+                        _ => break,
+                    }
+                }
+            });
+            self.stdin = Some(receiver);
+        }
+        if let Some(ref stdin) = self.stdin {
+            self.rx_fifo.extend(stdin.try_iter());
+        }
+    }
+
+    fn is_dlab(&self) -> bool {
+        self.lcr & LCR_DLAB != 0
+    }
+}
+
+impl Device for Uart {
+    fn read(&mut self, offset: u32, _size: usize) -> u32 {
+        let value = match offset {
+            RBR_THR_DLL if self.is_dlab() => self.divisor as u8,
+            RBR_THR_DLL => {
+                self.poll_stdin();
+                self.rx_fifo.pop_front().unwrap_or(0)
+            }
+            IER_DLM if self.is_dlab() => (self.divisor >> 8) as u8,
+            IER_DLM => self.ier,
+            IIR_FCR => IIR_NO_INTERRUPT,
+            LCR => self.lcr,
+            MCR => self.mcr,
+            LSR => {
+                self.poll_stdin();
+                let data_ready = if self.rx_fifo.is_empty() {
+                    0
+                } else {
+                    LSR_DATA_READY
+                };
+                // 송신은 즉시 끝나므로 항상 비어 있음
+                data_ready | LSR_THR_EMPTY | LSR_TX_EMPTY
+            }
+            MSR => 0,
+            SCR => self.scr,
+            _ => 0,
+        };
+        u32::from(value)
+    }
+
+    fn write(&mut self, offset: u32, _size: usize, value: u32) {
+        let value = value as u8;
+        match offset {
+            RBR_THR_DLL if self.is_dlab() => {
+                self.divisor = (self.divisor & 0xff00) | u16::from(value)
+            }
+            RBR_THR_DLL => {
+                self.transmitted.push(value);
+                if self.host_io {
+                    let mut stdout = std::io::stdout();
+                    stdout.write_all(&[value]).unwrap();
+                    stdout.flush().unwrap();
+                }
+            }
+            IER_DLM if self.is_dlab() => {
+                self.divisor = (self.divisor & 0x00ff) | (u16::from(value) << 8)
+            }
+            IER_DLM => self.ier = value & 0x0f,
+            LCR => self.lcr = value,
+            MCR => self.mcr = value & 0x1f,
+            SCR => self.scr = value,
+            _ => {}
+        }
+    }
+}
+
+/// What was transmitted went to the host and isn't saved.
+impl Snapshot for Uart {
+    fn save(&self, out: &mut Encoder) {
+        for &reg in [self.ier, self.lcr, self.mcr, self.scr].iter() {
+            out.u8(reg);
+        }
+        out.u32(u32::from(self.divisor));
+        let rx_fifo: Vec<u8> = self.rx_fifo.iter().cloned().collect();
+        out.bytes(&rx_fifo);
+    }
+
+    fn restore(&mut self, input: &mut Decoder) -> io::Result<()> {
+        for reg in [&mut self.ier, &mut self.lcr, &mut self.mcr, &mut self.scr].iter_mut() {
+            **reg = input.u8()?;
+        }
+        self.divisor = input.u32()? as u16;
+        self.rx_fifo = input.bytes()?.into();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transmit_receive_and_divisor_latch() {
+        let mut uart = Uart::default();
+        uart.write(RBR_THR_DLL, 1, u32::from(b'h'));
+        uart.write(RBR_THR_DLL, 1, u32::from(b'i'));
+        assert_eq!(uart.transmitted, b"hi");
+
+        uart.write(LCR, 1, u32::from(LCR_DLAB));
+        uart.write(RBR_THR_DLL, 1, 0x01);
+        uart.write(IER_DLM, 1, 0x02);
+        assert_eq!(uart.divisor, 0x0201);
+        uart.write(LCR, 1, 0x03);
+        assert_eq!(uart.transmitted.len(), 2);
+
+        assert_eq!(uart.read(LSR, 1) as u8 & LSR_DATA_READY, 0);
+        uart.receive(b"x");
+        assert_eq!(uart.read(LSR, 1) as u8 & LSR_DATA_READY, LSR_DATA_READY);
+        assert_eq!(uart.read(RBR_THR_DLL, 1), u32::from(b'x'));
+        assert_eq!(uart.read(LSR, 1) as u8 & LSR_DATA_READY, 0);
+    }
+}
@@ -1,4 +1,6 @@
+use checkpoint::{Decoder, Encoder, Snapshot};
 use std::collections::HashMap;
+use std::io;
 
 #[derive(Default, Debug, Clone)]
 pub struct BranchPredictor {
@@ -29,3 +31,26 @@ impl BranchPredictor {
         }
     }
 }
+
+impl Snapshot for BranchPredictor {
+    fn save(&self, out: &mut Encoder) {
+        let mut entries: Vec<_> = self.branch_map.iter().collect();
+        entries.sort_by_key(|&(&pc, _)| pc);
+        out.usize(entries.len());
+        for (&pc, &(prediction, last_taken)) in entries {
+            out.u32(pc);
+            out.bool(prediction);
+            out.bool(last_taken);
+        }
+    }
+
+    fn restore(&mut self, input: &mut Decoder) -> io::Result<()> {
+        self.branch_map.clear();
+        for _ in 0..input.usize()? {
+            let pc = input.u32()?;
+            let state = (input.bool()?, input.bool()?);
+            self.branch_map.insert(pc, state);
+        }
+        Ok(())
+    }
+}
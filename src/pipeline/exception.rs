@@ -4,4 +4,52 @@ pub enum Exception {
     WritingToReadOnlyMemory(u32),
     SyscallNotImpl(u32),
     FailCallingSyscall(u32),
+    IllegalInstruction(u32),
+    InstructionAccessFault(u32),
+    InstructionPageFault(u32),
+    LoadAccessFault(u32),
+    StoreAccessFault(u32),
+    LoadPageFault(u32),
+    StorePageFault(u32),
+    /// `ecall` trapping to the guest, holding the privilege level it came
+    /// from.
+    EnvironmentCall(u32),
+}
+
+impl Exception {
+    /// `mcause`/`scause` code of exceptions that trap to the guest. The
+    /// others are simulator errors and abort the run.
+    pub fn trap_cause(&self) -> Option<u32> {
+        use self::Exception::*;
+        match self {
+            InstructionAccessFault(_) => Some(1),
+            IllegalInstruction(_) => Some(2),
+            LoadAccessFault(_) => Some(5),
+            StoreAccessFault(_) => Some(7),
+            EnvironmentCall(privilege) => Some(8 + privilege),
+            InstructionPageFault(_) => Some(12),
+            LoadPageFault(_) => Some(13),
+            StorePageFault(_) => Some(15),
+            _ => None,
+        }
+    }
+
+    /// Value written to `mtval`/`stval` when the exception traps.
+    pub fn trap_value(&self) -> u32 {
+        use self::Exception::*;
+        match *self {
+            EnvironmentCall(_) => 0,
+            WritingToInvalidMemory(val)
+            | WritingToReadOnlyMemory(val)
+            | SyscallNotImpl(val)
+            | FailCallingSyscall(val)
+            | IllegalInstruction(val)
+            | InstructionAccessFault(val)
+            | InstructionPageFault(val)
+            | LoadAccessFault(val)
+            | StoreAccessFault(val)
+            | LoadPageFault(val)
+            | StorePageFault(val) => val,
+        }
+    }
 }
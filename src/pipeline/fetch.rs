@@ -0,0 +1,126 @@
+//! Fetch queue decoupling the front end from issue.
+//!
+//! The fetch stage reads up to `width` instructions per clock into the queue.
+//! An instruction can be issued once it has spent `depth` clocks in the front
+//! end, so refilling the queue after a redirect costs real clocks.
+
+use instruction::Instruction;
+use pipeline::exception::Exception;
+use pipeline::lifecycle::Timestamps;
+use std::collections::VecDeque;
+
+#[derive(Debug, Clone)]
+pub struct FetchConfig {
+    /// Instructions fetched per clock.
+    pub width: usize,
+    /// Capacity of the fetch queue.
+    pub queue_size: usize,
+    /// Clocks between fetching an instruction and being able to issue it.
+    pub depth: usize,
+}
+
+impl Default for FetchConfig {
+    fn default() -> Self {
+        FetchConfig {
+            width: 2,
+            queue_size: 8,
+            depth: 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FetchedInst {
+    pub pc: u32,
+    pub inst: Instruction,
+    /// Instruction word as fetched. `inst` is a `nop` for the ones that
+    /// aren't modelled.
+    pub raw: u32,
+    /// Direction predicted for a branch when it was fetched.
+    pub predicted_taken: bool,
+    /// Set when fetching or decoding failed. Raised only if the instruction
+    /// reaches commit, so faults on a wrong path disappear with the flush.
+    pub exception: Option<Exception>,
+    pub ready_at: usize,
+    pub times: Timestamps,
+}
+
+#[derive(Debug, Default)]
+pub struct FetchQueue {
+    pub config: FetchConfig,
+    queue: VecDeque<FetchedInst>,
+    /// Fetch waits for a redirect, e.g. behind a `jalr` or a system instruction.
+    stalled: bool,
+    /// Instructions fetched so far, including squashed ones.
+    fetched: usize,
+}
+
+impl FetchQueue {
+    pub fn new(config: FetchConfig) -> Self {
+        FetchQueue {
+            config,
+            queue: VecDeque::new(),
+            stalled: false,
+            fetched: 0,
+        }
+    }
+
+    pub fn can_fetch(&self) -> bool {
+        !self.stalled && self.queue.len() < self.config.queue_size
+    }
+
+    pub fn push(&mut self, fetched: FetchedInst, clock: usize) {
+        self.queue.push_back(FetchedInst {
+            ready_at: clock + self.config.depth,
+            times: Timestamps {
+                seq: self.fetched,
+                fetched: clock,
+                ..Default::default()
+            },
+            ..fetched
+        });
+        self.fetched += 1;
+    }
+
+    pub fn stall(&mut self) {
+        self.stalled = true;
+    }
+
+    /// Pops the oldest instruction if it has made it through the front end.
+    pub fn pop_ready(&mut self, clock: usize) -> Option<FetchedInst> {
+        match self.queue.front() {
+            Some(fetched) if fetched.ready_at <= clock => self.queue.pop_front(),
+            _ => None,
+        }
+    }
+
+    /// Returns the oldest instruction if it can be issued, without taking it.
+    pub fn peek_ready(&self, clock: usize) -> Option<&FetchedInst> {
+        self.queue
+            .front()
+            .filter(|fetched| fetched.ready_at <= clock)
+    }
+
+    /// Drops everything fetched so far and lets fetch continue.
+    pub fn redirect(&mut self) {
+        self.queue.clear();
+        self.stalled = false;
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &FetchedInst> {
+        self.queue.iter()
+    }
+
+    /// Address of the oldest instruction in the queue.
+    pub fn front_pc(&self) -> Option<u32> {
+        self.queue.front().map(|fetched| fetched.pc)
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
@@ -0,0 +1,246 @@
+//! Functional execution, to fast-forward through the parts of a program
+//! that aren't simulated in detail.
+//!
+//! A hart whose pipeline is empty runs one instruction per clock straight on
+//! its `RegisterFile` and the `ProcessMemory`, with no ROB, reservation
+//! stations, caches or TLBs in between. Traps, interrupts, system calls and
+//! semihosting behave as they do when the instruction retires in the
+//! pipeline, and branches keep training the branch predictor, so the
+//! detailed simulation can take over at any instruction.
+
+use super::exception::Exception;
+use super::functional_units::memory::MemoryUnit;
+use super::reorder_buffer::ReorderBufferEntry;
+use super::semihosting::Semihosting;
+use super::thread::ThreadTable;
+use super::{without_fp, Pipeline};
+use alu::alu;
+use consts;
+use instruction::{Function, Instruction, Opcode};
+use memory::mmu::{AccessType, Mmu};
+use memory::system::MemorySystem;
+
+impl Pipeline {
+    /// Runs the next instruction of the thread on the hart. Returns its PC
+    /// and the instruction, or `None` if the hart is idle or the
+    /// instruction trapped.
+    pub fn step(
+        &mut self,
+        mem_sys: &mut MemorySystem,
+        threads: &mut ThreadTable,
+    ) -> Option<(u32, Instruction)> {
+        self.clock += 1;
+        self.tid?;
+        self.sample_interrupts(mem_sys);
+        self.take_interrupt(mem_sys);
+
+        let pc = self.reg.pc.read();
+        match self.execute_now(pc, mem_sys, threads) {
+            Ok(inst) => Some((pc, inst)),
+            Err(exception) => {
+                self.take_trap(exception, pc, mem_sys);
+                None
+            }
+        }
+    }
+
+    fn execute_now(
+        &mut self,
+        pc: u32,
+        mem_sys: &mut MemorySystem,
+        threads: &mut ThreadTable,
+    ) -> Result<Instruction, Exception> {
+        let paddr = Mmu::translate_now(pc, AccessType::Fetch, &self.reg.csr, &mem_sys.memory)?;
+        let raw_inst = mem_sys
+            .memory
+            .read_inst(paddr)
+            .map_err(|_| Exception::InstructionAccessFault(pc))?;
+        let inst = Instruction::decode(raw_inst).ok_or(Exception::IllegalInstruction(raw_inst))?;
+        let inst = without_fp(inst);
+
+        let read = |reg: Option<u8>| self.reg.gpr[reg.unwrap_or(0) as usize].read();
+        let (rs1, rs2) = (read(inst.fields.rs1), read(inst.fields.rs2));
+        let imm = inst.fields.imm.unwrap_or(0);
+        let function = inst.function;
+        let hart = self.hart_id;
+        let mut npc = pc.wrapping_add(consts::WORD_SIZE as u32);
+        let mut entry = ReorderBufferEntry::new(pc, inst.clone(), self.reg.csr.privilege);
+
+        entry.reg_value = match inst.opcode {
+            Opcode::Branch => {
+                let is_taken = alu(&function, rs1 as i32, rs2 as i32) as u32;
+                self.branch_predictor.predict(pc);
+                self.branch_predictor.update(pc, is_taken);
+                if is_taken != 0 {
+                    npc = pc.wrapping_add(imm);
+                }
+                None
+            }
+            Opcode::Jal => {
+                let link = npc;
+                npc = pc.wrapping_add(imm);
+                Some(link)
+            }
+            Opcode::Jalr => {
+                let link = npc;
+                npc = rs1.wrapping_add(imm);
+                Some(link)
+            }
+            Opcode::AuiPc => Some(alu(&function, pc as i32, imm as i32) as u32),
+            Opcode::Lui => Some(alu(&function, 0, imm as i32) as u32),
+            Opcode::Load => {
+                let paddr =
+                    self.translate_data(rs1.wrapping_add(imm), AccessType::Load, mem_sys)?;
+                let value = if mem_sys.memory.mmio.contains(paddr) {
+                    MemoryUnit::execute_device(paddr, function, &mut mem_sys.memory)?
+                } else {
+                    MemoryUnit::execute(paddr, function, mem_sys)?
+                };
+                Some(value)
+            }
+            Opcode::Store => {
+                let paddr =
+                    self.translate_data(rs1.wrapping_add(imm), AccessType::Store, mem_sys)?;
+                MemoryUnit::write(function, paddr, rs2, mem_sys)?;
+                mem_sys.written_functionally(hart, paddr);
+                None
+            }
+            Opcode::Amo if function == Function::Lrw => {
+                let paddr = self.translate_data(rs1, AccessType::Load, mem_sys)?;
+                let value = MemoryUnit::execute(paddr, function, mem_sys)?;
+                mem_sys.reserve(hart, paddr);
+                Some(value)
+            }
+            Opcode::Amo => {
+                let paddr = self.translate_data(rs1, AccessType::Store, mem_sys)?;
+                let has_reservation =
+                    function != Function::Scw || mem_sys.holds_reservation(hart, paddr);
+                if function == Function::Scw {
+                    mem_sys.clear_reservation(hart);
+                }
+                if has_reservation {
+                    let (value, _) = MemoryUnit::write(function, paddr, rs2, mem_sys)?;
+                    mem_sys.written_functionally(hart, paddr);
+                    Some(value)
+                } else {
+                    Some(1)
+                }
+            }
+            Opcode::System => Some(0),
+            _ => {
+                let operand = if inst.fields.rs2.is_some() { rs2 } else { imm };
+                Some(alu(&function, rs1 as i32, operand as i32) as u32)
+            }
+        };
+
+        if let Opcode::System = inst.opcode {
+            if let (Function::Ecall, Some(exception)) = (function, self.ecall_exception()) {
+                return Err(exception);
+            }
+        }
+        if entry.reg_value.is_some() {
+            entry.retire(usize::MAX, &mut self.reg)?;
+        }
+
+        if let Opcode::System = inst.opcode {
+            match function {
+                Function::SfenceVma => self.mmu.flush(),
+                Function::Ecall => {
+                    let is_thread_call = self.thread_call(pc, mem_sys, threads).unwrap();
+                    if !is_thread_call {
+                        Pipeline::system_call(&mut mem_sys.memory, &mut self.reg).unwrap();
+                    }
+                    // thread가 block되거나 종료되어 hart가 비었음
+                    if self.tid.is_none() {
+                        return Ok(inst);
+                    }
+                }
+                Function::Ebreak
+                    if !self.reg.csr.is_translating()
+                        && Semihosting::is_call(pc, &mem_sys.memory) =>
+                {
+                    let exit = mem_sys
+                        .semihosting
+                        .call(&mut self.reg, &mut mem_sys.memory);
+                    if let Some(code) = exit {
+                        threads.exit_group(code);
+                    }
+                }
+                Function::Mret => npc = self.reg.csr.mepc,
+                Function::Sret => npc = self.reg.csr.sepc,
+                _ => {}
+            }
+        }
+        self.reg.pc.write(npc);
+        Ok(inst)
+    }
+
+    fn translate_data(
+        &self,
+        vaddr: u32,
+        access: AccessType,
+        mem_sys: &MemorySystem,
+    ) -> Result<u32, Exception> {
+        Mmu::translate_now(vaddr, access, &self.reg.csr, &mem_sys.memory)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pipeline::thread::ENOSYS;
+    use consts;
+    use machine::tests::with_program;
+
+    #[test]
+    fn matches_detailed_execution() {
+        // li a0, 0; li a1, 0x100; loop: addi a0, a0, 1; sw a0, 0(a1);
+        // lw a2, 0(a1); add a3, a3, a2; amoadd.w a4, a0, (a1); j loop
+        let program = [
+            0x0000_0513u32,
+            0x1000_0593,
+            0x0015_0513,
+            0x00a5_a023,
+            0x0005_a603,
+            0x00c6_86b3,
+            0x00a5_a72f,
+            0xfedf_f06f,
+        ];
+        let new_machine = || with_program(&program, Default::default());
+
+        let mut detailed = new_machine();
+        let mut retired = 0;
+        for _ in 0..300 {
+            retired += detailed.run_clock().0[0].len();
+        }
+        detailed.drain(true);
+        while !detailed.is_drained() {
+            retired += detailed.run_clock().0[0].len();
+        }
+
+        let mut functional = new_machine();
+        assert_eq!(functional.fast_forward(retired), (retired, false));
+        assert_eq!(functional.mem_sys.memory.data, detailed.mem_sys.memory.data);
+        let (detailed, functional) = (&detailed.harts[0].reg, &functional.harts[0].reg);
+        assert_eq!(functional.pc.read(), detailed.pc.read());
+        for (functional, detailed) in functional.gpr.iter().zip(detailed.gpr.iter()) {
+            assert_eq!(functional.read(), detailed.read());
+        }
+        assert!(functional.gpr[13].read() > 0);
+    }
+
+    #[test]
+    fn clone_without_shared_memory_returns_enosys() {
+        // li a7, 220; li a0, 17 (SIGCHLD); ecall; j .
+        let program = [0x0dc0_0893u32, 0x0110_0513, 0x0000_0073, 0x0000_006f];
+        let mut detailed = with_program(&program, Default::default());
+        for _ in 0..100 {
+            detailed.run_clock();
+        }
+        let mut functional = with_program(&program, Default::default());
+        functional.fast_forward(3);
+        for machine in &[detailed, functional] {
+            let a0 = machine.harts[0].reg.gpr[consts::SYSCALL_RET_REG].read();
+            assert_eq!(a0, ENOSYS.wrapping_neg());
+        }
+    }
+}
@@ -2,12 +2,17 @@ use instruction::{Instruction, Opcode};
 use pipeline::operand::Operand;
 use pipeline::reorder_buffer::ReorderBuffer;
 use pipeline::reservation_staion::{FinishedCalc, RSEntry, RSStatus};
+use pipeline::wakeup::WakeupTable;
 use register::RegisterFile;
 use std::collections::HashMap;
 
 #[derive(Debug, Clone, Default)]
 pub struct AddressUnit {
     pub buf: HashMap<usize, RSEntry>,
+    /// Entries waiting for their base register.
+    wakeup: WakeupTable<usize>,
+    /// Entries whose operands are all ready.
+    ready: Vec<usize>,
 }
 
 /**
@@ -17,7 +22,9 @@ pub struct AddressUnit {
  */
 impl AddressUnit {
     pub fn clear(&mut self) {
-        self.buf.clear()
+        self.buf.clear();
+        self.wakeup.clear();
+        self.ready.clear();
     }
     pub fn issue(
         &mut self,
@@ -25,44 +32,54 @@ impl AddressUnit {
         inst: Instruction,
         reg: &RegisterFile,
         rob: &ReorderBuffer,
+        clock: usize,
     ) {
         let rs1 = inst.fields.rs1.unwrap();
         let imm = inst.fields.imm.unwrap_or(0);
+        let base = reg.get_reg_value(rs1, rob);
+        let is_waiting = self.wakeup.listen(base, rob_idx);
         let entry = RSEntry {
             rob_index: rob_idx,
             status: RSStatus::Wait,
             inst,
-            operand: (reg.get_reg_value(rs1, rob), Operand::Value(imm)),
+            operand: (base, Operand::Value(imm)),
             value: 0,
             remaining_clock: 1,
+            ready_at: if is_waiting { None } else { Some(clock) },
+            executed_at: None,
         };
         self.buf.insert(rob_idx, entry);
+        if !is_waiting {
+            self.ready.push(rob_idx);
+        }
     }
 
-    pub fn propagate(&mut self, job: &FinishedCalc) {
-        for entry in self.buf.values_mut() {
-            let (op1, op2) = entry.operand;
-            let new_op1 = match op1 {
-                Operand::Rob(target_rob) if target_rob == job.rob_idx => {
-                    Operand::Value(job.reg_value)
-                }
-                _ => op1,
-            };
-            entry.operand = (new_op1, op2);
+    pub fn propagate(&mut self, job: &FinishedCalc, clock: usize) {
+        for rob_idx in self.wakeup.take(job.rob_idx) {
+            if let Some(entry) = self.buf.get_mut(&rob_idx) {
+                entry.operand.0 = Operand::Value(job.reg_value);
+                entry.ready_at = Some(clock);
+                self.ready.push(rob_idx);
+            }
         }
     }
 
-    pub fn execute(&mut self, rob: &mut ReorderBuffer) -> Option<u32> {
+    pub fn execute(&mut self, rob: &mut ReorderBuffer, clock: usize) -> Option<u32> {
         // Jalr의 실행이 끝난경우 npc 반환
+        let buf = &mut self.buf;
         let (finished_idx, npc) = self
-            .buf
-            .iter_mut()
-            .filter_map(|(rob_idx, entry)| {
+            .ready
+            .drain(..)
+            .filter_map(|rob_idx| {
+                let entry = buf.get_mut(&rob_idx).unwrap();
                 if let (Operand::Value(reg_val), Operand::Value(imm)) = entry.operand {
                     entry.value = reg_val.wrapping_add(imm);
                     entry.status = RSStatus::Finished;
-                    let rob_entry = rob.get_mut(*rob_idx).unwrap();
+                    entry.executed_at = Some(clock);
+                    let rob_entry = rob.get_mut(rob_idx).unwrap();
                     rob_entry.addr = Operand::Value(entry.value);
+                    rob_entry.times.mark_ready(entry.ready_at.unwrap());
+                    rob_entry.times.mark_executed(clock);
                     Some((
                         rob_idx,
                         if entry.inst.opcode == Opcode::Jalr {
@@ -75,7 +92,7 @@ impl AddressUnit {
                     None
                 }
             })
-            .fold((Vec::new(), 0u32), |(mut vec, npc), (&idx, jalr_val)| {
+            .fold((Vec::new(), 0u32), |(mut vec, npc), (idx, jalr_val)| {
                 vec.push(idx);
                 if npc == 0 {
                     (vec, jalr_val)
@@ -1,76 +1,191 @@
+use csr::CsrFile;
 use instruction::{Opcode, Function};
+use memory::dram::Requester;
+use memory::mmu::{AccessType, Mmu, Translation};
+use memory::system::{AccessKind, MemorySystem};
 use memory::ProcessMemory;
 use pipeline::exception::Exception;
 use pipeline::operand::Operand;
 use pipeline::reorder_buffer::ReorderBufferEntry;
+use pipeline::reservation_staion::FinishedCalc;
 
 pub struct MemoryUnit();
 
 impl MemoryUnit {
-    pub fn execute_store(store_entry: &mut ReorderBufferEntry, mem: &mut ProcessMemory) {
-        use self::Function::*;
-        if let Opcode::Amo = store_entry.inst.opcode {
-            if store_entry.reg_value.is_none() {
-                return;
-            }
+    /// Executes the store, `sc.w` or AMO at the ROB head. Memory is updated
+    /// in one step when the request for the line goes out on the bus, which
+    /// keeps AMOs atomic with respect to other harts, and the instruction
+    /// completes once the line is owned. Returns the value of `rd` for `sc.w`
+    /// and AMOs.
+    pub fn execute_store(
+        rob_idx: usize,
+        store_entry: &mut ReorderBufferEntry,
+        mem_sys: &mut MemorySystem,
+        hart: usize,
+        mmu: &mut Mmu,
+        csr: &CsrFile,
+        arrived: &[Requester],
+    ) -> Option<FinishedCalc> {
+        if store_entry.mem_rem_cycle == 0 {
+            return None;
         }
+        let (addr, value) = match (store_entry.addr, store_entry.mem_value) {
+            (Operand::Value(addr), Operand::Value(value)) => (addr, value),
+            _ => return None,
+        };
+        let is_amo = store_entry.inst.opcode == Opcode::Amo;
+        let finish = |reg_value: u32, exception: Option<Exception>| {
+            if is_amo {
+                Some(FinishedCalc {
+                    rob_idx,
+                    reg_value,
+                    exception,
+                })
+            } else {
+                None
+            }
+        };
 
-        if let (Operand::Value(addr), Operand::Value(value)) =
-            (store_entry.addr, store_entry.mem_value)
-        {
-            match store_entry.inst.function {
-                Sb => mem.write(addr, value as u8),
-                Sh => mem.write(addr, value as u16),
-                _ => mem.write(addr, value as u32),
+        if store_entry.mem_paddr.is_some() {
+            if !arrived.contains(&Requester::Store(rob_idx)) {
+                return None;
             }
-            .unwrap();
             store_entry.mem_rem_cycle = 0;
+            // 요청을 보낼 때 mem_value를 rd 값으로 바꿔 둠
+            return finish(value, None);
+        }
+
+        let paddr = match mmu.translate(addr, AccessType::Store, csr) {
+            Translation::Done(paddr) => paddr,
+            Translation::Pending => return None,
+            Translation::Fault(exception) => {
+                store_entry.mem_exception = Err(exception);
+                store_entry.mem_rem_cycle = 0;
+                return finish(0, Some(exception));
+            }
+        };
+        if let Function::Scw = store_entry.inst.function {
+            let has_reservation = mem_sys.holds_reservation(hart, paddr);
+            mem_sys.clear_reservation(hart);
+            if !has_reservation {
+                store_entry.mem_rem_cycle = 0;
+                return finish(1, None);
+            }
+        }
+        let replaced = Self::peek(store_entry.inst.function, paddr, &mem_sys.memory);
+        match Self::write(store_entry.inst.function, paddr, value, mem_sys) {
+            Ok((reg_value, written)) => {
+                mem_sys.access(hart, Requester::Store(rob_idx), paddr, AccessKind::Write);
+                store_entry.mem_paddr = Some(paddr);
+                store_entry.mem_written = Some(written);
+                store_entry.mem_replaced = replaced;
+                store_entry.mem_value = Operand::Value(reg_value);
+                None
+            }
+            Err(exception) => {
+                store_entry.mem_exception = Err(exception);
+                store_entry.mem_rem_cycle = 0;
+                finish(0, Some(exception))
+            }
+        }
+    }
+
+    /// Contents of the bytes a store would overwrite, if they are in memory
+    /// rather than a device.
+    fn peek(function: Function, addr: u32, memory: &ProcessMemory) -> Option<u32> {
+        if memory.mmio.contains(addr) {
+            return None;
+        }
+        match function {
+            Function::Sb => memory.read::<u8>(addr).map(u32::from),
+            Function::Sh => memory.read::<u16>(addr).map(u32::from),
+            _ => memory.read::<u32>(addr),
+        }
+        .ok()
+    }
+
+    /// Updates memory and returns the value of `rd` and the value written.
+    pub fn write(
+        function: Function,
+        addr: u32,
+        value: u32,
+        mem_sys: &mut MemorySystem,
+    ) -> Result<(u32, u32), Exception> {
+        use self::Function::*;
+        if mem_sys.clint.contains(addr) {
+            return match function {
+                Sw => mem_sys.clint.write(addr, value).map(|_| (0, value)),
+                _ => Err(Exception::StoreAccessFault(addr)),
+            };
+        }
+        let mem = &mut mem_sys.memory;
+        if mem.mmio.contains(addr) && !matches!(function, Sb | Sh | Sw) {
+            return Err(Exception::StoreAccessFault(addr));
+        }
+        let written = match function {
+            Sb => mem.write(addr, value as u8).map(|_| (0, value & 0xff)),
+            Sh => mem.write(addr, value as u16).map(|_| (0, value & 0xffff)),
+            Sw | Scw => mem.write(addr, value).map(|_| (0, value)),
+            _ => {
+                let old = mem.read::<u32>(addr)?;
+                let new = match function {
+                    Amoaddw => old.wrapping_add(value),
+                    Amoandw => old & value,
+                    Amoorw => old | value,
+                    Amoxorw => old ^ value,
+                    Amomaxuw => std::cmp::max(old, value),
+                    Amomaxw => std::cmp::max(old as i32, value as i32) as u32,
+                    Amominuw => std::cmp::min(old, value),
+                    Amominw => std::cmp::min(old as i32, value as i32) as u32,
+                    Amoswapw => value,
+                    _ => unreachable!(),
+                };
+                mem.write(addr, new).map(|_| (old, new))
+            }
+        }?;
+        mem_sys.stored(addr)?;
+        Ok(written)
+    }
+
+    /// Performs a load from a device register. Only called at the ROB head,
+    /// as reading a device may have side effects.
+    pub fn execute_device(
+        addr: u32,
+        function: Function,
+        mem: &mut ProcessMemory,
+    ) -> Result<u32, Exception> {
+        use self::Function::*;
+        match function {
+            Lb => mem.read_device(addr, 1).map(|val| val as i8 as u32),
+            Lbu => mem.read_device(addr, 1),
+            Lh => mem.read_device(addr, 2).map(|val| val as i16 as u32),
+            Lhu => mem.read_device(addr, 2),
+            Lw => mem.read_device(addr, 4),
+            _ => Err(Exception::LoadAccessFault(addr)),
         }
     }
 
     pub fn execute(
         addr: u32,
-        load_entry: &mut ReorderBufferEntry,
-        mem: &ProcessMemory,
+        function: Function,
+        mem_sys: &MemorySystem,
     ) -> Result<u32, Exception> {
         use self::Function::*;
+        if mem_sys.clint.contains(addr) {
+            return match function {
+                Lw => mem_sys.clint.read(addr),
+                _ => Err(Exception::LoadAccessFault(addr)),
+            };
+        }
+        let mem = &mem_sys.memory;
         // Store 확인은 Load Buffer에서 할 일 이므로 여기선 처리 안해도 됨.
-        let value = match load_entry.inst.function {
+        match function {
             Lb => mem.read::<i8>(addr).map(|val| val as u32),
             Lbu => mem.read::<u8>(addr).map(|val| val as u32),
             Lh => mem.read::<i16>(addr).map(|val| val as u32),
             Lhu => mem.read::<u16>(addr).map(|val| val as u32),
-            Lw => mem.read::<u32>(addr),
-            _ => {
-                let value = mem.read::<u32>(addr)?;
-                let value_to_calc = if let Operand::Value(val) = load_entry.mem_value {
-                    val
-                } else {
-                    unreachable!()
-                };
-
-                if let Lrw = load_entry.inst.function {
-                } else {
-                    load_entry.mem_rem_cycle = crate::consts::MEM_CYCLE;
-                }
-
-                let mem_val = match load_entry.inst.function {
-                    Lrw => 0,
-                    Amoaddw => value + value_to_calc,
-                    Amoandw => value & value_to_calc,
-                    Amoorw => value | value_to_calc,
-                    Amoxorw => value ^ value_to_calc,
-                    Amomaxuw => std::cmp::max(value, value_to_calc),
-                    Amomaxw => std::cmp::max(value as i32, value_to_calc as i32) as u32,
-                    Amominuw => std::cmp::min(value, value_to_calc),
-                    Amominw => std::cmp::min(value as i32, value_to_calc as i32) as u32,
-                    Amoswapw => value_to_calc,
-                    _ => unreachable!(),
-                };
-                load_entry.mem_value = Operand::Value(mem_val);
-                Ok(value)
-            }
-        };
-        value
+            Lw | Lrw => mem.read::<u32>(addr),
+            _ => unreachable!(),
+        }
     }
 }
@@ -0,0 +1,56 @@
+//! Interrupt bookkeeping of a hart.
+
+use std::fmt;
+
+#[derive(Debug, Default, Clone)]
+pub struct InterruptStats {
+    pub taken: usize,
+    /// Sum over taken interrupts of the clocks between the pending bit
+    /// rising and the handler being entered.
+    pub total_latency: usize,
+    pub max_latency: usize,
+}
+
+impl fmt::Display for InterruptStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let avg_latency = if self.taken == 0 {
+            0.0
+        } else {
+            self.total_latency as f64 / self.taken as f64
+        };
+        write!(
+            f,
+            "taken: {}, avg latency: {:.2}, max latency: {}",
+            self.taken, avg_latency, self.max_latency
+        )
+    }
+}
+
+/// Remembers when each pending bit of `mip` rose, to measure latency.
+#[derive(Debug, Default, Clone)]
+pub struct InterruptTracker {
+    raised_at: [Option<usize>; 16],
+    pub stats: InterruptStats,
+}
+
+impl InterruptTracker {
+    pub fn update(&mut self, mip: u32, clock: usize) {
+        for (irq, raised_at) in self.raised_at.iter_mut().enumerate() {
+            if (mip >> irq) & 1 == 0 {
+                *raised_at = None;
+            } else if raised_at.is_none() {
+                *raised_at = Some(clock);
+            }
+        }
+    }
+
+    pub fn taken(&mut self, irq: u32, clock: usize) {
+        let raised_at = self.raised_at[irq as usize].unwrap_or(clock);
+        let latency = clock - raised_at;
+        self.stats.taken += 1;
+        self.stats.total_latency += latency;
+        self.stats.max_latency = std::cmp::max(self.stats.max_latency, latency);
+        // 같은 pending이 계속 유지되더라도 다음 번은 지금부터 측정
+        self.raised_at[irq as usize] = Some(clock);
+    }
+}
@@ -0,0 +1,196 @@
+//! Undo journal of retired instructions.
+//!
+//! While recording, each instruction that retires leaves an `UndoRecord` of
+//! the architectural state it changed: the registers it wrote and the memory
+//! a store or AMO overwrote, with the contents before and after, and its PC.
+//! Undoing the records from the newest back puts the registers and memory
+//! back to what they were before any of them retired.
+//!
+//! CSRs, privilege, threads and memory changed by system calls or devices
+//! aren't recorded, so the records of system instructions and of traps can't
+//! be undone and mark how far back the hart can go.
+
+use consts;
+use instruction::{Function, Instruction, Opcode};
+use memory::ProcessMemory;
+use pipeline::reorder_buffer::ReorderBufferEntry;
+use register::RegisterFile;
+
+/// A register write: the register, and its value before and after.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RegWrite {
+    pub reg: u8,
+    pub old: u32,
+    pub new: u32,
+}
+
+/// A memory write of `size` bytes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MemWrite {
+    pub vaddr: u32,
+    pub paddr: u32,
+    pub size: u32,
+    pub old: u32,
+    pub new: u32,
+}
+
+impl MemWrite {
+    /// What a store, `sc.w` or AMO wrote to memory, once it has. `None` if
+    /// it wrote a device.
+    pub fn of(entry: &ReorderBufferEntry) -> Option<MemWrite> {
+        let (vaddr, size, new) = entry.mem_write()?;
+        Some(MemWrite {
+            vaddr,
+            paddr: entry.mem_paddr?,
+            size,
+            old: entry.mem_replaced?,
+            new,
+        })
+    }
+
+    /// Puts back the contents it overwrote.
+    pub fn restore(&self, memory: &mut ProcessMemory) {
+        let restored = match self.size {
+            1 => memory.write(self.paddr, self.old as u8),
+            2 => memory.write(self.paddr, self.old as u16),
+            _ => memory.write(self.paddr, self.old),
+        };
+        restored.expect("the memory was written before");
+    }
+
+    /// Whether it wrote any of the `len` bytes at virtual address `start`.
+    pub fn overlaps(&self, start: u32, len: u32) -> bool {
+        self.vaddr < start.wrapping_add(len) && start < self.vaddr.wrapping_add(self.size)
+    }
+}
+
+/// What one retired instruction changed.
+#[derive(Debug, Clone)]
+pub struct UndoRecord {
+    pub hart: usize,
+    /// Clock at which it retired.
+    pub clock: usize,
+    pub pc: u32,
+    pub inst: Instruction,
+    pub regs: Vec<RegWrite>,
+    pub mem: Option<MemWrite>,
+    /// Whether undoing it puts the hart back as it was before.
+    pub is_reversible: bool,
+}
+
+impl UndoRecord {
+    /// Marks a trap taken at `pc`, which changed CSRs and privilege.
+    pub fn trap(hart: usize, clock: usize, pc: u32) -> Self {
+        UndoRecord {
+            hart,
+            clock,
+            pc,
+            inst: Instruction::default(),
+            regs: Vec::new(),
+            mem: None,
+            is_reversible: false,
+        }
+    }
+
+    /// Puts back the registers and memory it changed. The hart continues
+    /// from `pc`.
+    pub fn undo(&self, reg: &mut RegisterFile, memory: &mut ProcessMemory) {
+        for write in self.regs.iter() {
+            reg.gpr[write.reg as usize].write(write.old);
+        }
+        if let Some(mem) = self.mem {
+            mem.restore(memory);
+        }
+    }
+}
+
+/// The registers `entry` writes when it retires, with their values before it
+/// does: its `rd`, or a0 for an `ecall` or `ebreak`, which return there. The
+/// new values are filled in by `wrote`.
+pub fn reg_writes(entry: &ReorderBufferEntry, reg: &RegisterFile) -> Vec<RegWrite> {
+    let rd = match entry.inst.function {
+        Function::Ecall | Function::Ebreak => consts::SYSCALL_RET_REG as u8,
+        _ => entry.rd,
+    };
+    if rd == 0 {
+        return Vec::new();
+    }
+    let old = reg.gpr[rd as usize].read();
+    vec![RegWrite {
+        reg: rd,
+        old,
+        new: old,
+    }]
+}
+
+/// Fills in the values `writes` left in the registers.
+pub fn wrote(writes: &mut [RegWrite], reg: &RegisterFile) {
+    for write in writes.iter_mut() {
+        write.new = reg.gpr[write.reg as usize].read();
+    }
+}
+
+/// Whether undoing what `entry` wrote to registers and memory is enough to
+/// go back to before it.
+pub fn is_reversible(entry: &ReorderBufferEntry) -> bool {
+    entry.inst.opcode != Opcode::System
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pipeline::reorder_buffer::ReorderBuffer;
+
+    #[test]
+    fn undo_puts_back_registers_and_memory() {
+        let mut reg = RegisterFile::new(0, 0);
+        let mut memory = ProcessMemory::default();
+        memory.data.resize(0x10, 0);
+        memory.v_address_range = (0, 0x10);
+        memory.stack_range = (0xffff_0000, 0);
+        memory.write(0x4, 0x1122_3344u32).unwrap();
+
+        // li a0, 7
+        let mut rob = ReorderBuffer::default();
+        let idx = rob.issue(0xfc, Instruction::new(0x0070_0513), &reg, false);
+        let mut regs = reg_writes(rob.get_mut(idx).unwrap(), &reg);
+        reg.gpr[10].write(7);
+        wrote(&mut regs, &reg);
+        assert_eq!(
+            regs,
+            vec![RegWrite {
+                reg: 10,
+                old: 0,
+                new: 7
+            }]
+        );
+        // 같은 값을 다시 써도 기록함
+        let mut again = reg_writes(rob.get_mut(idx).unwrap(), &reg);
+        wrote(&mut again, &reg);
+        assert_eq!(again[0].old, again[0].new);
+
+        // sb a0, 5(zero)
+        memory.write(0x5, 7u8).unwrap();
+        let record = UndoRecord {
+            hart: 0,
+            clock: 1,
+            pc: 0x100,
+            inst: Instruction::new(0x00a0_02a3),
+            regs,
+            mem: Some(MemWrite {
+                vaddr: 0x5,
+                paddr: 0x5,
+                size: 1,
+                old: 0x33,
+                new: 7,
+            }),
+            is_reversible: true,
+        };
+        assert!(record.mem.unwrap().overlaps(0x4, 2));
+        assert!(!record.mem.unwrap().overlaps(0x6, 2));
+
+        record.undo(&mut reg, &mut memory);
+        assert_eq!(reg.gpr[10].read(), 0);
+        assert_eq!(memory.read::<u32>(0x4).unwrap(), 0x1122_3344);
+    }
+}
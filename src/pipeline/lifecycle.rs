@@ -0,0 +1,51 @@
+//! Per-instruction lifecycle records.
+//!
+//! Each dynamic instruction carries the clocks at which it passed the points
+//! of the pipeline, from its fetch queue entry into its ROB entry. The buffers
+//! it visits on the way fill in when its operands became ready and when it
+//! started executing. When it retires or is squashed, the hart turns the
+//! timestamps into an `InstRecord`, if lifecycle tracing is on.
+
+use instruction::Instruction;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Timestamps {
+    /// Position among the instructions fetched by the hart.
+    pub seq: usize,
+    pub fetched: usize,
+    /// Issued to the ROB and the reservation station.
+    pub issued: Option<usize>,
+    /// The first clock it could execute with all of its operands.
+    pub ready: Option<usize>,
+    /// The first clock it spent in a functional unit.
+    pub executed: Option<usize>,
+    /// Its result was written back and it could retire.
+    pub completed: Option<usize>,
+}
+
+impl Timestamps {
+    pub fn mark_ready(&mut self, clock: usize) {
+        self.ready.get_or_insert(clock);
+    }
+
+    pub fn mark_executed(&mut self, clock: usize) {
+        self.executed.get_or_insert(clock);
+    }
+
+    pub fn mark_completed(&mut self, clock: usize) {
+        self.completed.get_or_insert(clock);
+    }
+}
+
+/// An instruction that left the pipeline.
+#[derive(Debug, Clone)]
+pub struct InstRecord {
+    pub hart: usize,
+    pub pc: u32,
+    pub inst: Instruction,
+    pub times: Timestamps,
+    /// Clock at which it retired or was squashed.
+    pub ended: usize,
+    /// Squashed on a wrong path, or by a trap or an interrupt.
+    pub flushed: bool,
+}
@@ -1,8 +1,11 @@
 use super::operand::Operand;
 use super::reorder_buffer::ReorderBuffer;
 use super::reservation_staion::FinishedCalc;
+use csr::CsrFile;
 use instruction::Opcode;
-use memory::ProcessMemory;
+use memory::dram::Requester;
+use memory::mmu::{AccessType, Mmu, Translation};
+use memory::system::{AccessKind, MemorySystem};
 use pipeline::exception::Exception;
 use pipeline::functional_units::memory::MemoryUnit;
 use register::RegisterFile;
@@ -20,11 +23,23 @@ pub struct LoadBufferEntry {
     pub rob_index: usize,
     pub status: LoadBufferStatus,
     pub value: Result<u32, Exception>,
+    /// Translated address, valid once the entry is executing.
+    pub paddr: u32,
+    /// Stores and AMOs issued before this load, as counted by the ROB.
+    pub older_mem_writes: usize,
+    /// Clock at which its address was known and no older store was in the
+    /// way.
+    pub ready_at: Option<usize>,
+    /// Clock at which it was sent to memory.
+    pub executed_at: Option<usize>,
 }
 
 #[derive(Debug, Default)]
 pub struct LoadBuffer {
     buf: HashMap<usize, LoadBufferEntry>,
+    /// Clocks from sending each load to memory to getting its value, until
+    /// they are taken.
+    latencies: Vec<usize>,
 }
 
 impl LoadBuffer {
@@ -32,6 +47,31 @@ impl LoadBuffer {
         self.buf.clear();
     }
 
+    pub fn occupancy(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Entries in use, in no particular order.
+    pub fn entries(&self) -> impl Iterator<Item = &LoadBufferEntry> {
+        self.buf.values()
+    }
+
+    pub fn contains(&self, rob_index: usize) -> bool {
+        self.buf.contains_key(&rob_index)
+    }
+
+    /// Entries waiting for memory.
+    pub fn in_flight(&self) -> usize {
+        self.buf
+            .values()
+            .filter(|entry| matches!(entry.status, LoadBufferStatus::Execute))
+            .count()
+    }
+
+    pub fn take_latencies(&mut self) -> Vec<usize> {
+        std::mem::take(&mut self.latencies)
+    }
+
     pub fn issue(&mut self, rob_index: usize, rob: &ReorderBuffer, reg: &RegisterFile) {
         let rob_entry = rob.get(rob_index).unwrap();
         let inst = &rob_entry.inst;
@@ -43,6 +83,10 @@ impl LoadBuffer {
                         rob_index,
                         status: LoadBufferStatus::Wait,
                         value: Ok(0),
+                        paddr: 0,
+                        older_mem_writes: rob.mem_writes_issued(),
+                        ready_at: None,
+                        executed_at: None,
                     },
                 );
             }
@@ -74,7 +118,6 @@ impl LoadBuffer {
     }
 
     fn is_load_ready(load: &LoadBufferEntry, rob: &ReorderBuffer) -> bool {
-        use instruction::Function;
         if let LoadBufferStatus::Finished = load.status {
             return false;
         }
@@ -89,49 +132,101 @@ impl LoadBuffer {
             return false;
         }
 
-        // Amo는 RS2까지 대기하다가 실행
-        if let Opcode::Amo = rob_entry.inst.opcode {
-            if let Operand::Rob(_) = rob_entry.mem_value {
-                return false;
-            }
-        }
-
         let has_to_wait = rob
-            .iter_with_id()
-            .take_while(|(id, _)| *id != load.rob_index)
-            .any(|(_, entry)| match entry.inst.opcode {
-                Opcode::Store | Opcode::Amo if entry.inst.function != Function::Lrw => {
-                    match entry.addr {
-                        Operand::Rob(_) => true,
-                        Operand::Value(addr) if addr == my_addr.unwrap() => true,
-                        _ => false,
-                    }
-                }
+            .older_mem_writes(load.older_mem_writes)
+            .any(|entry| match entry.addr {
+                Operand::Rob(_) => true,
+                Operand::Value(addr) if addr == my_addr.unwrap() => true,
                 _ => false,
             });
 
         !has_to_wait
     }
 
-    pub fn execute(&mut self, rob: &mut ReorderBuffer, mem: &ProcessMemory) {
+    #[allow(clippy::too_many_arguments)]
+    pub fn execute(
+        &mut self,
+        rob: &mut ReorderBuffer,
+        mem_sys: &mut MemorySystem,
+        hart: usize,
+        mmu: &mut Mmu,
+        csr: &CsrFile,
+        arrived: &[Requester],
+        clock: usize,
+    ) {
         for (idx, entry) in self.buf.iter_mut() {
-            if !Self::is_load_ready(entry, rob) {
-                continue;
-            }
-            entry.status = LoadBufferStatus::Execute;
-
-            let rob_entry = rob.get_mut(*idx).unwrap();
-            let addr = if let Operand::Value(a) = rob_entry.addr {
-                a
-            } else {
-                unreachable!()
-            };
-
-            rob_entry.mem_rem_cycle = rob_entry.mem_rem_cycle.saturating_sub(1);
-            if rob_entry.mem_rem_cycle == 0 {
-                entry.value = MemoryUnit::execute(addr, rob_entry, mem);
-                entry.status = LoadBufferStatus::Finished;
+            match entry.status {
+                LoadBufferStatus::Wait => {
+                    if !Self::is_load_ready(entry, rob) {
+                        continue;
+                    }
+                    let ready_at = *entry.ready_at.get_or_insert(clock);
+                    match mmu.translate(Self::target_addr(rob, *idx), AccessType::Load, csr) {
+                        Translation::Done(paddr) if mem_sys.memory.mmio.contains(paddr) => {
+                            // device 읽기는 부작용이 있을 수 있으므로 ROB head에서만 실행
+                            if rob.nth_index(0) != Some(*idx) {
+                                continue;
+                            }
+                            let rob_entry = rob.get_mut(*idx).unwrap();
+                            entry.value = MemoryUnit::execute_device(
+                                paddr,
+                                rob_entry.inst.function,
+                                &mut mem_sys.memory,
+                            );
+                            rob_entry.mem_paddr = Some(paddr);
+                            mem_sys.access(hart, Requester::Load(*idx), paddr, AccessKind::Read);
+                            entry.paddr = paddr;
+                            entry.status = LoadBufferStatus::Execute;
+                        }
+                        Translation::Done(paddr) => {
+                            mem_sys.access(hart, Requester::Load(*idx), paddr, AccessKind::Read);
+                            entry.paddr = paddr;
+                            entry.status = LoadBufferStatus::Execute;
+                        }
+                        Translation::Pending => {}
+                        Translation::Fault(exception) => {
+                            rob.get_mut(*idx).unwrap().mem_rem_cycle = 0;
+                            entry.value = Err(exception);
+                            entry.status = LoadBufferStatus::Finished;
+                        }
+                    }
+                    if let LoadBufferStatus::Wait = entry.status {
+                        continue;
+                    }
+                    entry.executed_at = Some(clock);
+                    // lr.w는 address unit을 거치지 않으므로 여기서 기록
+                    let times = &mut rob.get_mut(*idx).unwrap().times;
+                    times.mark_ready(ready_at);
+                    times.mark_executed(clock);
+                }
+                LoadBufferStatus::Execute => {
+                    if !arrived.contains(&Requester::Load(*idx)) {
+                        continue;
+                    }
+                    let rob_entry = rob.get_mut(*idx).unwrap();
+                    rob_entry.mem_rem_cycle = 0;
+                    // device에서 읽은 값은 요청을 보낼 때 이미 받아 둠
+                    if rob_entry.mem_paddr.is_none() {
+                        entry.value =
+                            MemoryUnit::execute(entry.paddr, rob_entry.inst.function, mem_sys);
+                    }
+                    // 값을 읽은 뒤의 다른 hart의 write가 reservation을 깨도록 여기서 등록
+                    if let Opcode::Amo = rob_entry.inst.opcode {
+                        mem_sys.reserve(hart, entry.paddr);
+                    }
+                    entry.status = LoadBufferStatus::Finished;
+                    self.latencies.push(clock - entry.executed_at.unwrap());
+                }
+                LoadBufferStatus::Finished => {}
             }
         }
     }
+
+    fn target_addr(rob: &ReorderBuffer, rob_index: usize) -> u32 {
+        if let Operand::Value(a) = rob.get(rob_index).unwrap().addr {
+            a
+        } else {
+            unreachable!()
+        }
+    }
 }
@@ -2,51 +2,443 @@
 
 pub mod branch_predictor;
 pub mod exception;
+pub mod fetch;
+pub mod functional;
 pub mod functional_units;
+pub mod interrupt;
+pub mod journal;
+pub mod lifecycle;
 pub mod load_buffer;
 pub mod operand;
 pub mod reorder_buffer;
+pub mod rename;
 pub mod reservation_staion;
+pub mod semihosting;
+pub mod stats;
+pub mod thread;
+pub mod wakeup;
 
 use self::exception::Exception;
+use self::journal::{MemWrite, UndoRecord};
+use self::lifecycle::InstRecord;
 use self::reorder_buffer::ReorderBufferEntry;
+use self::semihosting::Semihosting;
+use self::thread::{ThreadContext, ThreadTable};
 use consts;
-use instruction::Function;
+use csr;
+use instruction::{Function, Instruction};
 use memory;
+use memory::cache;
+use memory::clint;
+use memory::dram;
+use memory::dram::Requester;
+use memory::mmu;
+use memory::system::MemorySystem;
 use register;
+use std::collections::HashSet;
 
-/// Pipeline holding four inter-stage registers
+/// Instructions issued per clock, which is also the number of commit slots
+/// top-down accounting splits each clock into.
+pub const ISSUE_WIDTH: usize = 2;
+
+/// `pmpcfg` byte for a NAPOT region with every permission.
+const PMP_ALLOW_ALL: u32 = 0x1f;
+
+/// Floating-point instructions aren't modelled and run as `nop`s.
+fn without_fp(inst: Instruction) -> Instruction {
+    use instruction::Opcode;
+    match inst.opcode {
+        Opcode::Fmadd
+        | Opcode::Fmsub
+        | Opcode::Fnmadd
+        | Opcode::Fnmsub
+        | Opcode::OpFp
+        | Opcode::StoreFp
+        | Opcode::LoadFp => Instruction::default(),
+        _ => inst,
+    }
+}
+
+/// Tunable parameters of the simulated machine.
+#[derive(Debug, Clone, Default)]
+pub struct PipelineConfig {
+    pub dram: dram::DramConfig,
+    pub l1d: cache::CacheConfig,
+    pub clint: clint::ClintConfig,
+    pub mmu: mmu::MmuConfig,
+    pub fetch: fetch::FetchConfig,
+    pub rename: rename::RenameConfig,
+    /// Boots harts in M-mode and lets `ecall` trap to the guest. Otherwise
+    /// the program runs in S-mode with every exception delegated to it, and
+    /// `ecall` goes to the host.
+    pub full_system: bool,
+    /// Keeps an `InstRecord` of every instruction that retires or is
+    /// squashed.
+    pub trace_lifecycle: bool,
+}
+
+/// Where commit stops for a debugger: before an instruction at a
+/// breakpoint retires, after a store to a watched range retires, or once a
+/// number of instructions have retired. The rest of the clock is held back
+/// when it stops, so the next instruction hasn't started writing memory.
+#[derive(Debug, Clone, Default)]
+pub struct CommitStop {
+    pub breakpoints: HashSet<u32>,
+    /// Start and length of each watched range.
+    pub watchpoints: Vec<(u32, u32)>,
+    /// Instructions left to retire before stopping.
+    pub budget: Option<usize>,
+    /// Breakpoint the hart is resumed from, which the first instruction to
+    /// retire may pass.
+    pub resume_at: Option<u32>,
+    /// Breakpoint commit stopped before.
+    pub hit: Option<u32>,
+    /// Commit stopped during the last clock.
+    pub stopped: bool,
+}
+
+impl CommitStop {
+    /// Number of the ROB `entries`, in program order, that may retire. The
+    /// head is checked for a breakpoint before it has completed, as a store
+    /// there writes memory first.
+    fn limit<'a, I>(&mut self, entries: I) -> usize
+    where
+        I: Iterator<Item = &'a ReorderBufferEntry>,
+    {
+        self.stopped = false;
+        if self.breakpoints.is_empty() && self.watchpoints.is_empty() && self.budget.is_none() {
+            return usize::MAX;
+        }
+        let mut limit = 0;
+        for entry in entries.take(self.budget.unwrap_or(usize::MAX)) {
+            let is_resumed = limit == 0 && self.resume_at == Some(entry.pc);
+            if self.breakpoints.contains(&entry.pc) && !is_resumed {
+                self.hit = Some(entry.pc);
+                self.stopped = true;
+                break;
+            }
+            if !entry.is_completed() {
+                break;
+            }
+            limit += 1;
+            let is_watched = self
+                .watchpoints
+                .iter()
+                .any(|&(start, len)| entry.writes_to(start, len));
+            if is_watched {
+                self.stopped = true;
+                break;
+            }
+        }
+        limit
+    }
+
+    fn retired(&mut self, count: usize) {
+        if let Some(budget) = self.budget.as_mut() {
+            *budget = budget.saturating_sub(count);
+            self.stopped |= *budget == 0;
+        }
+        if count > 0 {
+            self.resume_at = None;
+        }
+    }
+}
+
+/// Pipeline of a single hart. Memory is shared with the other harts and is
+/// passed in on every clock.
 #[derive(Debug)]
 pub struct Pipeline {
+    pub hart_id: usize,
+    /// Thread running on the hart, or `None` while the hart is idle.
+    pub tid: Option<u32>,
+    /// Address given to `set_tid_address` by the running thread.
+    pub clear_child_tid: u32,
     pub reg: register::RegisterFile,
-    pub memory: memory::ProcessMemory,
+    pub fetch_queue: fetch::FetchQueue,
     pub rob: reorder_buffer::ReorderBuffer,
     pub rs: reservation_staion::ReservationStation,
     pub branch_predictor: branch_predictor::BranchPredictor,
+    pub mmu: mmu::Mmu,
+    pub interrupts: interrupt::InterruptTracker,
+    pub rename_stats: rename::RenameStats,
+    pub stats: stats::PipelineStats,
+    /// Destinations renamed but neither retired nor squashed yet.
+    renamed_in_flight: usize,
+    /// Issue waits until this clock while squashed mappings are walked back.
+    recovering_until: usize,
+    /// A mispredict emptied the ROB and no correct-path instruction has
+    /// been issued since.
+    refilling: bool,
+    /// Instructions that left the pipeline since the records were last
+    /// taken, when lifecycle tracing is on.
+    pub lifecycle: Option<Vec<InstRecord>>,
+    /// What each instruction that retired since the records were last taken
+    /// changed, while a debugger records.
+    pub journal: Option<Vec<UndoRecord>>,
+    /// Stops fetching so the pipeline empties before a checkpoint.
+    pub draining: bool,
+    pub commit_stop: CommitStop,
+    pub full_system: bool,
     pub clock: usize,
 }
 
 impl Pipeline {
-    pub fn new(entry_point: u32, memory: memory::ProcessMemory) -> Pipeline {
+    /// Constructs the pipeline of hart `hart_id`. The hart stays idle until
+    /// a thread is resumed on it.
+    pub fn new(hart_id: usize, config: PipelineConfig) -> Pipeline {
+        let mut reg = register::RegisterFile::new(0, 0);
+        reg.csr.mhartid = hart_id as u32;
+        if !config.full_system {
+            // M-mode firmware가 없으므로 모든 exception을 S-mode로 위임하고
+            // PMP로 전체 메모리를 열어 둠. 프로그램은 pk에서처럼 U-mode로 실행
+            reg.csr.write(csr::MEDELEG, !0).unwrap();
+            reg.csr.write(csr::PMPADDR0, !0).unwrap();
+            reg.csr.write(csr::PMPCFG0, PMP_ALLOW_ALL).unwrap();
+            reg.csr.privilege = csr::Privilege::User;
+        }
+        if config.rename.model == rename::RenameModel::PhysRegFile {
+            reg.prf = Some(rename::PhysRegFile::new(config.rename.phys_regs));
+        }
         Pipeline {
-            reg: register::RegisterFile::new(entry_point, memory.stack_pointer_init),
-            memory,
+            hart_id,
+            tid: None,
+            clear_child_tid: 0,
+            reg,
+            fetch_queue: fetch::FetchQueue::new(config.fetch),
             rob: Default::default(),
             rs: Default::default(),
             branch_predictor: Default::default(),
+            mmu: mmu::Mmu::new(config.mmu),
+            interrupts: Default::default(),
+            rename_stats: Default::default(),
+            stats: Default::default(),
+            renamed_in_flight: 0,
+            recovering_until: 0,
+            refilling: false,
+            lifecycle: if config.trace_lifecycle {
+                Some(Vec::new())
+            } else {
+                None
+            },
+            journal: None,
+            draining: false,
+            commit_stop: Default::default(),
+            full_system: config.full_system,
             clock: 0,
         }
     }
 
-    fn clear_all_buffers(&mut self) {
+    fn clear_all_buffers(&mut self, mem_sys: &mut MemorySystem) {
+        self.reg.sync_phys_regs();
+        let squashed = std::mem::replace(&mut self.renamed_in_flight, 0);
+        if squashed > 0 {
+            self.rename_stats.recoveries += 1;
+            self.rename_stats.squashed += squashed;
+            if self.reg.prf.is_some() {
+                let walk_clocks = squashed.div_ceil(rename::WALK_WIDTH);
+                self.rename_stats.recovery_clocks += walk_clocks;
+                self.recovering_until = self.clock + walk_clocks;
+            }
+        }
+        self.stats.squashed += self.rob.len();
+        let (hart, clock) = (self.hart_id, self.clock);
+        if let Some(records) = self.lifecycle.as_mut() {
+            records.extend(self.rob.iter().map(|entry| InstRecord {
+                hart,
+                pc: entry.pc,
+                inst: entry.inst.clone(),
+                times: entry.times,
+                ended: clock,
+                flushed: true,
+            }));
+        }
         self.rs.clear();
         self.rob.clear();
+        mem_sys.squash(self.hart_id);
         self.reg
             .related_rob
             .iter_mut()
             .for_each(|stat| *stat = None);
     }
 
+    /// Starts running `thread` on this idle hart.
+    pub fn resume(&mut self, thread: ThreadContext) {
+        for (reg, &value) in self.reg.gpr.iter_mut().zip(thread.gpr.iter()) {
+            reg.write(value);
+        }
+        self.reg.related_rob.iter_mut().for_each(|stat| *stat = None);
+        self.reg.sync_phys_regs();
+        self.tid = Some(thread.tid);
+        self.clear_child_tid = thread.clear_child_tid;
+        self.redirect(thread.pc);
+    }
+
+    /// Takes the running thread off the hart, to continue at `npc` later.
+    /// Only called right after an `ecall` retired, when nothing younger is in
+    /// flight.
+    fn park(&mut self, npc: u32, mem_sys: &mut MemorySystem) -> ThreadContext {
+        let tid = self.tid.take().unwrap();
+        self.clear_all_buffers(mem_sys);
+        self.squash_fetch_queue();
+        self.context(npc, tid)
+    }
+
+    fn context(&self, pc: u32, tid: u32) -> ThreadContext {
+        let mut gpr = [0; 32];
+        for (value, reg) in gpr.iter_mut().zip(self.reg.gpr.iter()) {
+            *value = reg.read();
+        }
+        ThreadContext {
+            tid,
+            pc,
+            gpr,
+            clear_child_tid: self.clear_child_tid,
+        }
+    }
+
+    /// Restarts fetch at `npc`, dropping everything in the fetch queue.
+    fn redirect(&mut self, npc: u32) {
+        self.reg.pc.write(npc);
+        self.squash_fetch_queue();
+    }
+
+    fn squash_fetch_queue(&mut self) {
+        self.stats.squashed += self.fetch_queue.len();
+        let (hart, clock) = (self.hart_id, self.clock);
+        if let Some(records) = self.lifecycle.as_mut() {
+            records.extend(self.fetch_queue.iter().map(|fetched| InstRecord {
+                hart,
+                pc: fetched.pc,
+                inst: fetched.inst.clone(),
+                times: fetched.times,
+                ended: clock,
+                flushed: true,
+            }));
+        }
+        self.fetch_queue.redirect();
+    }
+
+    /// Whether no instruction is in flight.
+    pub fn is_drained(&self) -> bool {
+        self.fetch_queue.is_empty() && self.rob.len() == 0
+    }
+
+    /// Squashes everything in flight and fetches again from `pc`, after a
+    /// debugger has changed registers or memory behind the pipeline.
+    pub fn restart_at(&mut self, pc: u32, mem_sys: &mut MemorySystem) {
+        self.clear_all_buffers(mem_sys);
+        self.redirect(pc);
+    }
+
+    /// PC of the oldest instruction that hasn't retired, which is the next
+    /// one to change the architectural state.
+    pub fn next_pc(&self) -> u32 {
+        match self.rob.iter().next() {
+            Some(head) => head.pc,
+            None => self
+                .fetch_queue
+                .front_pc()
+                .unwrap_or_else(|| self.reg.pc.read()),
+        }
+    }
+
+    /// Fetch clock of the oldest instruction still in the pipeline.
+    pub fn oldest_fetched(&self) -> Option<usize> {
+        self.rob
+            .iter()
+            .next()
+            .map(|entry| entry.times.fetched)
+            .or_else(|| self.fetch_queue.iter().next().map(|fetched| fetched.times.fetched))
+    }
+
+    /// Redirects execution to the M-mode trap handler, or to the S-mode one
+    /// if the exception is delegated. Exceptions that the guest can't handle
+    /// abort the simulation.
+    fn take_trap(&mut self, exception: Exception, epc: u32, mem_sys: &mut MemorySystem) {
+        let cause = exception
+            .trap_cause()
+            .unwrap_or_else(|| panic!("Exception at pc {:x}: {:?}", epc, exception));
+        let target = self.reg.csr.trap_target(cause);
+        if self.reg.csr.trap_vector(target) == 0 {
+            panic!(
+                "Exception at pc {:x} without a trap handler: {:?}",
+                epc, exception
+            );
+        }
+
+        self.stats.flushes += 1;
+        self.clear_all_buffers(mem_sys);
+        self.record_trap(epc);
+        let handler = self
+            .reg
+            .csr
+            .enter_trap(cause, epc, exception.trap_value());
+        self.redirect(handler);
+    }
+
+    /// Copies the CLINT lines of this hart into `mip`.
+    fn sample_interrupts(&mut self, mem_sys: &MemorySystem) {
+        let lines = [
+            (csr::IRQ_M_TIMER, mem_sys.clint.timer_pending(self.hart_id)),
+            (csr::IRQ_M_SOFT, mem_sys.clint.software_pending(self.hart_id)),
+        ];
+        for &(irq, is_pending) in lines.iter() {
+            if is_pending {
+                self.reg.csr.mip |= 1 << irq;
+            } else {
+                self.reg.csr.mip &= !(1 << irq);
+            }
+        }
+        self.interrupts.update(self.reg.csr.mip, self.clock);
+    }
+
+    /// Takes a pending interrupt right before the oldest instruction that
+    /// hasn't retired, which is flushed along with everything younger and
+    /// runs again once the handler returns.
+    fn take_interrupt(&mut self, mem_sys: &mut MemorySystem) {
+        let irq = match self.reg.csr.pending_interrupt() {
+            Some(irq) => irq,
+            None => return,
+        };
+        let epc = match self.rob.iter().next() {
+            // 이미 메모리를 바꾼 store나 AMO는 retire될 때까지 기다림
+            Some(head) if head.mem_paddr.is_some() => return,
+            _ => self.next_pc(),
+        };
+
+        self.stats.flushes += 1;
+        self.clear_all_buffers(mem_sys);
+        self.record_trap(epc);
+        let handler = self
+            .reg
+            .csr
+            .enter_trap(csr::CAUSE_INTERRUPT | irq, epc, 0);
+        self.redirect(handler);
+        self.interrupts.taken(irq, self.clock);
+    }
+
+    /// Leaves a record of a trap at `epc` in the undo journal, which going
+    /// back can't pass.
+    fn record_trap(&mut self, epc: u32) {
+        let (hart, clock) = (self.hart_id, self.clock);
+        if let Some(records) = self.journal.as_mut() {
+            records.push(UndoRecord::trap(hart, clock, epc));
+        }
+    }
+
+    /// In full-system mode, `ecall` traps to the guest once it has installed
+    /// a handler. Until then it still reaches the host, so bare programs can
+    /// call `exit`.
+    fn ecall_exception(&self) -> Option<Exception> {
+        let exception = Exception::EnvironmentCall(self.reg.csr.privilege as u32);
+        let target = self.reg.csr.trap_target(exception.trap_cause().unwrap());
+        if self.full_system && self.reg.csr.trap_vector(target) != 0 {
+            Some(exception)
+        } else {
+            None
+        }
+    }
+
     pub fn system_call(
         memory: &mut memory::ProcessMemory,
         reg: &mut register::RegisterFile,
@@ -108,7 +500,6 @@ impl Pipeline {
                 }
                 Ok(addr)
             }
-            93 | 94 => Ok(0),
             _ => Err(Exception::FailCallingSyscall(syscall_num)),
         };
         result.map(|ret_val| {
@@ -117,13 +508,165 @@ impl Pipeline {
         })
     }
 
-    pub fn commit(&mut self) -> Vec<(usize, ReorderBufferEntry)> {
+    /// Handles the system calls that create, block and end threads, and
+    /// returns false for any other call. A thread that blocks or exits leaves
+    /// the hart idle.
+    fn thread_call(
+        &mut self,
+        pc: u32,
+        mem_sys: &mut MemorySystem,
+        threads: &mut ThreadTable,
+    ) -> Result<bool, Exception> {
+        use self::thread::*;
+        let syscall_num = self.reg.gpr[consts::SYSCALL_NUM_REG].read();
+        let arg1 = self.reg.gpr[consts::SYSCALL_ARG1_REG].read();
+        let arg2 = self.reg.gpr[consts::SYSCALL_ARG2_REG].read();
+        let arg3 = self.reg.gpr[consts::SYSCALL_ARG3_REG].read();
+        let arg4 = self.reg.gpr[consts::SYSCALL_ARG4_REG].read();
+        let arg5 = self.reg.gpr[consts::SYSCALL_ARG5_REG].read();
+        let npc = pc.wrapping_add(consts::WORD_SIZE as u32);
+
+        let ret_val = match syscall_num {
+            93 => {
+                let thread = self.park(npc, mem_sys);
+                if thread.clear_child_tid != 0 {
+                    mem_sys.memory.write(thread.clear_child_tid, 0u32)?;
+                    threads.wake(thread.clear_child_tid, 1);
+                }
+                threads.exit_thread(arg1);
+                return Ok(true);
+            }
+            94 => {
+                threads.exit_group(arg1);
+                return Ok(true);
+            }
+            96 => {
+                self.clear_child_tid = arg1;
+                self.tid.unwrap()
+            }
+            98 => match arg2 & FUTEX_CMD_MASK {
+                FUTEX_WAIT | FUTEX_WAIT_BITSET => {
+                    if mem_sys.memory.read::<u32>(arg1)? != arg3 {
+                        EAGAIN.wrapping_neg()
+                    } else {
+                        // 깨어났을 때의 반환값을 미리 써 둠
+                        self.reg.gpr[consts::SYSCALL_RET_REG].write(0u32);
+                        let thread = self.park(npc, mem_sys);
+                        threads.block(arg1, thread);
+                        return Ok(true);
+                    }
+                }
+                FUTEX_WAKE | FUTEX_WAKE_BITSET => threads.wake(arg1, arg3),
+                _ => ENOSYS.wrapping_neg(),
+            },
+            178 => self.tid.unwrap(),
+            // 주소 공간을 공유하는 thread 생성만 지원
+            220 if arg1 & CLONE_VM == 0 => ENOSYS.wrapping_neg(),
+            220 => {
+                let tid = threads.new_tid();
+                let mut child = self.context(npc, tid);
+                child.gpr[consts::SYSCALL_RET_REG] = 0;
+                if arg2 != 0 {
+                    child.gpr[2] = arg2;
+                }
+                if arg1 & CLONE_SETTLS != 0 {
+                    child.gpr[4] = arg4;
+                }
+                child.clear_child_tid = if arg1 & CLONE_CHILD_CLEARTID != 0 {
+                    arg5
+                } else {
+                    0
+                };
+                if arg1 & CLONE_PARENT_SETTID != 0 {
+                    mem_sys.memory.write(arg3, tid)?;
+                }
+                if arg1 & CLONE_CHILD_SETTID != 0 {
+                    mem_sys.memory.write(arg5, tid)?;
+                }
+                threads.make_ready(child);
+                tid
+            }
+            _ => return Ok(false),
+        };
+        self.reg.gpr[consts::SYSCALL_RET_REG].write(ret_val);
+        Ok(true)
+    }
+
+    pub fn commit(
+        &mut self,
+        mem_sys: &mut MemorySystem,
+        threads: &mut ThreadTable,
+    ) -> Vec<(usize, ReorderBufferEntry)> {
         use instruction::Opcode;
-        let mut completed_entries = self.rob.completed_entries();
+        let limit = self.commit_stop.limit(self.rob.iter());
+        let mut completed_entries = self.rob.completed_entries(limit);
+        let mut has_trapped = false;
         let retired_count = completed_entries
-            .iter()
+            .iter_mut()
             .map(|(old_idx, entry)| {
-                let should_cancel = entry.retire(*old_idx, &mut self.memory, &mut self.reg);
+                let reg_writes = self
+                    .journal
+                    .as_ref()
+                    .map(|_| journal::reg_writes(entry, &self.reg));
+                let retired = match (entry.inst.function, self.ecall_exception()) {
+                    (Function::Ecall, Some(exception)) => Err(exception),
+                    _ => entry.retire(*old_idx, &mut self.reg),
+                };
+                let should_cancel = match retired {
+                    Ok(should_cancel) => should_cancel,
+                    Err(exception) => {
+                        self.take_trap(exception, entry.pc, mem_sys);
+                        has_trapped = true;
+                        return true;
+                    }
+                };
+                if entry.rd != 0 {
+                    self.renamed_in_flight -= 1;
+                }
+
+                match entry.inst.function {
+                    Function::SfenceVma => self.mmu.flush(),
+                    Function::Ecall => {
+                        let is_thread_call = self.thread_call(entry.pc, mem_sys, threads).unwrap();
+                        if !is_thread_call {
+                            Pipeline::system_call(&mut mem_sys.memory, &mut self.reg).unwrap();
+                        }
+                        // 같은 thread가 계속 실행되면 system call의 반환값을 rd 쓰기로 남김
+                        if self.tid.is_some() {
+                            entry.rd = consts::SYSCALL_RET_REG as u8;
+                            entry.reg_value = Some(self.reg.gpr[consts::SYSCALL_RET_REG].read());
+                        }
+                    }
+                    // 주소 변환 중에는 semihosting 호출을 인식하지 않음
+                    Function::Ebreak
+                        if !self.reg.csr.is_translating()
+                            && Semihosting::is_call(entry.pc, &mem_sys.memory) =>
+                    {
+                        let exit = mem_sys
+                            .semihosting
+                            .call(&mut self.reg, &mut mem_sys.memory);
+                        if let Some(code) = exit {
+                            threads.exit_group(code);
+                        }
+                    }
+                    // CSR 명령어는 이전 CSR 값을 rd에 씀
+                    _ if entry.inst.opcode == Opcode::System && entry.rd != 0 => {
+                        entry.reg_value = Some(self.reg.gpr[entry.rd as usize].read());
+                    }
+                    _ => {}
+                }
+                // System 명령어 뒤에서 멈춰 있던 fetch를 재개
+                if let Opcode::System = entry.inst.opcode {
+                    entry.csr_written = entry.written_csrs(&self.reg.csr);
+                    // ecall 등이 바꾼 architectural 값을 physical register에 반영
+                    self.reg.sync_phys_regs();
+                    let npc = match entry.inst.function {
+                        Function::Mret => self.reg.csr.mepc,
+                        Function::Sret => self.reg.csr.sepc,
+                        _ => entry.pc.wrapping_add(consts::WORD_SIZE as u32),
+                    };
+                    self.redirect(npc);
+                }
 
                 if let Opcode::Branch = entry.inst.opcode {
                     self.branch_predictor
@@ -133,8 +676,9 @@ impl Pipeline {
                 if !entry.inst.is_nop() {
                     if unsafe { crate::PRINT_STEPS } {
                         eprint!(
-                            "Clock #{} | pc: {:x} | val: {:08x} | inst: {:?} | fields: {}",
+                            "Clock #{} | hart: {} | pc: {:x} | val: {:08x} | inst: {:?} | fields: {}",
                             self.clock,
+                            self.hart_id,
                             entry.pc,
                             entry.inst.value,
                             entry.inst.function,
@@ -147,17 +691,30 @@ impl Pipeline {
                     }
                 }
 
+                if let (Some(mut regs), Some(records)) = (reg_writes, self.journal.as_mut()) {
+                    journal::wrote(&mut regs, &self.reg);
+                    records.push(UndoRecord {
+                        hart: self.hart_id,
+                        clock: self.clock,
+                        pc: entry.pc,
+                        inst: entry.inst.clone(),
+                        regs,
+                        mem: MemWrite::of(entry),
+                        is_reversible: journal::is_reversible(entry),
+                    });
+                }
+
                 if should_cancel {
-                    self.clear_all_buffers();
+                    self.stats.mispredicts += 1;
+                    self.stats.flushes += 1;
+                    self.clear_all_buffers(mem_sys);
+                    self.refilling = true;
+                    self.stats.top_down.resolve(true);
                     if let (Opcode::Branch, Some(is_taken)) = (entry.inst.opcode, entry.reg_value) {
                         if is_taken == 1 {
-                            self.reg
-                                .pc
-                                .write(entry.pc.wrapping_add(entry.inst.fields.imm.unwrap()));
+                            self.redirect(entry.pc.wrapping_add(entry.inst.fields.imm.unwrap()));
                         } else {
-                            self.reg
-                                .pc
-                                .write(entry.pc.wrapping_add(crate::consts::WORD_SIZE as u32));
+                            self.redirect(entry.pc.wrapping_add(crate::consts::WORD_SIZE as u32));
                         }
                     }
                 }
@@ -165,113 +722,337 @@ impl Pipeline {
             })
             .take_while(|&should_cancel| !should_cancel)
             .count();
+        // trap이 발생한 entry는 retire되지 않았으므로 제외
         let total_len = completed_entries.len();
-        completed_entries.truncate(std::cmp::min(retired_count + 1, total_len));
+        let retired_len = if has_trapped {
+            retired_count
+        } else {
+            retired_count + 1
+        };
+        // 꺼냈지만 retire되지 않은 entry는 flush된 것으로 기록
+        let (hart, clock) = (self.hart_id, self.clock);
+        if let Some(records) = self.lifecycle.as_mut() {
+            records.extend(
+                completed_entries
+                    .iter()
+                    .enumerate()
+                    .map(|(i, (_, entry))| InstRecord {
+                        hart,
+                        pc: entry.pc,
+                        inst: entry.inst.clone(),
+                        times: entry.times,
+                        ended: clock,
+                        flushed: i >= retired_len,
+                    }),
+            );
+        }
+        self.stats.squashed += total_len.saturating_sub(retired_len);
+        completed_entries.truncate(std::cmp::min(retired_len, total_len));
+        for (_, entry) in completed_entries.iter() {
+            self.stats.retire(&entry.inst);
+        }
+        self.account_slots(completed_entries.len());
+        // flush된 wrong path의 breakpoint에서는 멈추지 않음
+        if retired_count < total_len {
+            self.commit_stop.hit = None;
+            self.commit_stop.stopped = false;
+        }
+        self.commit_stop.retired(completed_entries.len());
         completed_entries
     }
 
-    fn is_program_finished(&self, retired_entries: &[(usize, ReorderBufferEntry)]) -> bool {
-        retired_entries.into_iter().any(|(_, rob_entry)| {
-            if let Function::Ecall = rob_entry.inst.function {
-                if let 93 | 94 = self.reg.gpr[consts::SYSCALL_NUM_REG].read() {
-                    return true;
-                }
+    /// Puts the commit slots `retired` instructions left unused in a
+    /// top-down category, by what holds up the oldest instruction. Slots
+    /// lost while instructions sit behind an unresolved branch are held
+    /// until it resolves, and go to bad speculation if it was mispredicted.
+    fn account_slots(&mut self, retired: usize) {
+        use self::operand::Operand;
+        use self::stats::Bound;
+        use instruction::Opcode;
+
+        let rob = &self.rob;
+        let head = rob.nth_index(0).map(|idx| (idx, rob.get(idx).unwrap()));
+        if head.is_some() {
+            self.refilling = false;
+        }
+        let stall = match head {
+            _ if self.refilling => Bound::BadSpeculation,
+            None => Bound::Frontend,
+            // 뒤의 명령어가 issue되지 못하고 있음
+            Some((_, entry)) if matches!(entry.inst.opcode, Opcode::Jalr | Opcode::System) => {
+                Bound::Frontend
             }
-            false
-        })
+            Some((idx, _)) if self.rs.load_buffer().contains(idx) => Bound::Memory,
+            // ROB head에서 메모리에 쓰는 중인 store와 AMO
+            Some((_, entry))
+                if matches!(entry.inst.opcode, Opcode::Store | Opcode::Amo)
+                    && matches!((entry.addr, entry.mem_value), (Operand::Value(_), Operand::Value(_)))
+                    && entry.mem_rem_cycle != 0 =>
+            {
+                Bound::Memory
+            }
+            // operand 대기, MUL/DIV 실행 중 등
+            Some(_) => Bound::Core,
+        };
+
+        // 뒤에 명령어가 있는 branch 중 결과를 모르거나 예측이 틀린 가장 오래된 것.
+        // 결과를 모르면 뒤의 명령어가 wrong path인지 아직 알 수 없음
+        let younger = self.rob.len().saturating_sub(1);
+        let is_mispredicted = self
+            .rob
+            .iter()
+            .take(younger)
+            .find(|entry| {
+                matches!(entry.inst.opcode, Opcode::Branch)
+                    && entry.reg_value != Some(entry.branch_pred as u32)
+            })
+            .map(|branch| branch.reg_value.is_some());
+        let top_down = &mut self.stats.top_down;
+        match is_mispredicted {
+            Some(false) => top_down.account_speculative(retired, stall),
+            Some(true) => {
+                top_down.resolve(true);
+                top_down.account(retired, Bound::BadSpeculation);
+            }
+            None => {
+                top_down.resolve(false);
+                top_down.account(retired, stall);
+            }
+        }
+    }
+
+    fn is_program_finished(&self, threads: &ThreadTable) -> bool {
+        threads.is_finished()
     }
 
     pub fn write_result(&mut self) {
         let completed_entries = self.rs.completed_jobs();
         for entry in completed_entries {
-            self.rs.propagate(&entry);
+            self.rs.propagate(&entry, self.clock);
             self.rob.propagate(&entry);
+            let rob_entry = self.rob.get_mut(entry.rob_idx).unwrap();
+            rob_entry.times.mark_completed(self.clock);
+            let phys_rd = rob_entry.phys_rd;
+            if let (Some(prf), Some((phys, _))) = (self.reg.prf.as_mut(), phys_rd) {
+                prf.write(phys, entry.reg_value);
+            }
         }
     }
 
-    pub fn execute(&mut self) {
-        let npc = self.rs.execute(&mut self.rob, &mut self.memory);
+    pub fn execute(&mut self, mem_sys: &mut MemorySystem, arrived: &[Requester]) {
+        self.mmu
+            .tick(self.clock, self.hart_id, arrived, mem_sys, &self.reg.csr);
+        let npc = self.rs.execute(
+            &mut self.rob,
+            mem_sys,
+            self.hart_id,
+            &mut self.mmu,
+            &self.reg.csr,
+            arrived,
+            self.clock,
+        );
+        for latency in self.rs.take_load_latencies() {
+            self.stats.load_done(latency);
+        }
+        self.stats.sample(
+            self.rob.len(),
+            self.rs.occupancy(),
+            self.rs.load_buffer_occupancy(),
+            self.rs.unit_ops(),
+        );
         if let Some(npc) = npc {
-            self.reg.pc.write(npc);
+            self.redirect(npc);
         }
     }
 
     pub fn issue(&mut self) {
-        use instruction::Function::*;
         use instruction::{Instruction, Opcode};
 
         // stall
+        // System 명령어(ecall, CSR 등)는 commit될 때까지 뒤의 명령어를 issue하지 않음
         {
             let last_rob_entry = self.rob.iter().rev().next();
             if let Some(entry) = last_rob_entry {
-                let has_to_stall = match entry.inst.function {
-                    Ecall => true,
-                    Jalr if !entry.is_completed() => true,
-                    _ => false,
+                let has_to_stall = match entry.inst.opcode {
+                    Opcode::System => true,
+                    Opcode::Jalr => !entry.is_completed(),
+                    _ => entry.mem_exception.is_err(),
                 };
                 if has_to_stall {
                     return;
                 }
             }
         }
+        if self.clock < self.recovering_until {
+            return;
+        }
 
-        for _ in 0..2 {
-            let pc = self.reg.pc.read();
-            let raw_inst = self.memory.read_inst(pc).unwrap();
-            let mut inst = Instruction::new(raw_inst);
-            if let Opcode::Fmadd
-            | Opcode::Fmsub
-            | Opcode::Fnmadd
-            | Opcode::Fnmsub
-            | Opcode::OpFp
-            | Opcode::StoreFp
-            | Opcode::LoadFp = inst.opcode
-            {
-                inst = Instruction::default();
+        for _ in 0..ISSUE_WIDTH {
+            // physical register가 남아 있지 않으면 rename할 수 없음
+            let needs_phys_reg = match self.fetch_queue.peek_ready(self.clock) {
+                Some(fetched) => {
+                    fetched.exception.is_none() && fetched.inst.fields.rd.unwrap_or(0) != 0
+                }
+                None => break,
+            };
+            let out_of_phys_regs = self.reg.prf.as_ref().is_some_and(|prf| !prf.has_free());
+            if needs_phys_reg && out_of_phys_regs {
+                self.rename_stats.free_list_stalls += 1;
+                break;
+            }
+
+            let fetched = match self.fetch_queue.pop_ready(self.clock) {
+                Some(fetched) => fetched,
+                None => break,
+            };
+            let pc = fetched.pc;
+
+            if let Some(exception) = fetched.exception {
+                // commit 단계에서 precise하게 trap이 발생하도록 NOP에 exception을 담아 issue
+                let rob_idx = self.rob.issue(pc, Instruction::default(), &self.reg, false);
+                let rob_entry = self.rob.get_mut(rob_idx).unwrap();
+                rob_entry.mem_exception = Err(exception);
+                rob_entry.times = fetched.times;
+                rob_entry.times.issued = Some(self.clock);
+                self.rs.issue(rob_idx, &self.rob, &self.reg, self.clock);
+                break;
+            }
+
+            let inst = fetched.inst;
+            let has_to_stop = matches!(inst.opcode, Opcode::Jalr | Opcode::System);
+            let inst_rd = inst.fields.rd.unwrap_or(0);
+            let rob_idx = self.rob.issue(pc, inst, &self.reg, fetched.predicted_taken);
+            let rob_entry = self.rob.get_mut(rob_idx).unwrap();
+            rob_entry.raw = fetched.raw;
+            rob_entry.times = fetched.times;
+            rob_entry.times.issued = Some(self.clock);
+            self.rs.issue(rob_idx, &self.rob, &self.reg, self.clock);
+            match self.reg.prf {
+                Some(ref mut prf) => {
+                    self.rob.get_mut(rob_idx).unwrap().phys_rd = prf.rename(inst_rd, rob_idx)
+                }
+                None => self.reg.set_reg_rob_index(inst_rd, rob_idx),
+            }
+            if inst_rd != 0 {
+                self.rename_stats.renamed += 1;
+                self.renamed_in_flight += 1;
             }
 
-            let (npc, has_to_stop) = match inst.opcode {
+            if has_to_stop {
+                break;
+            }
+        }
+    }
+
+    pub fn fetch(&mut self, mem_sys: &MemorySystem) {
+        use instruction::{Instruction, Opcode};
+        use memory::mmu::{AccessType, Translation};
+        use pipeline::fetch::FetchedInst;
+
+        if self.draining {
+            return;
+        }
+        for _ in 0..self.fetch_queue.config.width {
+            if !self.fetch_queue.can_fetch() {
+                break;
+            }
+
+            let pc = self.reg.pc.read();
+            let mut fetched = FetchedInst {
+                pc,
+                inst: Instruction::default(),
+                raw: consts::NOP,
+                predicted_taken: false,
+                exception: None,
+                ready_at: 0,
+                times: Default::default(),
+            };
+            let raw_inst = match self.mmu.translate(pc, AccessType::Fetch, &self.reg.csr) {
+                Translation::Done(addr) => mem_sys
+                    .memory
+                    .read_inst(addr)
+                    .map_err(|_| Exception::InstructionAccessFault(pc)),
+                Translation::Pending => break,
+                Translation::Fault(exception) => Err(exception),
+            };
+            let decoded = raw_inst.and_then(|raw_inst| {
+                let inst = Instruction::decode(raw_inst)
+                    .ok_or(Exception::IllegalInstruction(raw_inst))?;
+                Ok((raw_inst, inst))
+            });
+            let inst = match decoded {
+                Ok((raw_inst, inst)) => {
+                    fetched.raw = raw_inst;
+                    inst
+                }
+                Err(exception) => {
+                    // wrong path일 수도 있으므로 redirect될 때까지 fetch만 멈춤
+                    fetched.exception = Some(exception);
+                    self.fetch_queue.push(fetched, self.clock);
+                    self.fetch_queue.stall();
+                    break;
+                }
+            };
+            let inst = without_fp(inst);
+
+            let (npc, ends_group) = match inst.opcode {
                 Opcode::Jal => (pc.wrapping_add(inst.fields.imm.unwrap()), true),
-                Opcode::Jalr => (pc, true),
-                Opcode::System if inst.function == Function::Ecall => {
+                Opcode::Jalr | Opcode::System => {
+                    self.fetch_queue.stall();
                     (pc.wrapping_add(consts::WORD_SIZE as u32), true)
                 }
                 Opcode::Branch => {
-                    let npc = if self.branch_predictor.predict(pc) {
-                        // taken
-                        pc.wrapping_add(inst.fields.imm.unwrap())
+                    fetched.predicted_taken = self.branch_predictor.predict(pc);
+                    if fetched.predicted_taken {
+                        (pc.wrapping_add(inst.fields.imm.unwrap()), true)
                     } else {
-                        pc.wrapping_add(consts::WORD_SIZE as u32)
-                    };
-                    (npc, false)
+                        (pc.wrapping_add(consts::WORD_SIZE as u32), false)
+                    }
                 }
                 _ => (pc.wrapping_add(consts::WORD_SIZE as u32), false),
             };
             self.reg.pc.write(npc);
+            fetched.inst = inst;
+            self.fetch_queue.push(fetched, self.clock);
 
-            let inst_rd = inst.fields.rd.unwrap_or(0);
-            let rob_idx = self
-                .rob
-                .issue(pc, inst, &self.reg, &mut self.branch_predictor);
-            self.rs.issue(rob_idx, &self.rob, &self.reg);
-            self.reg.set_reg_rob_index(inst_rd, rob_idx);
-
-            if has_to_stop {
+            if ends_group {
                 break;
             }
         }
     }
+
     // return true when process ends.
-    pub fn run_clock(&mut self) -> (Vec<(usize, ReorderBufferEntry)>, bool) {
+    // `arrived`는 이번 clock에 끝난 이 hart의 메모리 접근
+    pub fn run_clock(
+        &mut self,
+        mem_sys: &mut MemorySystem,
+        threads: &mut ThreadTable,
+        arrived: &[Requester],
+    ) -> (Vec<(usize, ReorderBufferEntry)>, bool) {
         self.clock += 1;
-        let retired_insts = self.commit();
-        if self.is_program_finished(&retired_insts) {
+        if self.tid.is_none() {
+            return (Vec::new(), false);
+        }
+        let retired_insts = self.commit(mem_sys, threads);
+        if self.is_program_finished(threads) {
             return (retired_insts, true);
         }
+        // thread가 block되거나 종료되어 hart가 비었음
+        if self.tid.is_none() {
+            return (retired_insts, false);
+        }
+        // debugger가 멈춘 지점에서 나머지 단계를 진행하지 않음
+        if self.commit_stop.stopped {
+            return (retired_insts, false);
+        }
+        self.sample_interrupts(mem_sys);
+        self.take_interrupt(mem_sys);
+        self.rename_stats.sample(self.renamed_in_flight);
 
         self.write_result();
-        self.execute();
+        self.execute(mem_sys, arrived);
         self.issue();
+        self.fetch(mem_sys);
         (retired_insts, false)
     }
 }
@@ -0,0 +1,231 @@
+//! Register renaming models.
+//!
+//! The default model renames through the ROB: speculative results live in
+//! their ROB entry and `RegisterFile::related_rob` points each architectural
+//! register at its newest producer. The physical-register-file model, as in
+//! the MIPS R10K or BOOM, keeps every value in a `PhysRegFile` instead. Issue
+//! takes a free physical register for each destination and records it in the
+//! map table; commit moves the mapping to the retirement map and frees the
+//! register it replaced. Issue stalls while the free list is empty.
+//!
+//! On a flush the map table is restored from the retirement map. The squashed
+//! mappings are walked back `WALK_WIDTH` per clock to return their registers
+//! to the free list, and issue waits until the walk is done.
+
+use pipeline::operand::Operand;
+use register::Register;
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+
+/// Mappings undone per clock while recovering, matching the issue width.
+pub const WALK_WIDTH: usize = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RenameModel {
+    /// Speculative values live in the ROB.
+    Rob,
+    /// Explicit physical register file with a free list.
+    PhysRegFile,
+}
+
+impl FromStr for RenameModel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rob" => Ok(RenameModel::Rob),
+            "prf" => Ok(RenameModel::PhysRegFile),
+            _ => Err(format!(
+                "unknown rename model '{}', expected rob or prf",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RenameConfig {
+    pub model: RenameModel,
+    /// Size of the physical register file, including the 32 registers
+    /// holding architectural state.
+    pub phys_regs: usize,
+}
+
+impl Default for RenameConfig {
+    fn default() -> Self {
+        RenameConfig {
+            model: RenameModel::Rob,
+            phys_regs: 64,
+        }
+    }
+}
+
+/// Counters kept for both models, so they can be compared.
+#[derive(Debug, Default, Clone)]
+pub struct RenameStats {
+    /// Instructions that got a new mapping for their destination.
+    pub renamed: usize,
+    /// Clocks issue stalled on an empty free list.
+    pub free_list_stalls: usize,
+    /// Sum over clocks of the speculative mappings in flight.
+    pub total_in_flight: usize,
+    pub peak_in_flight: usize,
+    pub clocks: usize,
+    /// Flushes that discarded at least one speculative mapping.
+    pub recoveries: usize,
+    pub squashed: usize,
+    /// Clocks issue waited for recovery walks.
+    pub recovery_clocks: usize,
+}
+
+impl RenameStats {
+    pub fn sample(&mut self, in_flight: usize) {
+        self.clocks += 1;
+        self.total_in_flight += in_flight;
+        self.peak_in_flight = std::cmp::max(self.peak_in_flight, in_flight);
+    }
+}
+
+impl fmt::Display for RenameStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let avg_in_flight = if self.clocks == 0 {
+            0.0
+        } else {
+            self.total_in_flight as f64 / self.clocks as f64
+        };
+        write!(
+            f,
+            "renamed: {}, free-list stalls: {}, avg in flight: {:.2}, peak in flight: {}, recoveries: {}, squashed: {}, recovery clocks: {}",
+            self.renamed,
+            self.free_list_stalls,
+            avg_in_flight,
+            self.peak_in_flight,
+            self.recoveries,
+            self.squashed,
+            self.recovery_clocks
+        )
+    }
+}
+
+/// Physical register file with its map table, retirement map and free list.
+/// x0 is always mapped to physical register 0, which is never renamed.
+#[derive(Debug, Clone)]
+pub struct PhysRegFile {
+    /// `None` until the producer has written back.
+    values: Vec<Option<u32>>,
+    /// ROB index of the instruction writing each register, while it runs.
+    producers: Vec<Option<usize>>,
+    map_table: [usize; 32],
+    retirement_map: [usize; 32],
+    free_list: VecDeque<usize>,
+}
+
+impl PhysRegFile {
+    pub fn new(phys_regs: usize) -> Self {
+        let phys_regs = std::cmp::max(phys_regs, 33);
+        let mut identity = [0; 32];
+        for (arch, phys) in identity.iter_mut().enumerate() {
+            *phys = arch;
+        }
+        PhysRegFile {
+            values: vec![Some(0); phys_regs],
+            producers: vec![None; phys_regs],
+            map_table: identity,
+            retirement_map: identity,
+            free_list: (32..phys_regs).collect(),
+        }
+    }
+
+    /// Operand for architectural register `arch`: its value if it has been
+    /// written, or the ROB index of its producer.
+    pub fn lookup(&self, arch: u8) -> Operand {
+        let phys = self.map_table[arch as usize];
+        match self.values[phys] {
+            Some(value) => Operand::Value(value),
+            None => Operand::Rob(self.producers[phys].unwrap()),
+        }
+    }
+
+    pub fn has_free(&self) -> bool {
+        !self.free_list.is_empty()
+    }
+
+    /// Maps `arch` to a free register written by the ROB entry `rob_idx`.
+    /// Returns the new register and the one it replaces, or `None` for x0.
+    pub fn rename(&mut self, arch: u8, rob_idx: usize) -> Option<(usize, usize)> {
+        if arch == 0 {
+            return None;
+        }
+        let phys = self.free_list.pop_front().expect("free list is empty");
+        self.values[phys] = None;
+        self.producers[phys] = Some(rob_idx);
+        let stale = std::mem::replace(&mut self.map_table[arch as usize], phys);
+        Some((phys, stale))
+    }
+
+    pub fn write(&mut self, phys: usize, value: u32) {
+        self.values[phys] = Some(value);
+        self.producers[phys] = None;
+    }
+
+    /// Commits the mapping of `arch` to `phys`, freeing `stale`.
+    pub fn retire(&mut self, arch: u8, phys: usize, stale: usize) {
+        self.retirement_map[arch as usize] = phys;
+        self.free_list.push_back(stale);
+    }
+
+    /// Mappings made since the last commit.
+    pub fn in_flight(&self) -> usize {
+        self.values.len() - 32 - self.free_list.len()
+    }
+
+    /// Drops every speculative mapping and reloads the architectural
+    /// registers from `gpr`, which instructions such as `ecall` may have
+    /// changed at commit. Returns the number of mappings squashed.
+    pub fn recover(&mut self, gpr: &[Register; 32]) -> usize {
+        let squashed = self.in_flight();
+        self.map_table = self.retirement_map;
+        let mut is_mapped = vec![false; self.values.len()];
+        for (reg, &phys) in gpr.iter().zip(self.retirement_map.iter()) {
+            self.values[phys] = Some(reg.read());
+            is_mapped[phys] = true;
+        }
+        self.producers.iter_mut().for_each(|producer| *producer = None);
+        self.free_list = (0..self.values.len())
+            .filter(|&phys| !is_mapped[phys])
+            .collect();
+        squashed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rename_retire_and_recover() {
+        let mut prf = PhysRegFile::new(34);
+        let mut gpr = [Register::new(0, true); 32];
+
+        let (p1, stale1) = prf.rename(5, 100).unwrap();
+        assert_eq!(stale1, 5);
+        assert!(matches!(prf.lookup(5), Operand::Rob(100)));
+        let (p2, stale2) = prf.rename(5, 101).unwrap();
+        assert_eq!(stale2, p1);
+        assert!(!prf.has_free());
+        assert_eq!(prf.rename(0, 102), None);
+
+        prf.write(p1, 7);
+        prf.retire(5, p1, stale1);
+        assert_eq!(prf.in_flight(), 1);
+        assert!(matches!(prf.lookup(5), Operand::Rob(101)));
+
+        // 두 번째 mapping은 flush되고 retire된 값이 남음
+        gpr[5].write(7u32);
+        assert_eq!(prf.recover(&gpr), 1);
+        assert!(matches!(prf.lookup(5), Operand::Value(7)));
+        assert_eq!(prf.in_flight(), 0);
+        assert!(prf.free_list.contains(&p2) && prf.free_list.contains(&5));
+    }
+}
@@ -1,10 +1,10 @@
 pub mod iter;
 use super::operand::Operand;
+use csr::{CsrFile, Privilege, MIE, MIP, MSTATUS, SIE, SIP, SSTATUS};
 use instruction::{Function, Instruction, Opcode};
-use memory::ProcessMemory;
-use pipeline::branch_predictor::BranchPredictor;
+use pipeline::lifecycle::Timestamps;
 use pipeline::reservation_staion::FinishedCalc;
-use pipeline::Pipeline;
+use pipeline::wakeup::WakeupTable;
 use register::RegisterFile;
 use std::collections::{HashMap, VecDeque, LinkedList};
 use std::fmt::Debug;
@@ -15,6 +15,9 @@ use pipeline::exception::Exception;
 pub struct ReorderBufferEntry {
     pub pc: u32,
     pub inst: Instruction,
+    /// Instruction word as fetched, which `inst` may have turned into a
+    /// `nop`.
+    pub raw: u32,
     pub mem_value: Operand,
     pub reg_value: Option<u32>,
     pub rd: u8,
@@ -22,9 +25,107 @@ pub struct ReorderBufferEntry {
     pub branch_pred: bool,
     pub mem_rem_cycle: usize,
     pub mem_exception: Result<(), Exception>,
+    /// Physical address a store, AMO or device load at the head was sent
+    /// to, once it has been. Memory or the device has changed by then.
+    pub mem_paddr: Option<u32>,
+    /// Value a store, `sc.w` or AMO wrote to memory.
+    pub mem_written: Option<u32>,
+    /// Contents of memory it overwrote, unless it wrote a device.
+    pub mem_replaced: Option<u32>,
+    /// CSRs it wrote and their values after it retired, in address order.
+    pub csr_written: Vec<(u16, u32)>,
+    /// Privilege the instruction was issued at. It retires at the same one,
+    /// as privilege only changes when a serializing instruction commits or
+    /// the pipeline is flushed.
+    pub privilege: Privilege,
+    /// Physical register the result goes to and the one it replaced, when
+    /// renaming through a physical register file.
+    pub phys_rd: Option<(usize, usize)>,
+    pub times: Timestamps,
 }
 
 impl ReorderBufferEntry {
+    /// An entry for `inst` at `pc` with nothing computed yet.
+    pub fn new(pc: u32, inst: Instruction, privilege: Privilege) -> Self {
+        ReorderBufferEntry {
+            pc,
+            rd: inst.fields.rd.unwrap_or(0),
+            raw: inst.value,
+            inst,
+            reg_value: None,
+            mem_value: Operand::default(),
+            addr: Operand::default(),
+            branch_pred: false,
+            mem_rem_cycle: crate::consts::MEM_CYCLE,
+            mem_exception: Ok(()),
+            mem_paddr: None,
+            mem_written: None,
+            mem_replaced: None,
+            csr_written: Vec::new(),
+            privilege,
+            phys_rd: None,
+            times: Timestamps::default(),
+        }
+    }
+
+    /// Address, size and value of what a store, `sc.w` or AMO wrote, once
+    /// it has.
+    pub fn mem_write(&self) -> Option<(u32, u32, u32)> {
+        let size = match self.inst.function {
+            Function::Sb => 1,
+            Function::Sh => 2,
+            _ => 4,
+        };
+        match (self.addr, self.mem_written) {
+            (Operand::Value(addr), Some(value)) => Some((addr, size, value)),
+            _ => None,
+        }
+    }
+
+    /// CSRs a retired instruction wrote, with their values in `csr`. A write
+    /// through `sstatus`, `sie` or `sip` also writes the M-mode register
+    /// behind it, and `mret` and `sret` write `mstatus`.
+    pub fn written_csrs(&self, csr: &CsrFile) -> Vec<(u16, u32)> {
+        let addr = match self.inst.function {
+            Function::Mret | Function::Sret => MSTATUS,
+            _ if self.writes_csr() => self.inst.csr_addr(),
+            _ => return Vec::new(),
+        };
+        let mut addrs = vec![addr];
+        match addr {
+            SSTATUS => addrs.push(MSTATUS),
+            SIE => addrs.push(MIE),
+            SIP => addrs.push(MIP),
+            _ => {}
+        }
+        addrs
+            .into_iter()
+            .filter_map(|addr| Some((addr, csr.value(addr)?)))
+            .collect()
+    }
+
+    /// Whether a Zicsr instruction writes its CSR. `csrrs` and `csrrc` don't
+    /// with x0 or an immediate of 0.
+    fn writes_csr(&self) -> bool {
+        match self.inst.function {
+            Function::Csrrw | Function::Csrrwi => true,
+            Function::Csrrs | Function::Csrrc | Function::Csrrsi | Function::Csrrci => {
+                self.inst.fields.rs1 != Some(0)
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether it wrote any of the `len` bytes at `start`.
+    pub fn writes_to(&self, start: u32, len: u32) -> bool {
+        match self.mem_write() {
+            Some((addr, size, _)) => {
+                addr < start.wrapping_add(len) && start < addr.wrapping_add(size)
+            }
+            None => false,
+        }
+    }
+
     pub fn is_completed(&self) -> bool {
         let mem_val_done = if let Operand::Value(_) = self.mem_value {
             true
@@ -47,41 +148,97 @@ impl ReorderBufferEntry {
     }
 
     // true 반환이면 branch prediction miss
-    pub fn retire(
-        &self,
-        old_index: usize,
-        memory: &mut ProcessMemory,
-        reg: &mut RegisterFile,
-    ) -> bool {
-        self.mem_exception.unwrap();
+    // Err 반환이면 이 entry는 retire되지 않고 trap이 발생함
+    // ecall은 thread 상태가 필요하므로 Pipeline::commit에서 처리함
+    pub fn retire(&self, old_index: usize, reg: &mut RegisterFile) -> Result<bool, Exception> {
+        self.mem_exception?;
 
         if let Opcode::Branch = self.inst.opcode {
             if let Some(branch_result) = self.reg_value {
                 if branch_result == self.branch_pred as u32 {
-                    return false;
+                    return Ok(false);
                 }
             }
-            return true;
+            return Ok(true);
         }
 
-        if let Function::Ecall = self.inst.function {
-            Pipeline::system_call(memory, reg).unwrap();
-            return false;
+        match self.inst.function {
+            Function::Csrrw
+            | Function::Csrrs
+            | Function::Csrrc
+            | Function::Csrrwi
+            | Function::Csrrsi
+            | Function::Csrrci => {
+                let old_value = self.access_csr(reg)?;
+                self.write_back(old_index, old_value, reg);
+                return Ok(false);
+            }
+            // pc는 Pipeline::commit에서 mepc/sepc로 redirect
+            Function::Mret => {
+                reg.csr.mret().ok_or(Exception::IllegalInstruction(self.inst.value))?;
+                return Ok(false);
+            }
+            Function::Sret => {
+                reg.csr.sret().ok_or(Exception::IllegalInstruction(self.inst.value))?;
+                return Ok(false);
+            }
+            Function::Wfi if reg.csr.privilege == Privilege::User => {
+                return Err(Exception::IllegalInstruction(self.inst.value));
+            }
+            _ => {}
         }
 
         if let Some(reg_val) = self.reg_value {
-            reg.gpr[self.rd as usize].write(reg_val);
-            if let Some(related_rob) = reg.related_rob[self.rd as usize] {
-                if related_rob == old_index {
-                    reg.related_rob[self.rd as usize] = None;
-                }
+            self.write_back(old_index, reg_val, reg);
+        }
+
+        Ok(false)
+    }
+
+    fn write_back(&self, old_index: usize, value: u32, reg: &mut RegisterFile) {
+        reg.gpr[self.rd as usize].write(value);
+        if let (Some(prf), Some((phys, stale))) = (reg.prf.as_mut(), self.phys_rd) {
+            prf.write(phys, value);
+            prf.retire(self.rd, phys, stale);
+        }
+        if let Some(related_rob) = reg.related_rob[self.rd as usize] {
+            if related_rob == old_index {
+                reg.related_rob[self.rd as usize] = None;
             }
         }
+    }
+
+    // CSR 명령어는 issue 단계에서 serialize 되므로, 여기서 읽는 rs1은 항상
+    // architectural 값임.
+    fn access_csr(&self, reg: &mut RegisterFile) -> Result<u32, Exception> {
+        let illegal = Exception::IllegalInstruction(self.inst.value);
+        let addr = self.inst.csr_addr();
+        let rs1 = self.inst.fields.rs1.unwrap();
+        let src = match self.inst.function {
+            Function::Csrrwi | Function::Csrrsi | Function::Csrrci => u32::from(rs1),
+            _ => reg.gpr[rs1 as usize].read(),
+        };
 
-        false
+        let old_value = reg.csr.read(addr).ok_or(illegal)?;
+        if self.writes_csr() {
+            let new_value = match self.inst.function {
+                Function::Csrrs | Function::Csrrsi => old_value | src,
+                Function::Csrrc | Function::Csrrci => old_value & !src,
+                _ => src,
+            };
+            reg.csr.write(addr, new_value).ok_or(illegal)?;
+        }
+        Ok(old_value)
     }
 }
 
+/// Operands of a ROB entry that can wait for another entry's result.
+#[derive(Debug, Clone, Copy)]
+enum EntryOperand {
+    Addr,
+    MemValue,
+}
+
 #[derive(Debug, Default)]
 pub struct ReorderBuffer {
     // highst_index: usize,
@@ -90,6 +247,11 @@ pub struct ReorderBuffer {
     // index_map: HashMap<usize, usize>,
     // buf: Vec<(usize, ReorderBufferEntry)>,
     buf: LinkedList<ReorderBufferEntry>,
+    wakeup: WakeupTable<(usize, EntryOperand)>,
+    /// Stores and AMOs other than `lr.w` in program order, each with the
+    /// number of such entries issued before it.
+    mem_writes: VecDeque<(usize, usize)>,
+    mem_writes_issued: usize,
 }
 
 impl std::fmt::Display for ReorderBuffer {
@@ -109,6 +271,8 @@ impl ReorderBuffer {
         // self.index_map.clear();
         // self.index_queue.clear();
         self.buf.clear();
+        self.wakeup.clear();
+        self.mem_writes.clear();
     }
 
     // fn register_new_index(&mut self) -> usize {
@@ -150,6 +314,10 @@ impl ReorderBuffer {
         // } else {
         //     None
         // }
+        let head_idx = self.nth_index(0);
+        if head_idx.is_some() && self.mem_writes.front().map(|&(_, idx)| idx) == head_idx {
+            self.mem_writes.pop_front();
+        }
         self.buf.pop_front()
     }
 
@@ -158,7 +326,7 @@ impl ReorderBuffer {
         pc: u32,
         inst: Instruction,
         reg: &crate::register::RegisterFile,
-        branch_predictor: &mut BranchPredictor,
+        branch_pred: bool,
     ) -> usize {
         let (mem_value, addr) = match inst.opcode {
             Opcode::Store => (
@@ -171,25 +339,43 @@ impl ReorderBuffer {
             ),
             _ => (Operand::default(), Operand::default()),
         };
-        let rd = inst.fields.rd.unwrap_or(0);
-        let branch_pred = if let Opcode::Branch = inst.opcode {
-            branch_predictor.predict(pc)
-        } else {
-            false
-        };
         let new_entry = ReorderBufferEntry {
-            pc,
-            inst,
-            reg_value: None,
             mem_value,
-            rd,
             addr,
             branch_pred,
-            mem_rem_cycle: crate::consts::MEM_CYCLE,
-            mem_exception: Ok(()),
+            ..ReorderBufferEntry::new(pc, inst, reg.csr.privilege)
         };
+        let is_mem_write = match new_entry.inst.opcode {
+            Opcode::Store => true,
+            Opcode::Amo => new_entry.inst.function != Function::Lrw,
+            _ => false,
+        };
+
+        let idx = self.add(new_entry);
+        self.wakeup.listen(addr, (idx, EntryOperand::Addr));
+        self.wakeup.listen(mem_value, (idx, EntryOperand::MemValue));
+        if is_mem_write {
+            self.mem_writes.push_back((self.mem_writes_issued, idx));
+            self.mem_writes_issued += 1;
+        }
+        idx
+    }
 
-        self.add(new_entry)
+    /// Number of stores and AMOs other than `lr.w` issued so far.
+    pub fn mem_writes_issued(&self) -> usize {
+        self.mem_writes_issued
+    }
+
+    /// Stores and AMOs other than `lr.w` still in the buffer that were
+    /// issued before the first `issued_before` of them, oldest first.
+    pub fn older_mem_writes(
+        &self,
+        issued_before: usize,
+    ) -> impl Iterator<Item = &ReorderBufferEntry> {
+        self.mem_writes
+            .iter()
+            .take_while(move |&&(seq, _)| seq < issued_before)
+            .map(move |&(_, idx)| self.get(idx).unwrap())
     }
 
     pub fn len(&self) -> usize {
@@ -240,32 +426,27 @@ impl ReorderBuffer {
     }
 
     pub fn propagate(&mut self, job: &FinishedCalc) {
-        for entry in self.buf.iter_mut() {
-            let idx = entry as *const ReorderBufferEntry as usize;
-            if idx == job.rob_idx {
-                entry.reg_value = Some(job.reg_value);
-                if let Some(exception) = job.exception {
-                    entry.mem_exception = Err(exception);
-                }
-                continue;
-            }
+        let entry = self.get_mut(job.rob_idx).unwrap();
+        entry.reg_value = Some(job.reg_value);
+        if let Some(exception) = job.exception {
+            entry.mem_exception = Err(exception);
+        }
 
-            let ops = [entry.addr, entry.mem_value];
-            let mut it = ops.into_iter().map(|op| match op {
-                Operand::Rob(target_rob) if *target_rob == job.rob_idx => {
-                    Operand::Value(job.reg_value)
-                }
-                _ => *op,
-            });
-            entry.addr = it.next().unwrap();
-            entry.mem_value = it.next().unwrap();
+        for (idx, operand) in self.wakeup.take(job.rob_idx) {
+            let entry = self.get_mut(idx).unwrap();
+            match operand {
+                EntryOperand::Addr => entry.addr = Operand::Value(job.reg_value),
+                EntryOperand::MemValue => entry.mem_value = Operand::Value(job.reg_value),
+            }
         }
     }
 
-    pub fn completed_entries(&mut self) -> Vec<(usize, ReorderBufferEntry)> {
+    /// Takes up to `limit` completed entries from the head.
+    pub fn completed_entries(&mut self, limit: usize) -> Vec<(usize, ReorderBufferEntry)> {
         let completed: Vec<_> = self
             .iter_with_id()
             .take_while(|(_, entry)| entry.is_completed())
+            .take(limit)
             .map(|(idx, _)| idx)
             .collect();
 
@@ -3,8 +3,13 @@ use super::functional_units as fu;
 use super::load_buffer::LoadBuffer;
 use super::operand::Operand;
 use super::reorder_buffer::ReorderBuffer;
+use super::stats::{UNIT_AGU, UNIT_ALU, UNIT_COUNT, UNIT_MEM, UNIT_MUL_DIV};
+use super::wakeup::WakeupTable;
+use csr::CsrFile;
 use instruction::{Function, Instruction, Opcode};
-use memory::ProcessMemory;
+use memory::dram::Requester;
+use memory::mmu::Mmu;
+use memory::system::MemorySystem;
 use register::RegisterFile;
 use std::collections::HashMap;
 
@@ -23,6 +28,9 @@ pub struct RSEntry {
     pub operand: (Operand, Operand),
     pub value: u32,
     pub remaining_clock: usize,
+    /// Clock at which both operands were available.
+    pub ready_at: Option<usize>,
+    pub executed_at: Option<usize>,
 }
 
 impl RSEntry {
@@ -46,42 +54,90 @@ pub struct ReservationStation {
     address_unit: fu::address::AddressUnit,
     load_buf: LoadBuffer,
     station: HashMap<usize, RSEntry>,
+    /// Station entries waiting for an operand, with its position.
+    wakeup: WakeupTable<(usize, usize)>,
+    /// Station entries whose operands are all ready, until they finish.
+    ready: Vec<usize>,
+    finished: Vec<usize>,
+    /// Results of `sc.w` and AMOs executed at the ROB head.
+    head_results: Vec<FinishedCalc>,
+    /// Operations in flight in each class of unit during the last clock.
+    unit_ops: [usize; UNIT_COUNT],
 }
 
 impl ReservationStation {
     pub fn clear(&mut self) {
         self.station.clear();
+        self.wakeup.clear();
+        self.ready.clear();
+        self.finished.clear();
         self.load_buf.clear();
         self.address_unit.clear();
+        self.head_results.clear();
     }
 
-    pub fn issue(&mut self, rob_index: usize, rob: &ReorderBuffer, reg: &RegisterFile) {
+    /// Entries in use, including those of the address unit.
+    pub fn occupancy(&self) -> usize {
+        self.station.len() + self.address_unit.buf.len()
+    }
+
+    /// Entries in use, including those of the address unit, in no
+    /// particular order.
+    pub fn entries(&self) -> impl Iterator<Item = &RSEntry> {
+        self.station.values().chain(self.address_unit.buf.values())
+    }
+
+    pub fn load_buffer(&self) -> &LoadBuffer {
+        &self.load_buf
+    }
+
+    pub fn load_buffer_occupancy(&self) -> usize {
+        self.load_buf.occupancy()
+    }
+
+    pub fn unit_ops(&self) -> &[usize; UNIT_COUNT] {
+        &self.unit_ops
+    }
+
+    /// Latencies of the loads that got their value since the last call.
+    pub fn take_load_latencies(&mut self) -> Vec<usize> {
+        self.load_buf.take_latencies()
+    }
+
+    pub fn issue(
+        &mut self,
+        rob_index: usize,
+        rob: &ReorderBuffer,
+        reg: &RegisterFile,
+        clock: usize,
+    ) {
         let rob_entry = rob.get(rob_index).unwrap();
         let inst = &rob_entry.inst;
         match inst.opcode {
-            Opcode::Store => self.address_unit.issue(rob_index, inst.clone(), reg, rob),
+            Opcode::Store => self
+                .address_unit
+                .issue(rob_index, inst.clone(), reg, rob, clock),
             Opcode::Load => {
-                self.address_unit.issue(rob_index, inst.clone(), reg, rob);
+                self.address_unit
+                    .issue(rob_index, inst.clone(), reg, rob, clock);
                 self.load_buf.issue(rob_index, rob, reg);
             }
-            Opcode::Amo if inst.function != Function::Scw => {
+            Opcode::Amo if inst.function == Function::Lrw => {
                 self.load_buf.issue(rob_index, rob, reg);
             }
+            // sc.w와 AMO는 ROB head에서 실행
+            Opcode::Amo => {}
             Opcode::Jalr => {
-                self.address_unit.issue(rob_index, inst.clone(), reg, rob);
-                self.station.insert(
+                self.address_unit
+                    .issue(rob_index, inst.clone(), reg, rob, clock);
+                self.insert(
                     rob_index,
-                    RSEntry {
-                        rob_index,
-                        status: RSStatus::Wait,
-                        inst: inst.clone(),
-                        operand: (
-                            Operand::Value(rob_entry.pc),
-                            Operand::Value(crate::consts::WORD_SIZE as u32),
-                        ),
-                        value: 0,
-                        remaining_clock: Self::remain_clock(inst.function),
-                    },
+                    inst,
+                    (
+                        Operand::Value(rob_entry.pc),
+                        Operand::Value(crate::consts::WORD_SIZE as u32),
+                    ),
+                    clock,
                 );
             }
             _ => {
@@ -95,7 +151,6 @@ impl ReservationStation {
                         Operand::Value(inst.fields.imm.unwrap()),
                     ),
                     Opcode::Lui => (Operand::Value(0), Operand::Value(inst.fields.imm.unwrap())),
-                    Opcode::Amo => (Operand::Value(0), Operand::Value(0)),
                     _ => {
                         let op1 = inst.fields.rs1.unwrap_or(0);
                         let op1 = reg.get_reg_value(op1, rob);
@@ -107,103 +162,151 @@ impl ReservationStation {
                         (op1, op2)
                     }
                 };
-
-                self.station.insert(
-                    rob_index,
-                    RSEntry {
-                        rob_index,
-                        status: RSStatus::Wait,
-                        inst: inst.clone(),
-                        operand,
-                        value: 0,
-                        remaining_clock: Self::remain_clock(inst.function),
-                    },
-                );
+                self.insert(rob_index, inst, operand, clock);
             }
         }
     }
 
-    pub fn propagate(&mut self, job: &FinishedCalc) {
-        self.address_unit.propagate(job);
+    fn insert(
+        &mut self,
+        rob_index: usize,
+        inst: &Instruction,
+        operand: (Operand, Operand),
+        clock: usize,
+    ) {
+        let waits_op1 = self.wakeup.listen(operand.0, (rob_index, 0));
+        let waits_op2 = self.wakeup.listen(operand.1, (rob_index, 1));
+        let is_ready = !waits_op1 && !waits_op2;
+        if is_ready {
+            self.ready.push(rob_index);
+        }
+        self.station.insert(
+            rob_index,
+            RSEntry {
+                rob_index,
+                status: RSStatus::Wait,
+                inst: inst.clone(),
+                operand,
+                value: 0,
+                remaining_clock: Self::remain_clock(inst.function),
+                ready_at: if is_ready { Some(clock) } else { None },
+                executed_at: None,
+            },
+        );
+    }
 
-        for entry in self.station.values_mut() {
-            let (op1, op2) = entry.operand;
-            let ops = [op1, op2];
-            let mut new_op_it = ops.iter().map(|op| match op {
-                Operand::Rob(target_rob) if *target_rob == job.rob_idx => {
-                    Operand::Value(job.reg_value)
-                }
-                _ => *op,
-            });
-            let new_op1 = new_op_it.next().unwrap();
-            let new_op2 = new_op_it.next().unwrap();
-            entry.operand = (new_op1, new_op2);
+    pub fn propagate(&mut self, job: &FinishedCalc, clock: usize) {
+        self.address_unit.propagate(job, clock);
+
+        for (rob_index, position) in self.wakeup.take(job.rob_idx) {
+            let entry = match self.station.get_mut(&rob_index) {
+                Some(entry) => entry,
+                None => continue,
+            };
+            match position {
+                0 => entry.operand.0 = Operand::Value(job.reg_value),
+                _ => entry.operand.1 = Operand::Value(job.reg_value),
+            }
+            if let (Some(_), Some(_)) = entry.operand_values() {
+                entry.ready_at = Some(clock);
+                self.ready.push(rob_index);
+            }
         }
     }
 
     // Jalr이 AddressUnit에서 계산 끝난 경우 pc를 반환
-    pub fn execute(&mut self, rob: &mut ReorderBuffer, mem: &mut ProcessMemory) -> Option<u32> {
-        let npc = self.address_unit.execute(rob);
-        self.load_buf.execute(rob, mem);
+    #[allow(clippy::too_many_arguments)]
+    pub fn execute(
+        &mut self,
+        rob: &mut ReorderBuffer,
+        mem_sys: &mut MemorySystem,
+        hart: usize,
+        mmu: &mut Mmu,
+        csr: &CsrFile,
+        arrived: &[Requester],
+        clock: usize,
+    ) -> Option<u32> {
+        self.unit_ops = [0; UNIT_COUNT];
+        // address unit은 계산이 끝난 entry를 바로 제거함
+        let waiting_addrs = self.address_unit.buf.len();
+        let npc = self.address_unit.execute(rob, clock);
+        self.unit_ops[UNIT_AGU] = waiting_addrs - self.address_unit.buf.len();
+        self.load_buf
+            .execute(rob, mem_sys, hart, mmu, csr, arrived, clock);
+        self.unit_ops[UNIT_MEM] = self.load_buf.in_flight();
 
         // Store
-        let head_entry = rob.nth_index(0).and_then(|idx| rob.get_mut(idx));
-        if let Some(head) = head_entry {
+        if let Some(head_idx) = rob.nth_index(0) {
+            let head = rob.get_mut(head_idx).unwrap();
             match head.inst.opcode {
                 Opcode::Store | Opcode::Amo if head.inst.function != Function::Lrw => {
-                    super::functional_units::memory::MemoryUnit::execute_store(head, mem)
+                    if let (Operand::Value(_), Operand::Value(_)) = (head.addr, head.mem_value) {
+                        head.times.mark_ready(clock);
+                        head.times.mark_executed(clock);
+                    }
+                    let result = super::functional_units::memory::MemoryUnit::execute_store(
+                        head_idx, head, mem_sys, hart, mmu, csr, arrived,
+                    );
+                    self.head_results.extend(result);
+                    if head.is_completed() {
+                        head.times.mark_completed(clock);
+                    }
+                    if head.mem_paddr.is_some() && head.mem_rem_cycle != 0 {
+                        self.unit_ops[UNIT_MEM] += 1;
+                    }
                 }
                 _ => {}
             }
         }
 
         // General
-        for entry in self.station.values_mut() {
-            if let RSStatus::Finished = entry.status {
-                continue;
-            }
-
+        let station = &mut self.station;
+        let finished = &mut self.finished;
+        let unit_ops = &mut self.unit_ops;
+        self.ready.retain(|rob_index| {
+            let entry = station.get_mut(rob_index).unwrap();
             if let (Operand::Value(a), Operand::Value(b)) = entry.operand {
                 if let RSStatus::Wait = entry.status {
-                    entry.status = RSStatus::Execute
+                    entry.status = RSStatus::Execute;
+                    entry.executed_at = Some(clock);
+                    let times = &mut rob.get_mut(*rob_index).unwrap().times;
+                    times.mark_ready(entry.ready_at.unwrap());
+                    times.mark_executed(clock);
+                }
+                if Self::remain_clock(entry.inst.function) > 1 {
+                    unit_ops[UNIT_MUL_DIV] += 1;
+                } else {
+                    unit_ops[UNIT_ALU] += 1;
                 }
                 entry.remaining_clock -= 1;
                 if entry.remaining_clock == 0 {
                     entry.value = crate::alu::alu(&entry.inst.function, a as i32, b as i32) as u32;
                     entry.status = RSStatus::Finished;
+                    finished.push(*rob_index);
+                    return false;
                 }
             }
-        }
+            true
+        });
         npc
     }
 
     pub fn completed_jobs(&mut self) -> Vec<FinishedCalc> {
         let mut loads = self.load_buf.pop_finished();
-        let mut generals: Vec<_> = {
-            let finished: Vec<_> = self
-                .station
-                .iter()
-                .filter_map(|(&idx, entry)| {
-                    if let RSStatus::Finished = entry.status {
-                        Some(idx)
-                    } else {
-                        None
-                    }
-                })
-                .collect();
-
-            finished
-                .into_iter()
-                .map(|idx| self.station.remove(&idx).unwrap())
-                .map(|entry| FinishedCalc {
-                    rob_idx: entry.rob_index,
-                    reg_value: entry.value,
-                    exception: None,
-                })
-                .collect()
-        };
+        let station = &mut self.station;
+        let mut generals: Vec<_> = self
+            .finished
+            .drain(..)
+            .map(|idx| station.remove(&idx).unwrap())
+            .map(|entry| FinishedCalc {
+                rob_idx: entry.rob_index,
+                reg_value: entry.value,
+                exception: None,
+            })
+            .collect();
 
         loads.append(&mut generals);
+        loads.append(&mut self.head_results);
         loads
     }
 
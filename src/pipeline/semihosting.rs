@@ -0,0 +1,306 @@
+//! RISC-V semihosting.
+//!
+//! A semihosting call is an `ebreak` between `slli x0, x0, 0x1f` and
+//! `srai x0, x0, 7`, with the operation in a0 and its argument, usually a
+//! pointer to a block of words, in a1. The result goes back in a0. The
+//! operations follow the Arm semihosting specification, and only the ones
+//! newlib needs for console and file I/O are implemented.
+
+use checkpoint::{Decoder, Encoder, Snapshot};
+use consts;
+use memory::ProcessMemory;
+use pipeline::exception::Exception;
+use register::RegisterFile;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::time::{Duration, Instant};
+
+/// `slli x0, x0, 0x1f`
+const ENTRY_NOP: u32 = 0x01f0_1013;
+/// `srai x0, x0, 7`
+const EXIT_NOP: u32 = 0x4070_5013;
+
+const SYS_OPEN: u32 = 0x01;
+const SYS_CLOSE: u32 = 0x02;
+const SYS_WRITE0: u32 = 0x04;
+const SYS_WRITE: u32 = 0x05;
+const SYS_READ: u32 = 0x06;
+const SYS_CLOCK: u32 = 0x10;
+const SYS_EXIT: u32 = 0x18;
+const SYS_EXIT_EXTENDED: u32 = 0x20;
+
+const ADP_STOPPED_APPLICATION_EXIT: u32 = 0x20026;
+
+/// Error result of most operations.
+const FAILED: u32 = !0;
+
+#[derive(Debug)]
+enum Handle {
+    Stdin,
+    Stdout,
+    Stderr,
+    /// A file with its name and `fopen` mode, to reopen it after a restore.
+    File(File, String, u32),
+}
+
+#[derive(Debug)]
+pub struct Semihosting {
+    /// Open handles, indexed by the number given to the program.
+    handles: Vec<Option<Handle>>,
+    started: Instant,
+}
+
+impl Default for Semihosting {
+    fn default() -> Self {
+        Semihosting {
+            // 0번은 사용하지 않음
+            handles: vec![None],
+            started: Instant::now(),
+        }
+    }
+}
+
+impl Semihosting {
+    /// Returns true if the `ebreak` at `pc` is surrounded by the semihosting
+    /// sequence. `pc` must not need translating.
+    pub fn is_call(pc: u32, mem: &ProcessMemory) -> bool {
+        let word_size = consts::WORD_SIZE as u32;
+        let entry = mem.read_inst(pc.wrapping_sub(word_size)).ok();
+        let exit = mem.read_inst(pc.wrapping_add(word_size)).ok();
+        entry == Some(ENTRY_NOP) && exit == Some(EXIT_NOP)
+    }
+
+    /// Runs the call in a0 and writes its result there. Returns the exit
+    /// code if the program asked to exit. An operation that isn't
+    /// implemented or an argument that points outside memory returns -1.
+    pub fn call(&mut self, reg: &mut RegisterFile, mem: &mut ProcessMemory) -> Option<u32> {
+        match self.run(reg, mem) {
+            Ok(exit) => exit,
+            Err(_) => {
+                reg.gpr[consts::SYSCALL_RET_REG].write(FAILED);
+                None
+            }
+        }
+    }
+
+    fn run(
+        &mut self,
+        reg: &mut RegisterFile,
+        mem: &mut ProcessMemory,
+    ) -> Result<Option<u32>, Exception> {
+        let op = reg.gpr[consts::SYSCALL_ARG1_REG].read();
+        let arg = reg.gpr[consts::SYSCALL_ARG2_REG].read();
+        let param = |n: u32| mem.read::<u32>(arg.wrapping_add(4 * n));
+
+        let ret_val = match op {
+            SYS_OPEN => {
+                let name = mem.read_bytes(param(0)?, param(2)? as usize)?;
+                let name = String::from_utf8_lossy(name).into_owned();
+                self.open(&name, param(1)?)
+            }
+            SYS_CLOSE => {
+                let handle = param(0)? as usize;
+                match self.handles.get_mut(handle).and_then(Option::take) {
+                    Some(_) => 0,
+                    None => FAILED,
+                }
+            }
+            SYS_WRITE0 => {
+                let mut bytes = Vec::new();
+                let mut addr = arg;
+                loop {
+                    match mem.read::<u8>(addr)? {
+                        0 => break,
+                        byte => bytes.push(byte),
+                    }
+                    addr = addr.wrapping_add(1);
+                }
+                let mut stdout = std::io::stdout();
+                stdout.write_all(&bytes).unwrap();
+                stdout.flush().unwrap();
+                0
+            }
+            // 처리하지 못한 byte 수를 반환
+            SYS_WRITE => {
+                let len = param(2)?;
+                let bytes = mem.read_bytes(param(1)?, len as usize)?;
+                let written = match self.handles.get_mut(param(0)? as usize) {
+                    Some(Some(Handle::Stdout)) => write_flushed(&mut std::io::stdout(), bytes),
+                    Some(Some(Handle::Stderr)) => write_flushed(&mut std::io::stderr(), bytes),
+                    Some(Some(Handle::File(file, _, _))) => write_flushed(file, bytes),
+                    _ => None,
+                };
+                written.map_or(len, |written| len - written as u32)
+            }
+            SYS_READ => {
+                let handle = param(0)? as usize;
+                let len = param(2)?;
+                let buf = mem.read_bytes_mut(param(1)?, len as usize)?;
+                let read = match self.handles.get_mut(handle) {
+                    Some(Some(Handle::Stdin)) => std::io::stdin().read(buf).ok(),
+                    Some(Some(Handle::File(file, _, _))) => file.read(buf).ok(),
+                    _ => None,
+                };
+                read.map_or(len, |read| len - read as u32)
+            }
+            SYS_CLOCK => (self.started.elapsed().as_millis() / 10) as u32,
+            // RV32에서는 a1이 reason 자체임
+            SYS_EXIT => {
+                let code = if arg == ADP_STOPPED_APPLICATION_EXIT { 0 } else { 1 };
+                return Ok(Some(code));
+            }
+            SYS_EXIT_EXTENDED => {
+                let code = if param(0)? == ADP_STOPPED_APPLICATION_EXIT {
+                    param(1)?
+                } else {
+                    1
+                };
+                return Ok(Some(code));
+            }
+            _ => return Err(Exception::SyscallNotImpl(op)),
+        };
+        reg.gpr[consts::SYSCALL_RET_REG].write(ret_val);
+        Ok(None)
+    }
+
+    /// Opens `name` with an `fopen` mode numbered as in the specification:
+    /// "r", "rb", "r+", "r+b", "w", "wb", "w+", "w+b", "a", "ab", "a+", "a+b".
+    /// ":tt" is the console.
+    fn open(&mut self, name: &str, mode: u32) -> u32 {
+        let handle = if name == ":tt" {
+            match mode / 4 {
+                0 => Handle::Stdin,
+                1 => Handle::Stdout,
+                _ => Handle::Stderr,
+            }
+        } else {
+            match Self::options(mode, true).open(name) {
+                Ok(file) => Handle::File(file, name.to_string(), mode),
+                Err(_) => return FAILED,
+            }
+        };
+        self.handles.push(Some(handle));
+        (self.handles.len() - 1) as u32
+    }
+
+    /// Options of an `fopen` mode. Reopening doesn't truncate.
+    fn options(mode: u32, is_first_open: bool) -> OpenOptions {
+        let update = mode & 2 != 0;
+        let mut options = OpenOptions::new();
+        match mode / 4 {
+            0 => options.read(true).write(update),
+            1 => options
+                .write(true)
+                .create(true)
+                .truncate(is_first_open)
+                .read(update),
+            _ => options.append(true).create(true).read(update),
+        };
+        options
+    }
+}
+
+/// Files are saved by name, mode and offset and opened again on restore.
+impl Snapshot for Semihosting {
+    fn save(&self, out: &mut Encoder) {
+        out.u64(self.started.elapsed().as_millis() as u64);
+        out.usize(self.handles.len());
+        for handle in self.handles.iter() {
+            match handle {
+                None => out.u8(0),
+                Some(Handle::Stdin) => out.u8(1),
+                Some(Handle::Stdout) => out.u8(2),
+                Some(Handle::Stderr) => out.u8(3),
+                Some(Handle::File(file, name, mode)) => {
+                    out.u8(4);
+                    out.str(name);
+                    out.u32(*mode);
+                    // File::seek는 &mut가 필요하지만 &File도 Seek를 구현함
+                    let mut file = file;
+                    out.u64(file.stream_position().unwrap_or(0));
+                }
+            }
+        }
+    }
+
+    fn restore(&mut self, input: &mut Decoder) -> io::Result<()> {
+        let elapsed = Duration::from_millis(input.u64()?);
+        self.started = Instant::now()
+            .checked_sub(elapsed)
+            .unwrap_or_else(Instant::now);
+        self.handles.clear();
+        for _ in 0..input.usize()? {
+            let handle = match input.u8()? {
+                0 => None,
+                1 => Some(Handle::Stdin),
+                2 => Some(Handle::Stdout),
+                3 => Some(Handle::Stderr),
+                _ => {
+                    let name = input.str()?;
+                    let mode = input.u32()?;
+                    let position = input.u64()?;
+                    let mut file = Self::options(mode, false).open(&name)?;
+                    file.seek(SeekFrom::Start(position))?;
+                    Some(Handle::File(file, name, mode))
+                }
+            };
+            self.handles.push(handle);
+        }
+        Ok(())
+    }
+}
+
+fn write_flushed<W: Write>(writer: &mut W, bytes: &[u8]) -> Option<usize> {
+    writer.write_all(bytes).and_then(|_| writer.flush()).ok()?;
+    Some(bytes.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_round_trip_and_exit() {
+        let mut mem = ProcessMemory::default();
+        mem.data.resize(0x200, 0);
+        mem.v_address_range = (0, 0x200);
+        mem.stack_range = (0xffff_0000, 0);
+        let mut reg = RegisterFile::new(0, 0);
+        let mut semihosting = Semihosting::default();
+        let mut call = |mem: &mut ProcessMemory, op: u32, params: &[u32]| {
+            for (i, &param) in params.iter().enumerate() {
+                mem.write(0x100 + 4 * i as u32, param).unwrap();
+            }
+            reg.gpr[consts::SYSCALL_ARG1_REG].write(op);
+            reg.gpr[consts::SYSCALL_ARG2_REG].write(0x100);
+            let exit = semihosting.call(&mut reg, mem);
+            (reg.gpr[consts::SYSCALL_RET_REG].read(), exit)
+        };
+
+        let path = std::env::temp_dir().join(format!("casim-semihosting-{}", std::process::id()));
+        let path = path.to_str().unwrap().as_bytes();
+        mem.read_bytes_mut(0, path.len()).unwrap().copy_from_slice(path);
+        mem.read_bytes_mut(0x80, 5).unwrap().copy_from_slice(b"hello");
+
+        // "w" 모드로 열고 쓰기
+        let (handle, _) = call(&mut mem, SYS_OPEN, &[0, 4, path.len() as u32]);
+        assert_ne!(handle, FAILED);
+        assert_eq!(call(&mut mem, SYS_WRITE, &[handle, 0x80, 5]).0, 0);
+        assert_eq!(call(&mut mem, SYS_CLOSE, &[handle]).0, 0);
+        assert_eq!(call(&mut mem, SYS_CLOSE, &[handle]).0, FAILED);
+
+        // "r" 모드로 다시 읽으면 남은 byte 수를 반환
+        let (handle, _) = call(&mut mem, SYS_OPEN, &[0, 0, path.len() as u32]);
+        assert_eq!(call(&mut mem, SYS_READ, &[handle, 0xc0, 8]).0, 3);
+        assert_eq!(mem.read_bytes(0xc0, 5).unwrap(), b"hello");
+        call(&mut mem, SYS_CLOSE, &[handle]);
+        std::fs::remove_file(std::str::from_utf8(path).unwrap()).unwrap();
+
+        // 잘못된 pointer나 지원하지 않는 호출은 host를 멈추지 않고 -1을 반환
+        assert_eq!(call(&mut mem, SYS_WRITE, &[1, 0x1f0, 0x100]).0, FAILED);
+        assert_eq!(call(&mut mem, SYS_READ, &[1, 0x1f0, 0x100]).0, FAILED);
+        assert_eq!(call(&mut mem, 0x13, &[]).0, FAILED);
+
+        assert_eq!(call(&mut mem, SYS_EXIT_EXTENDED, &[ADP_STOPPED_APPLICATION_EXIT, 3]).1, Some(3));
+    }
+}
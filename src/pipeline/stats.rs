@@ -0,0 +1,360 @@
+//! Counters of a hart's pipeline.
+
+use instruction::{Function, Instruction, Opcode};
+use pipeline::ISSUE_WIDTH;
+use report::{ratio, Section, Value};
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// Classes of functional units, as counted by the reservation station.
+pub const UNIT_COUNT: usize = 4;
+pub const UNIT_ALU: usize = 0;
+pub const UNIT_MUL_DIV: usize = 1;
+/// Address unit.
+pub const UNIT_AGU: usize = 2;
+/// Loads and stores waiting for memory.
+pub const UNIT_MEM: usize = 3;
+const UNIT_NAMES: [&str; UNIT_COUNT] = ["alu", "mul_div", "agu", "mem"];
+
+/// Load latencies are counted in buckets of powers of two, the last one
+/// holding everything longer.
+const LATENCY_BUCKETS: usize = 8;
+
+/// Top-down category of a commit slot: used by a retiring instruction, or
+/// the reason it went unused.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Bound {
+    Retiring,
+    /// Nothing to commit because fetch or issue fell behind, including
+    /// issue waiting behind a `jalr` or a system instruction.
+    Frontend,
+    /// The ROB is refilling after a mispredict flushed it, or held wrong
+    /// path instructions behind the mispredicted branch.
+    BadSpeculation,
+    /// The oldest instruction is in the load buffer, or is a store or AMO
+    /// writing memory at the ROB head.
+    Memory,
+    /// The oldest instruction waits for its operands or a functional unit,
+    /// e.g. the multiplier.
+    Core,
+}
+
+const BOUND_COUNT: usize = 5;
+
+/// Commit slots and clocks by top-down category. Each clock has
+/// `ISSUE_WIDTH` slots, and counts as retiring if anything retired in it.
+#[derive(Debug, Default, Clone)]
+pub struct TopDown {
+    pub clocks: usize,
+    pub slots: [usize; BOUND_COUNT],
+    pub cycles: [usize; BOUND_COUNT],
+    /// Unused slots and clocks accounted while instructions sat behind an
+    /// unresolved branch, held until it resolves.
+    pending_slots: [usize; BOUND_COUNT],
+    pending_cycles: [usize; BOUND_COUNT],
+}
+
+impl TopDown {
+    /// Accounts a clock in which `retired` instructions retired and `stall`
+    /// kept the other slots unused.
+    pub fn account(&mut self, retired: usize, stall: Bound) {
+        let retiring = self.account_retiring(retired);
+        self.slots[stall as usize] += ISSUE_WIDTH - retiring;
+        if retired == 0 {
+            self.cycles[stall as usize] += 1;
+        }
+    }
+
+    /// Like `account`, but holds the unused slots until `resolve` tells
+    /// whether the instructions behind the oldest unresolved branch were on
+    /// the wrong path.
+    pub fn account_speculative(&mut self, retired: usize, stall: Bound) {
+        let retiring = self.account_retiring(retired);
+        self.pending_slots[stall as usize] += ISSUE_WIDTH - retiring;
+        if retired == 0 {
+            self.pending_cycles[stall as usize] += 1;
+        }
+    }
+
+    fn account_retiring(&mut self, retired: usize) -> usize {
+        let retiring = std::cmp::min(retired, ISSUE_WIDTH);
+        self.clocks += 1;
+        self.slots[Bound::Retiring as usize] += retiring;
+        if retired > 0 {
+            self.cycles[Bound::Retiring as usize] += 1;
+        }
+        retiring
+    }
+
+    /// Moves the held slots to bad speculation if the branch was
+    /// mispredicted, or to the categories they were accounted in.
+    pub fn resolve(&mut self, mispredicted: bool) {
+        let slots = std::mem::take(&mut self.pending_slots);
+        let cycles = std::mem::take(&mut self.pending_cycles);
+        for (counts, pending) in [(&mut self.slots, slots), (&mut self.cycles, cycles)] {
+            if mispredicted {
+                counts[Bound::BadSpeculation as usize] += pending.iter().sum::<usize>();
+            } else {
+                for (count, held) in counts.iter_mut().zip(pending.iter()) {
+                    *count += held;
+                }
+            }
+        }
+    }
+
+    fn section(&self, name: &str, counts: &[usize; BOUND_COUNT], total: usize) -> Section {
+        let share = |count: usize| ratio(count * 100, total);
+        let mut section = Section::new(name);
+        section.push("retiring", share(counts[Bound::Retiring as usize]));
+        section.push("bad_speculation", share(counts[Bound::BadSpeculation as usize]));
+        section.push("frontend_bound", share(counts[Bound::Frontend as usize]));
+        let (memory, core) = (counts[Bound::Memory as usize], counts[Bound::Core as usize]);
+        section.push("backend_bound", share(memory + core));
+        section.push("memory_bound", share(memory));
+        section.push("core_bound", share(core));
+        section
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Occupancy {
+    pub total: usize,
+    pub peak: usize,
+}
+
+impl Occupancy {
+    fn sample(&mut self, entries: usize) {
+        self.total += entries;
+        self.peak = std::cmp::max(self.peak, entries);
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct PipelineStats {
+    /// Clocks the hart ran a thread, over which occupancy and utilization
+    /// are averaged.
+    pub clocks: usize,
+    pub retired: usize,
+    pub opcode_mix: HashMap<Opcode, usize>,
+    pub function_mix: HashMap<Function, usize>,
+    pub branches: usize,
+    /// Flushes after a mispredicted branch reached commit.
+    pub mispredicts: usize,
+    /// Flushes by mispredicts, traps and interrupts.
+    pub flushes: usize,
+    /// Instructions dropped from the fetch queue and the ROB by flushes.
+    pub squashed: usize,
+    pub rob: Occupancy,
+    pub rs: Occupancy,
+    pub load_buffer: Occupancy,
+    /// Clocks each class of unit had an operation in flight.
+    pub unit_busy: [usize; UNIT_COUNT],
+    /// Sum over clocks of the operations in flight in each class.
+    pub unit_ops: [usize; UNIT_COUNT],
+    pub loads: usize,
+    pub total_load_latency: usize,
+    pub max_load_latency: usize,
+    pub load_latency: [usize; LATENCY_BUCKETS],
+    pub top_down: TopDown,
+}
+
+impl PipelineStats {
+    pub fn retire(&mut self, inst: &Instruction) {
+        self.retired += 1;
+        *self.opcode_mix.entry(inst.opcode).or_insert(0) += 1;
+        *self.function_mix.entry(inst.function).or_insert(0) += 1;
+        if let Opcode::Branch = inst.opcode {
+            self.branches += 1;
+        }
+    }
+
+    pub fn sample(
+        &mut self,
+        rob: usize,
+        rs: usize,
+        load_buffer: usize,
+        unit_ops: &[usize; UNIT_COUNT],
+    ) {
+        self.clocks += 1;
+        self.rob.sample(rob);
+        self.rs.sample(rs);
+        self.load_buffer.sample(load_buffer);
+        for (unit, &ops) in unit_ops.iter().enumerate() {
+            self.unit_ops[unit] += ops;
+            if ops > 0 {
+                self.unit_busy[unit] += 1;
+            }
+        }
+    }
+
+    /// Records a load that took `latency` clocks from being sent to memory
+    /// to getting its value.
+    pub fn load_done(&mut self, latency: usize) {
+        self.loads += 1;
+        self.total_load_latency += latency;
+        self.max_load_latency = std::cmp::max(self.max_load_latency, latency);
+        let bucket = (usize::BITS - latency.leading_zeros()).saturating_sub(1) as usize;
+        self.load_latency[std::cmp::min(bucket, LATENCY_BUCKETS - 1)] += 1;
+    }
+
+    /// Report sections of the counters, with IPC over `clocks`.
+    pub fn sections(&self, clocks: usize) -> Vec<Section> {
+        let mut pipeline = Section::new("pipeline");
+        pipeline.push("retired", Value::Count(self.retired));
+        pipeline.push("ipc", ratio(self.retired, clocks));
+        pipeline.push("branches", Value::Count(self.branches));
+        pipeline.push("mispredicts", Value::Count(self.mispredicts));
+        pipeline.push(
+            "mispredict_rate",
+            ratio(self.mispredicts * 100, self.branches),
+        );
+        pipeline.push("flushes", Value::Count(self.flushes));
+        pipeline.push("squashed", Value::Count(self.squashed));
+
+        let mut occupancy = Section::new("occupancy");
+        let buffers = [("rob", self.rob), ("rs", self.rs), ("lb", self.load_buffer)];
+        for &(name, buffer) in buffers.iter() {
+            occupancy.push(&format!("{}_avg", name), ratio(buffer.total, self.clocks));
+            occupancy.push(&format!("{}_max", name), Value::Count(buffer.peak));
+        }
+
+        let mut units = Section::new("units");
+        for (unit, name) in UNIT_NAMES.iter().enumerate() {
+            units.push(
+                &format!("{}_busy", name),
+                ratio(self.unit_busy[unit] * 100, self.clocks),
+            );
+            units.push(
+                &format!("{}_avg_ops", name),
+                ratio(self.unit_ops[unit], self.clocks),
+            );
+        }
+
+        let mut load_latency = Section::new("load_latency");
+        load_latency.push("loads", Value::Count(self.loads));
+        load_latency.push("avg", ratio(self.total_load_latency, self.loads));
+        load_latency.push("max", Value::Count(self.max_load_latency));
+        for (bucket, &loads) in self.load_latency.iter().enumerate() {
+            let label = match bucket {
+                0 => "0-1".to_string(),
+                _ if bucket == LATENCY_BUCKETS - 1 => format!("{}+", 1 << bucket),
+                _ => format!("{}-{}", 1 << bucket, (2 << bucket) - 1),
+            };
+            load_latency.push(&label, Value::Count(loads));
+        }
+
+        // 끝날 때까지 풀리지 않은 branch는 맞게 예측된 것으로 봄
+        let mut top_down = self.top_down.clone();
+        top_down.resolve(false);
+        let slots = top_down.section("top_down", &top_down.slots, top_down.clocks * ISSUE_WIDTH);
+        let cycles = top_down.section("top_down_cycles", &top_down.cycles, top_down.clocks);
+
+        vec![
+            pipeline,
+            occupancy,
+            units,
+            Self::mix("opcode_mix", &self.opcode_mix),
+            Self::mix("function_mix", &self.function_mix),
+            load_latency,
+            slots,
+            cycles,
+        ]
+    }
+
+    /// Counts by name, most frequent first.
+    fn mix<K: Hash + Eq + std::fmt::Debug>(name: &str, mix: &HashMap<K, usize>) -> Section {
+        let mut counts: Vec<_> = mix
+            .iter()
+            .map(|(key, &count)| (format!("{:?}", key), count))
+            .collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        let mut section = Section::new(name);
+        for (key, count) in counts {
+            section.push(&key, Value::Count(count));
+        }
+        section
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mix_and_latency_buckets() {
+        let mut stats = PipelineStats::default();
+        let add = Instruction::default();
+        let beq = Instruction::new(0x0000_0063);
+        stats.retire(&add);
+        stats.retire(&add);
+        stats.retire(&beq);
+        stats.mispredicts = 1;
+        for &latency in [1, 2, 3, 4, 40, 500].iter() {
+            stats.load_done(latency);
+        }
+        stats.sample(4, 2, 1, &[1, 0, 0, 2]);
+        stats.sample(2, 0, 1, &[0, 0, 0, 1]);
+
+        assert_eq!(stats.load_latency, [1, 2, 1, 0, 0, 1, 0, 1]);
+        let sections = stats.sections(6);
+        let find = |section: &str, key: &str| {
+            let section = sections.iter().find(|s| s.name == section).unwrap();
+            section.entries.iter().find(|e| e.0 == key).unwrap().1
+        };
+        assert_eq!(find("pipeline", "ipc"), Value::Real(0.5));
+        assert_eq!(find("pipeline", "mispredict_rate"), Value::Real(100.0));
+        assert_eq!(find("occupancy", "rob_avg"), Value::Real(3.0));
+        assert_eq!(find("units", "alu_busy"), Value::Real(50.0));
+        assert_eq!(find("units", "mem_avg_ops"), Value::Real(1.5));
+        assert_eq!(find("load_latency", "128+"), Value::Count(1));
+        assert_eq!(sections[3].entries[0], ("OpImm".to_string(), Value::Count(2)));
+    }
+
+    #[test]
+    fn top_down_slots() {
+        let mut top_down = TopDown::default();
+        top_down.account(3, Bound::Core);
+        top_down.account(1, Bound::Memory);
+        top_down.account(0, Bound::Memory);
+        top_down.account(0, Bound::BadSpeculation);
+
+        assert_eq!(top_down.slots, [3, 0, 2, 3, 0]);
+        assert_eq!(top_down.cycles, [2, 0, 1, 1, 0]);
+        let stats = PipelineStats {
+            top_down,
+            ..Default::default()
+        };
+        let sections = stats.sections(4);
+        let slots = sections.iter().find(|s| s.name == "top_down").unwrap();
+        assert_eq!(slots.entries[0], ("retiring".to_string(), Value::Real(37.5)));
+        assert_eq!(slots.entries[3], ("backend_bound".to_string(), Value::Real(37.5)));
+        assert_eq!(slots.entries[5], ("core_bound".to_string(), Value::Real(0.0)));
+    }
+
+    #[test]
+    fn slots_behind_a_branch_wait_for_it() {
+        let mut top_down = TopDown::default();
+        top_down.account_speculative(1, Bound::Memory);
+        top_down.account_speculative(0, Bound::Core);
+        assert_eq!(top_down.slots, [1, 0, 0, 0, 0]);
+        top_down.resolve(true);
+        assert_eq!(top_down.slots, [1, 0, 3, 0, 0]);
+        assert_eq!(top_down.cycles, [1, 0, 1, 0, 0]);
+
+        top_down.account_speculative(0, Bound::Memory);
+        top_down.resolve(false);
+        assert_eq!(top_down.slots, [1, 0, 3, 2, 0]);
+        assert_eq!(top_down.cycles, [1, 0, 1, 1, 0]);
+
+        // 끝날 때 풀리지 않은 branch 뒤의 slot도 보고함
+        top_down.account_speculative(0, Bound::Core);
+        let stats = PipelineStats {
+            top_down,
+            ..Default::default()
+        };
+        let sections = stats.sections(4);
+        let slots = sections.iter().find(|s| s.name == "top_down").unwrap();
+        assert_eq!(slots.entries[1], ("bad_speculation".to_string(), Value::Real(37.5)));
+        assert_eq!(slots.entries[5], ("core_bound".to_string(), Value::Real(25.0)));
+    }
+}
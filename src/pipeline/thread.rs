@@ -0,0 +1,219 @@
+//! Threads of the guest process.
+//!
+//! A hart runs at most one thread at a time. Threads created by `clone` start
+//! on an idle hart, or wait in the ready queue until a running thread blocks
+//! on a futex or exits, so more threads than harts can share the machine.
+//! Running threads are never preempted.
+
+use checkpoint::{Decoder, Encoder, Snapshot};
+use std::collections::VecDeque;
+use std::io;
+
+pub const CLONE_VM: u32 = 0x0000_0100;
+pub const CLONE_SETTLS: u32 = 0x0008_0000;
+pub const CLONE_PARENT_SETTID: u32 = 0x0010_0000;
+pub const CLONE_CHILD_CLEARTID: u32 = 0x0020_0000;
+pub const CLONE_CHILD_SETTID: u32 = 0x0100_0000;
+
+pub const FUTEX_WAIT: u32 = 0;
+pub const FUTEX_WAKE: u32 = 1;
+pub const FUTEX_WAIT_BITSET: u32 = 9;
+pub const FUTEX_WAKE_BITSET: u32 = 10;
+/// Bits of the futex operation that select the command.
+pub const FUTEX_CMD_MASK: u32 = 0x7f;
+
+pub const EAGAIN: u32 = 11;
+pub const ENOSYS: u32 = 38;
+
+/// Architectural state of a thread that isn't running on a hart.
+#[derive(Debug, Clone)]
+pub struct ThreadContext {
+    pub tid: u32,
+    pub pc: u32,
+    pub gpr: [u32; 32],
+    /// Address cleared and woken as a futex when the thread exits.
+    pub clear_child_tid: u32,
+}
+
+#[derive(Debug, Default)]
+pub struct ThreadTable {
+    next_tid: u32,
+    live_threads: usize,
+    ready: VecDeque<ThreadContext>,
+    /// Threads blocked in `FUTEX_WAIT` with their futex address, oldest first.
+    waiting: Vec<(u32, ThreadContext)>,
+    exit_code: Option<u32>,
+}
+
+impl ThreadTable {
+    pub fn new(first_tid: u32) -> Self {
+        ThreadTable {
+            next_tid: first_tid,
+            ..Default::default()
+        }
+    }
+
+    /// Allocates the id of a new thread, which counts as live from now on.
+    pub fn new_tid(&mut self) -> u32 {
+        let tid = self.next_tid;
+        self.next_tid += 1;
+        self.live_threads += 1;
+        tid
+    }
+
+    /// Queues a thread to run on the next idle hart.
+    pub fn make_ready(&mut self, thread: ThreadContext) {
+        self.ready.push_back(thread);
+    }
+
+    pub fn next_ready(&mut self) -> Option<ThreadContext> {
+        self.ready.pop_front()
+    }
+
+    pub fn block(&mut self, futex_addr: u32, thread: ThreadContext) {
+        self.waiting.push((futex_addr, thread));
+    }
+
+    /// Wakes up to `count` threads blocked on `futex_addr` and returns how
+    /// many were woken.
+    pub fn wake(&mut self, futex_addr: u32, count: u32) -> u32 {
+        let mut woken = 0;
+        while woken < count {
+            let waiter = self.waiting.iter().position(|(addr, _)| *addr == futex_addr);
+            match waiter {
+                Some(pos) => {
+                    let (_, thread) = self.waiting.remove(pos);
+                    self.ready.push_back(thread);
+                    woken += 1;
+                }
+                None => break,
+            }
+        }
+        woken
+    }
+
+    /// Ends one thread. The process ends with the last one.
+    pub fn exit_thread(&mut self, code: u32) {
+        self.live_threads -= 1;
+        if self.live_threads == 0 {
+            self.exit_code = Some(code);
+        }
+    }
+
+    /// Ends the whole process, whatever its threads are doing.
+    pub fn exit_group(&mut self, code: u32) {
+        self.exit_code = Some(code);
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.exit_code.is_some()
+    }
+
+    pub fn exit_code(&self) -> Option<u32> {
+        self.exit_code
+    }
+
+    /// True if nothing can run anymore because every live thread is blocked.
+    pub fn is_deadlocked(&self) -> bool {
+        !self.is_finished() && self.ready.is_empty() && self.waiting.len() == self.live_threads
+    }
+}
+
+impl Snapshot for ThreadContext {
+    fn save(&self, out: &mut Encoder) {
+        out.u32(self.tid);
+        out.u32(self.pc);
+        for &reg in self.gpr.iter() {
+            out.u32(reg);
+        }
+        out.u32(self.clear_child_tid);
+    }
+
+    fn restore(&mut self, input: &mut Decoder) -> io::Result<()> {
+        self.tid = input.u32()?;
+        self.pc = input.u32()?;
+        for reg in self.gpr.iter_mut() {
+            *reg = input.u32()?;
+        }
+        self.clear_child_tid = input.u32()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for ThreadTable {
+    fn save(&self, out: &mut Encoder) {
+        out.u32(self.next_tid);
+        out.usize(self.live_threads);
+        out.usize(self.ready.len());
+        for thread in self.ready.iter() {
+            thread.save(out);
+        }
+        out.usize(self.waiting.len());
+        for (futex_addr, thread) in self.waiting.iter() {
+            out.u32(*futex_addr);
+            thread.save(out);
+        }
+    }
+
+    fn restore(&mut self, input: &mut Decoder) -> io::Result<()> {
+        let read_thread = |input: &mut Decoder| {
+            let mut thread = ThreadContext {
+                tid: 0,
+                pc: 0,
+                gpr: [0; 32],
+                clear_child_tid: 0,
+            };
+            thread.restore(input).map(|_| thread)
+        };
+        self.next_tid = input.u32()?;
+        self.live_threads = input.usize()?;
+        self.ready.clear();
+        for _ in 0..input.usize()? {
+            self.ready.push_back(read_thread(input)?);
+        }
+        self.waiting.clear();
+        for _ in 0..input.usize()? {
+            let futex_addr = input.u32()?;
+            self.waiting.push((futex_addr, read_thread(input)?));
+        }
+        self.exit_code = None;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn thread(tid: u32) -> ThreadContext {
+        ThreadContext {
+            tid,
+            pc: 0,
+            gpr: [0; 32],
+            clear_child_tid: 0,
+        }
+    }
+
+    #[test]
+    fn futex_wake_is_fifo_per_address() {
+        let mut threads = ThreadTable::new(1);
+        for _ in 0..3 {
+            threads.new_tid();
+        }
+        threads.block(0x100, thread(1));
+        threads.block(0x200, thread(2));
+        threads.block(0x100, thread(3));
+        assert!(threads.is_deadlocked());
+
+        assert_eq!(threads.wake(0x100, 5), 2);
+        assert_eq!(threads.next_ready().unwrap().tid, 1);
+        assert_eq!(threads.next_ready().unwrap().tid, 3);
+        assert!(threads.next_ready().is_none());
+
+        threads.exit_thread(0);
+        threads.exit_thread(0);
+        assert!(!threads.is_finished());
+        threads.exit_thread(3);
+        assert_eq!(threads.exit_code(), Some(3));
+    }
+}
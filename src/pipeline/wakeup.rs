@@ -0,0 +1,65 @@
+//! Tag-indexed wakeup.
+//!
+//! An operand waiting for a result holds the ROB index of its producer.
+//! Rather than comparing every waiting operand against each finished job,
+//! the buffers register their waiting operands here under the producer's
+//! index when they issue, and only visit those consumers when the result is
+//! written back. A flush clears the table along with the buffers.
+
+use pipeline::operand::Operand;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone)]
+pub struct WakeupTable<C> {
+    consumers: HashMap<usize, Vec<C>>,
+}
+
+impl<C> Default for WakeupTable<C> {
+    fn default() -> Self {
+        WakeupTable {
+            consumers: HashMap::new(),
+        }
+    }
+}
+
+impl<C> WakeupTable<C> {
+    /// Registers `consumer` with the producer of `operand`, if the operand
+    /// is still waiting. Returns true if it is.
+    pub fn listen(&mut self, operand: Operand, consumer: C) -> bool {
+        match operand {
+            Operand::Rob(producer) => {
+                self.consumers.entry(producer).or_default().push(consumer);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Takes the consumers waiting for `producer`.
+    pub fn take(&mut self, producer: usize) -> Vec<C> {
+        self.consumers.remove(&producer).unwrap_or_default()
+    }
+
+    pub fn clear(&mut self) {
+        self.consumers.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn consumers_are_woken_once() {
+        let mut table = WakeupTable::default();
+        assert!(table.listen(Operand::Rob(1), 'a'));
+        assert!(table.listen(Operand::Rob(1), 'b'));
+        assert!(table.listen(Operand::Rob(2), 'c'));
+        assert!(!table.listen(Operand::Value(3), 'd'));
+
+        assert_eq!(table.take(1), vec!['a', 'b']);
+        assert!(table.take(1).is_empty());
+        table.clear();
+        assert!(table.take(2).is_empty());
+    }
+}
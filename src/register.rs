@@ -1,6 +1,8 @@
 //! 32-bit register and RV32I register file.
 
+use csr::CsrFile;
 use pipeline::operand::Operand;
+use pipeline::rename::PhysRegFile;
 use pipeline::reorder_buffer::ReorderBuffer;
 use std::fmt;
 
@@ -8,12 +10,17 @@ type RegisterStat = Option<usize>;
 
 /// A complete RV32I register file.
 ///
-/// Holds 32 general purpose registers and a program counter register.
+/// Holds 32 general purpose registers, a program counter register and the
+/// control and status registers.
 #[derive(Debug)]
 pub struct RegisterFile {
     pub pc: Register,
     pub gpr: [Register; 32],
     pub related_rob: [RegisterStat; 32],
+    pub csr: CsrFile,
+    /// Physical register file used instead of `related_rob` when renaming
+    /// through one. `gpr` still holds the architectural values.
+    pub prf: Option<PhysRegFile>,
 }
 
 impl fmt::Display for RegisterFile {
@@ -33,6 +40,8 @@ impl RegisterFile {
             pc: Register::new(pc, true),
             gpr: [Register::new(0, true); 32],
             related_rob: [None; 32],
+            csr: Default::default(),
+            prf: None,
         };
         reg_file.gpr[0] = Register::new(0, false); // reinit x0 as read-only
         reg_file.gpr[2] = Register::new(stack_pointer, true);
@@ -41,6 +50,9 @@ impl RegisterFile {
     }
 
     pub fn get_reg_value(&self, reg: u8, rob: &ReorderBuffer) -> Operand {
+        if let Some(ref prf) = self.prf {
+            return prf.lookup(reg);
+        }
         self.related_rob[reg as usize]
             .map(|idx| {
                 rob.get(idx)
@@ -52,6 +64,16 @@ impl RegisterFile {
             .unwrap_or(Operand::Value(self.gpr[reg as usize].read()))
     }
 
+    /// Drops the speculative mappings of the physical register file, if
+    /// any, and reloads it from `gpr`. Returns the number of mappings
+    /// dropped.
+    pub fn sync_phys_regs(&mut self) -> usize {
+        match self.prf {
+            Some(ref mut prf) => prf.recover(&self.gpr),
+            None => 0,
+        }
+    }
+
     pub fn set_reg_rob_index(&mut self, reg: u8, rob_idx: usize) {
         if reg == 0 {
             return;
@@ -0,0 +1,270 @@
+//! End-of-run statistics report.
+//!
+//! The counters of the machine and of each hart are gathered into named
+//! sections of key/value pairs, which can be printed as text or written as
+//! JSON or CSV for scripts.
+
+use machine::Machine;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StatsFormat {
+    Text,
+    Json,
+    Csv,
+}
+
+impl FromStr for StatsFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(StatsFormat::Text),
+            "json" => Ok(StatsFormat::Json),
+            "csv" => Ok(StatsFormat::Csv),
+            _ => Err(format!(
+                "unknown stats format '{}', expected text, json or csv",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    Count(usize),
+    Real(f64),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Count(count) => write!(f, "{}", count),
+            Value::Real(real) => write!(f, "{:.2}", real),
+        }
+    }
+}
+
+/// `numerator / denominator`, or zero if nothing was counted.
+pub fn ratio(numerator: usize, denominator: usize) -> Value {
+    if denominator == 0 {
+        Value::Real(0.0)
+    } else {
+        Value::Real(numerator as f64 / denominator as f64)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Section {
+    pub name: String,
+    pub entries: Vec<(String, Value)>,
+}
+
+impl Section {
+    pub fn new(name: &str) -> Self {
+        Section {
+            name: name.to_string(),
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, key: &str, value: Value) {
+        self.entries.push((key.to_string(), value));
+    }
+}
+
+impl fmt::Display for Section {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, (key, value)) in self.entries.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}: {}", key, value)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub machine: Vec<Section>,
+    /// Sections of each hart, by hart id.
+    pub harts: Vec<Vec<Section>>,
+}
+
+impl Report {
+    pub fn new(machine: &Machine) -> Self {
+        let mut clocks = Section::new("machine");
+        clocks.push("clocks", Value::Count(machine.clock));
+
+        let dram = &machine.mem_sys.dram.stats;
+        let mut dram_section = Section::new("dram");
+        dram_section.push("reads", Value::Count(dram.reads));
+        dram_section.push("writes", Value::Count(dram.writes));
+        dram_section.push("row_hits", Value::Count(dram.row_hits));
+        dram_section.push("row_misses", Value::Count(dram.row_misses));
+        dram_section.push("row_conflicts", Value::Count(dram.row_conflicts));
+        dram_section.push(
+            "avg_read_latency",
+            ratio(dram.total_read_latency, dram.reads),
+        );
+
+        let bus = &machine.mem_sys.stats;
+        let mut coherence = Section::new("coherence");
+        coherence.push("bus_reads", Value::Count(bus.bus_reads));
+        coherence.push("bus_read_exclusives", Value::Count(bus.bus_read_exclusives));
+        coherence.push("bus_upgrades", Value::Count(bus.bus_upgrades));
+        coherence.push("invalidations", Value::Count(bus.invalidations));
+        coherence.push("cache_to_cache", Value::Count(bus.cache_to_cache));
+        coherence.push("writebacks", Value::Count(bus.writebacks));
+
+        let harts = machine
+            .harts
+            .iter()
+            .zip(machine.mem_sys.caches.iter())
+            .map(|(hart, cache)| {
+                let mmu = &hart.mmu.stats;
+                let mut mmu_section = Section::new("mmu");
+                mmu_section.push("itlb_hits", Value::Count(mmu.itlb_hits));
+                mmu_section.push("itlb_misses", Value::Count(mmu.itlb_misses));
+                mmu_section.push("dtlb_hits", Value::Count(mmu.dtlb_hits));
+                mmu_section.push("dtlb_misses", Value::Count(mmu.dtlb_misses));
+                mmu_section.push("walk_mem_accesses", Value::Count(mmu.walk_mem_accesses));
+                mmu_section.push(
+                    "avg_walk_latency",
+                    ratio(mmu.total_walk_latency, mmu.walks_done),
+                );
+                mmu_section.push("page_faults", Value::Count(mmu.page_faults));
+
+                let l1d = &cache.stats;
+                let mut l1d_section = Section::new("l1d");
+                l1d_section.push("hits", Value::Count(l1d.hits));
+                l1d_section.push("misses", Value::Count(l1d.misses));
+                l1d_section.push("hit_rate", ratio(l1d.hits * 100, l1d.hits + l1d.misses));
+                l1d_section.push("upgrades", Value::Count(l1d.upgrades));
+                l1d_section.push("writebacks", Value::Count(l1d.writebacks));
+                l1d_section.push("invalidations", Value::Count(l1d.invalidations));
+
+                let irq = &hart.interrupts.stats;
+                let mut interrupts = Section::new("interrupts");
+                interrupts.push("taken", Value::Count(irq.taken));
+                interrupts.push("avg_latency", ratio(irq.total_latency, irq.taken));
+                interrupts.push("max_latency", Value::Count(irq.max_latency));
+
+                let rename = &hart.rename_stats;
+                let mut rename_section = Section::new("rename");
+                rename_section.push("renamed", Value::Count(rename.renamed));
+                rename_section.push("free_list_stalls", Value::Count(rename.free_list_stalls));
+                rename_section.push(
+                    "avg_in_flight",
+                    ratio(rename.total_in_flight, rename.clocks),
+                );
+                rename_section.push("peak_in_flight", Value::Count(rename.peak_in_flight));
+                rename_section.push("recoveries", Value::Count(rename.recoveries));
+                rename_section.push("squashed", Value::Count(rename.squashed));
+                rename_section.push("recovery_clocks", Value::Count(rename.recovery_clocks));
+
+                let mut sections = vec![mmu_section, l1d_section, interrupts, rename_section];
+                sections.extend(hart.stats.sections(machine.clock - machine.start_clock));
+                sections
+            })
+            .collect();
+
+        Report {
+            machine: vec![clocks, dram_section, coherence],
+            harts,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        let object = |sections: &[Section], indent: &str| {
+            let members: Vec<_> = sections
+                .iter()
+                .map(|section| {
+                    let entries: Vec<_> = section
+                        .entries
+                        .iter()
+                        .map(|(key, value)| format!("{}: {}", json_string(key), json_number(*value)))
+                        .collect();
+                    format!(
+                        "{}  {}: {{{}}}",
+                        indent,
+                        json_string(&section.name),
+                        entries.join(", ")
+                    )
+                })
+                .collect();
+            members.join(",\n")
+        };
+
+        let harts: Vec<_> = self
+            .harts
+            .iter()
+            .map(|sections| format!("    {{\n{}\n    }}", object(sections, "    ")))
+            .collect();
+        format!(
+            "{{\n{},\n  \"harts\": [\n{}\n  ]\n}}\n",
+            object(&self.machine, ""),
+            harts.join(",\n")
+        )
+    }
+
+    /// One row per counter, with an empty hart column for the machine-wide
+    /// ones.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("hart,section,counter,value\n");
+        let sections = self
+            .machine
+            .iter()
+            .map(|section| (String::new(), section))
+            .chain(self.harts.iter().enumerate().flat_map(|(hart, sections)| {
+                sections.iter().map(move |section| (hart.to_string(), section))
+            }));
+        for (hart, section) in sections {
+            for (key, value) in section.entries.iter() {
+                csv += &format!("{},{},{},{}\n", hart, section.name, key, value);
+            }
+        }
+        csv
+    }
+}
+
+fn json_string(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn json_number(value: Value) -> String {
+    match value {
+        Value::Real(real) if !real.is_finite() => "null".to_string(),
+        Value::Real(real) => format!("{:.4}", real),
+        Value::Count(count) => count.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_and_csv_layout() {
+        let mut section = Section::new("pipeline");
+        section.push("retired", Value::Count(3));
+        section.push("ipc", ratio(3, 4));
+        let report = Report {
+            machine: vec![section.clone()],
+            harts: vec![vec![section]],
+        };
+
+        assert_eq!(
+            report.to_json(),
+            "{\n  \"pipeline\": {\"retired\": 3, \"ipc\": 0.7500},\n  \"harts\": [\n    {\n      \"pipeline\": {\"retired\": 3, \"ipc\": 0.7500}\n    }\n  ]\n}\n"
+        );
+        assert_eq!(
+            report.to_csv(),
+            "hart,section,counter,value\n,pipeline,retired,3\n,pipeline,ipc,0.75\n0,pipeline,retired,3\n0,pipeline,ipc,0.75\n"
+        );
+        assert_eq!(report.harts[0][0].to_string(), "retired: 3, ipc: 0.75");
+        assert!("xml".parse::<StatsFormat>().is_err());
+    }
+}
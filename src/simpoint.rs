@@ -0,0 +1,297 @@
+//! SimPoint-style sampled simulation.
+//!
+//! A program is first run functionally to write a basic block vector for
+//! every interval of N instructions, in the format SimPoint reads. SimPoint
+//! clusters the intervals and picks one simulation point per cluster with a
+//! weight. Each point is then fast-forwarded to, simulated in detail after
+//! an optional warm-up, and the IPCs of the points are combined by weight
+//! into an estimate for the whole program.
+
+use instruction::{Instruction, Opcode};
+use machine::Machine;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io::{self, Write};
+
+/// Writes a basic block vector per interval, one line each:
+///
+/// ```text
+/// T:1:24 :2:8 :5:112
+/// ```
+///
+/// Blocks are numbered from 1 in the order they are first entered, and each
+/// count is the number of instructions the interval ran in that block. A
+/// block ends after a branch, jump or system instruction, or when the next
+/// instruction doesn't follow it, as after a trap.
+pub struct BbvWriter<W: Write> {
+    out: W,
+    interval: usize,
+    block_ids: HashMap<u32, usize>,
+    counts: BTreeMap<usize, usize>,
+    /// Start of the block each hart is in and the PC that continues it.
+    current: Vec<Option<(u32, u32)>>,
+    executed: usize,
+}
+
+impl<W: Write> BbvWriter<W> {
+    pub fn new(out: W, interval: usize, harts: usize) -> Self {
+        BbvWriter {
+            out,
+            interval,
+            block_ids: HashMap::new(),
+            counts: BTreeMap::new(),
+            current: vec![None; harts],
+            executed: 0,
+        }
+    }
+
+    /// Counts an instruction hart `hart` ran at `pc`.
+    pub fn record(&mut self, hart: usize, pc: u32, inst: &Instruction) -> io::Result<()> {
+        let start = match self.current[hart] {
+            Some((start, next_pc)) if next_pc == pc => start,
+            _ => pc,
+        };
+        let next_id = self.block_ids.len() + 1;
+        let id = *self.block_ids.entry(start).or_insert(next_id);
+        *self.counts.entry(id).or_insert(0) += 1;
+
+        let ends_block = matches!(
+            inst.opcode,
+            Opcode::Branch | Opcode::Jal | Opcode::Jalr | Opcode::System
+        );
+        self.current[hart] = if ends_block {
+            None
+        } else {
+            Some((start, pc.wrapping_add(4)))
+        };
+
+        self.executed += 1;
+        if self.executed.is_multiple_of(self.interval) {
+            self.write_interval()?;
+        }
+        Ok(())
+    }
+
+    fn write_interval(&mut self) -> io::Result<()> {
+        let entries: Vec<_> = self
+            .counts
+            .iter()
+            .map(|(id, count)| format!(":{}:{}", id, count))
+            .collect();
+        writeln!(self.out, "T{}", entries.join(" "))?;
+        self.counts.clear();
+        Ok(())
+    }
+
+    /// Writes the last, partial interval. Returns the output and the number
+    /// of instructions counted.
+    pub fn finish(mut self) -> io::Result<(W, usize)> {
+        if !self.counts.is_empty() {
+            self.write_interval()?;
+        }
+        self.out.flush()?;
+        Ok((self.out, self.executed))
+    }
+}
+
+/// An interval chosen by SimPoint and the share of the program it stands for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SimPoint {
+    pub interval: usize,
+    pub weight: f64,
+}
+
+impl SimPoint {
+    /// Reads SimPoint's `-saveSimpoints` and `-saveSimpointWeights` files,
+    /// whose lines are `<interval> <cluster>` and `<weight> <cluster>`.
+    /// Points are returned in program order.
+    pub fn parse(simpoints: &str, weights: &str) -> Result<Vec<SimPoint>, String> {
+        fn pairs<'a, T: std::str::FromStr>(
+            text: &'a str,
+            name: &'a str,
+        ) -> impl Iterator<Item = Result<(T, usize), String>> + 'a {
+            text.lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(move |line| {
+                    let mut fields = line.split_whitespace();
+                    let value = fields.next().and_then(|value| value.parse().ok());
+                    let cluster = fields.next().and_then(|cluster| cluster.parse().ok());
+                    match (value, cluster) {
+                        (Some(value), Some(cluster)) => Ok((value, cluster)),
+                        _ => Err(format!("invalid line in the {} file: '{}'", name, line)),
+                    }
+                })
+        }
+
+        let mut weight_of = HashMap::new();
+        for pair in pairs::<f64>(weights, "weights") {
+            let (weight, cluster) = pair?;
+            weight_of.insert(cluster, weight);
+        }
+        let mut points = Vec::new();
+        for pair in pairs::<usize>(simpoints, "simpoints") {
+            let (interval, cluster) = pair?;
+            let weight = *weight_of
+                .get(&cluster)
+                .ok_or_else(|| format!("no weight for cluster {}", cluster))?;
+            points.push(SimPoint { interval, weight });
+        }
+        points.sort_by_key(|point| point.interval);
+        Ok(points)
+    }
+}
+
+/// What the detailed simulation of a point measured.
+#[derive(Debug, Clone, Copy)]
+pub struct Sample {
+    pub point: SimPoint,
+    pub instructions: usize,
+    pub clocks: usize,
+}
+
+impl Sample {
+    pub fn ipc(&self) -> f64 {
+        if self.clocks == 0 {
+            0.0
+        } else {
+            self.instructions as f64 / self.clocks as f64
+        }
+    }
+}
+
+impl fmt::Display for Sample {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "interval: {}, weight: {:.4}, instructions: {}, clocks: {}, ipc: {:.2}",
+            self.point.interval,
+            self.point.weight,
+            self.instructions,
+            self.clocks,
+            self.ipc()
+        )
+    }
+}
+
+/// IPC of the whole program estimated from the samples: the weighted CPIs
+/// are added up, so each point counts by the instructions it stands for.
+/// Weights are normalized over the points that were reached.
+pub fn weighted_ipc(samples: &[Sample]) -> f64 {
+    let measured = samples.iter().filter(|sample| sample.instructions > 0);
+    let (weight, cpi) = measured.fold((0.0, 0.0), |(weight, cpi), sample| {
+        let sample_cpi = sample.clocks as f64 / sample.instructions as f64;
+        (
+            weight + sample.point.weight,
+            cpi + sample.point.weight * sample_cpi,
+        )
+    });
+    if cpi == 0.0 {
+        0.0
+    } else {
+        weight / cpi
+    }
+}
+
+/// Fast-forwards `machine` to each point in turn, runs `warmup`
+/// instructions in detail to warm the caches, TLBs and pipeline, and then
+/// measures `interval` instructions. The pipeline is drained before going
+/// back to fast-forwarding. Stops early if the program ends.
+pub fn simulate(
+    machine: &mut Machine,
+    points: &[SimPoint],
+    interval: usize,
+    warmup: usize,
+) -> Vec<Sample> {
+    let mut samples = Vec::new();
+    let mut position = 0;
+    let run_detailed = |machine: &mut Machine, position: &mut usize| {
+        let (retired, is_finished) = machine.run_clock();
+        *position += retired.iter().map(|entries| entries.len()).sum::<usize>();
+        is_finished
+    };
+
+    for &point in points {
+        let start = point.interval * interval;
+        let warm_start = std::cmp::max(start.saturating_sub(warmup), position);
+        let (executed, mut is_finished) = machine.fast_forward(warm_start - position);
+        position += executed;
+        while !is_finished && position < start {
+            is_finished = run_detailed(machine, &mut position);
+        }
+        if is_finished {
+            break;
+        }
+
+        let (first_clock, first_inst) = (machine.clock, position);
+        while !is_finished && position < first_inst + interval {
+            is_finished = run_detailed(machine, &mut position);
+        }
+        samples.push(Sample {
+            point,
+            instructions: position - first_inst,
+            clocks: machine.clock - first_clock,
+        });
+
+        machine.drain(true);
+        while !is_finished && !machine.is_drained() {
+            is_finished = run_detailed(machine, &mut position);
+        }
+        machine.drain(false);
+        if is_finished {
+            break;
+        }
+    }
+    samples
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vectors_points_and_weights() {
+        // addi, addi, bne -> 0x0; jal -> 0x0
+        let addi = Instruction::new(0x0015_0513);
+        let bne = Instruction::new(0xfe05_1ce3);
+        let mut bbv = BbvWriter::new(Vec::new(), 4, 1);
+        for &(pc, inst) in [(0, &addi), (4, &addi), (8, &bne), (0, &addi), (4, &addi)].iter() {
+            bbv.record(0, pc, inst).unwrap();
+        }
+        // 분기 없이 이어지지 않는 PC는 새 block
+        bbv.record(0, 0x40, &addi).unwrap();
+        let (out, executed) = bbv.finish().unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "T:1:4\nT:1:1 :2:1\n");
+        assert_eq!(executed, 6);
+
+        let points = SimPoint::parse("7 0\n2 1\n", "0.25 0\n0.75 1\n").unwrap();
+        assert_eq!(
+            points,
+            vec![
+                SimPoint {
+                    interval: 2,
+                    weight: 0.75
+                },
+                SimPoint {
+                    interval: 7,
+                    weight: 0.25
+                },
+            ]
+        );
+        assert!(SimPoint::parse("7 3\n", "0.25 0\n").is_err());
+
+        let samples = [
+            Sample {
+                point: points[0],
+                instructions: 100,
+                clocks: 50,
+            },
+            Sample {
+                point: points[1],
+                instructions: 100,
+                clocks: 200,
+            },
+        ];
+        // CPI 0.5와 2.0의 가중 평균 0.875
+        assert!((weighted_ipc(&samples) - 1.0 / 0.875).abs() < 1e-9);
+    }
+}
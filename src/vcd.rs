@@ -0,0 +1,265 @@
+//! Value Change Dumps of the pipeline, for GTKWave and other waveform
+//! viewers.
+//!
+//! Every hart gets a scope with its fetch PC, the PC of the instruction it
+//! retired last, its 32 registers, the PCs at the head and tail of the ROB,
+//! how many entries the ROB, the reservation station and the load buffer
+//! hold, how many instructions it retired in the clock and whether a
+//! mispredicted branch flushed it. One clock is one time unit, and a signal
+//! is only written when it changes.
+
+use machine::Machine;
+use pipeline::reorder_buffer::ReorderBufferEntry;
+use pipeline::Pipeline;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Probe {
+    Pc,
+    CommitPc,
+    Gpr(usize),
+    RobHeadPc,
+    RobTailPc,
+    RobOccupancy,
+    RsOccupancy,
+    LoadBufferOccupancy,
+    Commits,
+    Mispredict,
+}
+
+impl Probe {
+    fn all() -> Vec<Probe> {
+        let mut probes = vec![Probe::Pc, Probe::CommitPc];
+        probes.extend((0..32).map(Probe::Gpr));
+        probes.extend_from_slice(&[
+            Probe::RobHeadPc,
+            Probe::RobTailPc,
+            Probe::RobOccupancy,
+            Probe::RsOccupancy,
+            Probe::LoadBufferOccupancy,
+            Probe::Commits,
+            Probe::Mispredict,
+        ]);
+        probes
+    }
+
+    fn name(self) -> String {
+        match self {
+            Probe::Pc => "pc".to_string(),
+            Probe::CommitPc => "commit_pc".to_string(),
+            Probe::Gpr(i) => format!("x{}", i),
+            Probe::RobHeadPc => "rob_head_pc".to_string(),
+            Probe::RobTailPc => "rob_tail_pc".to_string(),
+            Probe::RobOccupancy => "rob_occupancy".to_string(),
+            Probe::RsOccupancy => "rs_occupancy".to_string(),
+            Probe::LoadBufferOccupancy => "lb_occupancy".to_string(),
+            Probe::Commits => "commits".to_string(),
+            Probe::Mispredict => "mispredict".to_string(),
+        }
+    }
+
+    fn width(self) -> usize {
+        match self {
+            Probe::Mispredict => 1,
+            Probe::Commits => 8,
+            Probe::RobOccupancy | Probe::RsOccupancy | Probe::LoadBufferOccupancy => 16,
+            _ => 32,
+        }
+    }
+}
+
+/// Signal names to dump, separated by commas. A name ending in `*` matches
+/// every signal starting with the rest, e.g. `pc,x1*,rob_*`.
+#[derive(Debug, Clone)]
+pub struct SignalFilter {
+    patterns: Vec<String>,
+}
+
+impl SignalFilter {
+    pub fn matches(&self, name: &str) -> bool {
+        self.patterns.iter().any(|pattern| match pattern.strip_suffix('*') {
+            Some(prefix) => name.starts_with(prefix),
+            None => name == pattern,
+        })
+    }
+}
+
+impl Default for SignalFilter {
+    fn default() -> Self {
+        SignalFilter {
+            patterns: vec!["*".to_string()],
+        }
+    }
+}
+
+impl FromStr for SignalFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let patterns: Vec<_> = s
+            .split(',')
+            .map(str::trim)
+            .filter(|pattern| !pattern.is_empty())
+            .map(String::from)
+            .collect();
+        if patterns.is_empty() {
+            return Err("no signal given".to_string());
+        }
+        Ok(SignalFilter { patterns })
+    }
+}
+
+/// Writes the signals of every hart of a machine.
+pub struct VcdWriter<W: Write> {
+    out: W,
+    probes: Vec<Probe>,
+    /// Values written last, by hart and probe.
+    values: Vec<Vec<Option<u64>>>,
+    commit_pcs: Vec<u64>,
+    mispredict_flushes: Vec<usize>,
+}
+
+impl<W: Write> VcdWriter<W> {
+    /// Declares the signals of `harts` harts that pass `filter`.
+    pub fn new(mut out: W, harts: usize, filter: &SignalFilter) -> io::Result<Self> {
+        let probes: Vec<_> = Probe::all()
+            .into_iter()
+            .filter(|probe| filter.matches(&probe.name()))
+            .collect();
+        writeln!(out, "$version casim $end")?;
+        writeln!(out, "$timescale 1ns $end")?;
+        for hart in 0..harts {
+            writeln!(out, "$scope module hart{} $end", hart)?;
+            for (i, probe) in probes.iter().enumerate() {
+                writeln!(
+                    out,
+                    "$var wire {} {} {} $end",
+                    probe.width(),
+                    Self::id(hart * probes.len() + i),
+                    probe.name()
+                )?;
+            }
+            writeln!(out, "$upscope $end")?;
+        }
+        writeln!(out, "$enddefinitions $end")?;
+
+        Ok(VcdWriter {
+            out,
+            values: vec![vec![None; probes.len()]; harts],
+            probes,
+            commit_pcs: vec![0; harts],
+            mispredict_flushes: vec![0; harts],
+        })
+    }
+
+    /// Writes the signals that changed in `machine` at the end of the
+    /// current clock. `retired` is what each hart retired in it.
+    pub fn sample(
+        &mut self,
+        machine: &Machine,
+        retired: &[Vec<(usize, ReorderBufferEntry)>],
+    ) -> io::Result<()> {
+        let mut changes = Vec::new();
+        for (hart, pipeline) in machine.harts.iter().enumerate() {
+            let retired = &retired[hart];
+            if let Some((_, entry)) = retired.last() {
+                self.commit_pcs[hart] = u64::from(entry.pc);
+            }
+            let flushes = pipeline.stats.mispredicts;
+            let has_flushed = flushes != self.mispredict_flushes[hart];
+            self.mispredict_flushes[hart] = flushes;
+
+            for (i, &probe) in self.probes.iter().enumerate() {
+                let value = match probe {
+                    Probe::CommitPc => self.commit_pcs[hart],
+                    Probe::Commits => retired.len() as u64,
+                    Probe::Mispredict => has_flushed as u64,
+                    _ => Self::read(probe, pipeline),
+                };
+                if self.values[hart][i] != Some(value) {
+                    self.values[hart][i] = Some(value);
+                    changes.push((hart * self.probes.len() + i, probe.width(), value));
+                }
+            }
+        }
+
+        if changes.is_empty() {
+            return Ok(());
+        }
+        writeln!(self.out, "#{}", machine.clock)?;
+        for (index, width, value) in changes {
+            if width == 1 {
+                writeln!(self.out, "{}{}", value, Self::id(index))?;
+            } else {
+                writeln!(self.out, "b{:b} {}", value, Self::id(index))?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    fn read(probe: Probe, pipeline: &Pipeline) -> u64 {
+        let value = match probe {
+            Probe::Pc => pipeline.reg.pc.read() as usize,
+            Probe::Gpr(i) => pipeline.reg.gpr[i].read() as usize,
+            Probe::RobHeadPc => pipeline.rob.iter().next().map_or(0, |entry| entry.pc as usize),
+            Probe::RobTailPc => pipeline.rob.iter().next_back().map_or(0, |entry| entry.pc as usize),
+            Probe::RobOccupancy => pipeline.rob.len(),
+            Probe::RsOccupancy => pipeline.rs.occupancy(),
+            Probe::LoadBufferOccupancy => pipeline.rs.load_buffer_occupancy(),
+            Probe::CommitPc | Probe::Commits | Probe::Mispredict => unreachable!(),
+        };
+        value as u64
+    }
+
+    /// Identifier code of the `index`th signal, in base 94 over the
+    /// printable characters.
+    fn id(mut index: usize) -> String {
+        let mut id = String::new();
+        loop {
+            id.push((b'!' + (index % 94) as u8) as char);
+            index /= 94;
+            if index == 0 {
+                return id;
+            }
+            index -= 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use memory::ProcessMemory;
+
+    #[test]
+    fn filter_and_changes_only() {
+        let filter: SignalFilter = "pc, x1*,rob_*".parse().unwrap();
+        assert!(filter.matches("x10") && filter.matches("rob_occupancy"));
+        assert!(!filter.matches("x2") && !filter.matches("commit_pc"));
+        assert_eq!(VcdWriter::<Vec<u8>>::id(93), "~");
+        assert_eq!(VcdWriter::<Vec<u8>>::id(94), "!!");
+
+        let mut memory = ProcessMemory::default();
+        memory.data.resize(0x100, 0);
+        memory.v_address_range = (0, 0x100);
+        let mut machine = Machine::new(0, memory, 1, true, Default::default());
+        let mut vcd = VcdWriter::new(Vec::new(), 1, &"pc,x2".parse().unwrap()).unwrap();
+        machine.clock = 1;
+        vcd.sample(&machine, &[Vec::new()]).unwrap();
+        machine.clock = 2;
+        vcd.sample(&machine, &[Vec::new()]).unwrap();
+        machine.harts[0].reg.pc.write(0x40u32);
+        machine.clock = 3;
+        vcd.sample(&machine, &[Vec::new()]).unwrap();
+
+        let dump = String::from_utf8(vcd.finish().unwrap()).unwrap();
+        assert!(dump.contains("$var wire 32 ! pc $end\n$var wire 32 \" x2 $end"));
+        assert!(dump.ends_with("#1\nb0 !\nb0 \"\n#3\nb1000000 !\n"));
+    }
+}